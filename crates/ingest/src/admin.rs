@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::{Offset, TopicPartitionList};
+use schema_registry::{RetryOptions, SchemaRegistry, SubjectNameStrategy};
+use tracing::info;
+
+use crate::{DataWriter, IngestError, IngestOptions, kafka, TableOptions};
+
+/// Timeout of the kafka metadata and offset requests.
+const ADMIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The committed offset of the consumer group and the watermarks of a topic partition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerOffset {
+    pub partition: i32,
+    /// The next offset to consume, `None` if the group has not committed an offset yet.
+    pub committed: Option<i64>,
+    pub low_watermark: i64,
+    pub high_watermark: i64,
+}
+
+impl ConsumerOffset {
+    /// Number of messages not consumed yet.
+    pub fn lag(&self) -> i64 {
+        self.high_watermark - self.committed.unwrap_or(self.low_watermark).max(self.low_watermark)
+    }
+}
+
+/// The position the committed offsets of a consumer group are reset to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OffsetReset {
+    Earliest,
+    Latest,
+    Offset(i64),
+}
+
+impl FromStr for OffsetReset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "earliest" => Ok(OffsetReset::Earliest),
+            "latest" => Ok(OffsetReset::Latest),
+            _ => s.parse().map(OffsetReset::Offset)
+                .map_err(|_| format!("Invalid offset {}, expected earliest, latest or an offset", s)),
+        }
+    }
+}
+
+/// The committed offsets of the consumer group of the options per partition of the topic.
+pub fn consumer_offsets(opts: &IngestOptions, topic: &str) -> Result<Vec<ConsumerOffset>, IngestError> {
+    let consumer: BaseConsumer = kafka::consumer_config(opts).create()?;
+    let mut tpl = TopicPartitionList::new();
+    for partition in topic_partitions(&consumer, topic)? {
+        tpl.add_partition(topic, partition);
+    }
+
+    let committed = consumer.committed_offsets(tpl, ADMIN_TIMEOUT)?;
+    committed.elements().iter()
+        .map(|element| {
+            let (low_watermark, high_watermark) = consumer.fetch_watermarks(topic, element.partition(), ADMIN_TIMEOUT)?;
+            Ok(ConsumerOffset {
+                partition: element.partition(),
+                committed: element.offset().to_raw().filter(|offset| *offset >= 0),
+                low_watermark,
+                high_watermark,
+            })
+        })
+        .collect()
+}
+
+/// Commits the reset offsets of the given (or else all the) partitions of the topic for the consumer group
+/// of the options. The consumer group must not have active members.
+pub fn reset_consumer_offsets(
+    opts: &IngestOptions,
+    topic: &str,
+    partitions: &[i32],
+    reset: OffsetReset,
+) -> Result<Vec<(i32, i64)>, IngestError> {
+    let consumer: BaseConsumer = kafka::consumer_config(opts).create()?;
+    let partitions = if partitions.is_empty() {
+        topic_partitions(&consumer, topic)?
+    } else {
+        partitions.to_vec()
+    };
+
+    let mut tpl = TopicPartitionList::new();
+    let mut offsets = Vec::new();
+    for partition in partitions {
+        let (low, high) = consumer.fetch_watermarks(topic, partition, ADMIN_TIMEOUT)?;
+        let offset = match reset {
+            OffsetReset::Earliest => low,
+            OffsetReset::Latest => high,
+            OffsetReset::Offset(offset) => offset.clamp(low, high),
+        };
+        tpl.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+        offsets.push((partition, offset));
+    }
+    consumer.commit(&tpl, CommitMode::Sync)?;
+    info!("Reset offsets of group {} for topic {}: {:?}", opts.consumer_group_id, topic, offsets);
+    Ok(offsets)
+}
+
+fn topic_partitions(consumer: &BaseConsumer, topic: &str) -> Result<Vec<i32>, IngestError> {
+    let metadata = consumer.fetch_metadata(Some(topic), ADMIN_TIMEOUT)?;
+    let partitions: Vec<i32> = metadata.topics().iter()
+        .filter(|t| t.name() == topic)
+        .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+        .collect();
+    if partitions.is_empty() {
+        return Err(IngestError::InvalidConfig(format!("Topic {} not found", topic)));
+    }
+    Ok(partitions)
+}
+
+/// Creates (if not exists) the delta table of the latest schema registered under the subject.
+/// The table schema is the one of the `record_name` message, or else of the first message of the schema.
+/// Returns the table version.
+pub async fn create_table(
+    registry: &SchemaRegistry,
+    subject: &SubjectNameStrategy,
+    record_name: Option<&str>,
+    table_uri: &str,
+    storage_options: &HashMap<String, String>,
+) -> Result<i64, IngestError> {
+    let schema = registry.latest_proto_schema_of(subject).await?;
+    let full_name = record_name.unwrap_or(schema.full_name());
+    let arrow_schema = schema.message_to_arrow_schema(full_name)?;
+    let table_options = TableOptions { storage_options: storage_options.clone(), ..Default::default() };
+    let writer = DataWriter::try_new_with_options(table_uri, Arc::new(arrow_schema), table_options, RetryOptions::default())
+        .await?;
+    Ok(writer.table().version())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_offset_reset() {
+        assert_eq!("earliest".parse(), Ok(OffsetReset::Earliest));
+        assert_eq!("latest".parse(), Ok(OffsetReset::Latest));
+        assert_eq!("42".parse(), Ok(OffsetReset::Offset(42)));
+        assert!("first".parse::<OffsetReset>().is_err());
+    }
+
+    #[test]
+    fn consumer_lag() {
+        let offset = ConsumerOffset { partition: 0, committed: Some(15), low_watermark: 10, high_watermark: 20 };
+        assert_eq!(offset.lag(), 5);
+        assert_eq!(ConsumerOffset { committed: None, ..offset.clone() }.lag(), 10);
+        // Committed offsets deleted by retention
+        assert_eq!(ConsumerOffset { committed: Some(2), ..offset }.lag(), 10);
+    }
+}
@@ -0,0 +1,729 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ingest::{CdcEnvelope, FieldTransforms, IngestConfig, IngestOptions, KafkaAuth, KafkaSecurity, KafkaTls, kafka_properties_from_env, kafka_properties_from_file, KeyFormat, MessageFormat, OffsetReset, OptimizeOptions, ParquetCompression, ParquetOptions, ParquetStatistics, PartitionColumn, SchemaSource, SqlTransform, TombstoneMode, TopicRoute, VacuumOptions, WriteMode};
+use schema_registry::{RegistryAuth, RegistrySettings, RetryOptions, SubjectNameStrategy, SubjectStrategy};
+
+/// Kafka protobuf to delta table ingestion.
+#[derive(Debug, Parser)]
+#[command(name = "ingest", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+    /// Log output format.
+    #[arg(long, global = true, value_enum, env = "INGEST_LOG_FORMAT", default_value_t = LogFormat::Plain)]
+    pub log_format: LogFormat,
+    /// Log filter directives, e.g. `info,ingest=debug`.
+    #[arg(long, global = true, env = "RUST_LOG", default_value = "info")]
+    pub log_filter: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// Single line human readable logs.
+    Plain,
+    /// Multi line human readable logs.
+    Pretty,
+    /// Newline delimited JSON logs including the span fields.
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Ingests the messages of a topic (or topic pattern) to a delta table, or runs the pipelines of a config file.
+    Run(Box<RunArgs>),
+    /// Inspects the schemas registered for a topic.
+    #[command(subcommand)]
+    Schema(SchemaCommand),
+    /// Manages the delta tables.
+    #[command(subcommand)]
+    Table(TableCommand),
+    /// Inspects or resets the committed offsets of a consumer group.
+    #[command(subcommand)]
+    Offsets(OffsetsCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SchemaCommand {
+    /// Prints the raw proto schemas (including references) of the latest schema of the topic subject.
+    Show(SchemaArgs),
+    /// Prints the arrow schema of the latest schema of the topic subject.
+    Arrow(SchemaArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TableCommand {
+    /// Creates the delta table of the latest schema of the topic subject (if it does not exist).
+    Create(TableCreateArgs),
+    /// Compacts the small files of the delta table (OPTIMIZE), all partitions including the ones being written.
+    Optimize(TableOptimizeArgs),
+    /// Deletes the data files removed from the delta table longer than the retention ago (VACUUM).
+    Vacuum(TableVacuumArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum OffsetsCommand {
+    /// Prints the committed offsets and lag of the consumer group per partition.
+    Show(OffsetsArgs),
+    /// Resets the committed offsets of the consumer group (which must not be running).
+    Reset(OffsetsResetArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// The topic name or a regex topic pattern starting with `^`.
+    #[arg(required_unless_present = "config")]
+    pub topic: Option<String>,
+    /// The target delta table uri, `{topic}` is replaced with the topic name of pattern subscriptions.
+    #[arg(required_unless_present = "config")]
+    pub table_uri: Option<String>,
+    /// YAML or TOML pipelines config file, replaces the topic, table and every other option.
+    #[arg(long, conflicts_with_all = ["topic", "table_uri"])]
+    pub config: Option<PathBuf>,
+    /// Address of the prometheus `/metrics` and the `/healthz`, `/readyz` endpoints (e.g. `0.0.0.0:9090`),
+    /// disabled if not set.
+    #[arg(long, alias = "metrics-addr", env = "INGEST_HTTP_ADDR")]
+    pub http_addr: Option<SocketAddr>,
+    /// Routes a message type to a separate table (e.g. `example.Contact=./data/contacts`).
+    #[arg(long = "message-table", value_name = "FULL_NAME=TABLE_URI")]
+    pub message_tables: Vec<String>,
+    /// Resolves the table schemas from the latest subject schema of the given strategy
+    /// (topic, record or topic-record) instead of the first message writer schema.
+    #[arg(long)]
+    pub subject_strategy: Option<SubjectStrategy>,
+    /// Decodes the message keys (none, string, bytes or protobuf) into the `key` column of the table.
+    #[arg(long, default_value = "none")]
+    pub key_format: KeyFormat,
+    /// Applies the tombstones of compacted topics (skip, soft-delete or delete), requires a key format.
+    #[arg(long, default_value = "skip")]
+    pub tombstones: TombstoneMode,
+    /// Merges the messages into the table on the given key columns (e.g. `id`) instead of appending them.
+    #[arg(long, value_delimiter = ',')]
+    pub upsert_keys: Vec<String>,
+    /// The column resolving the duplicate upsert keys of a batch, the kafka offset if not set.
+    #[arg(long, requires = "upsert_keys")]
+    pub upsert_event_time: Option<String>,
+    /// Applies the operations of Debezium style change data capture messages (`before`, `after` and `op` fields).
+    #[arg(long, requires = "upsert_keys")]
+    pub cdc: bool,
+    /// Partitions a created table by a field or a derived column (e.g. `status`, `date(created_date)`,
+    /// `hour(_kafka_timestamp)`), the data files of each flush are split per partition.
+    #[arg(long, value_delimiter = ',')]
+    pub partition_by: Vec<PartitionColumn>,
+    /// Keeps only the given message fields (dot separated paths of nested fields, e.g. `details.physical.age`).
+    #[arg(long, value_delimiter = ',')]
+    pub select_fields: Vec<String>,
+    /// Removes the given message fields.
+    #[arg(long, value_delimiter = ',')]
+    pub drop_fields: Vec<String>,
+    /// Renames a message field in place (e.g. `details.physical.age=age_years`).
+    #[arg(long = "rename-field", value_name = "PATH=NAME")]
+    pub rename_fields: Vec<String>,
+    /// Flattens the nested message fields into top level columns named by their paths joined with the separator.
+    #[arg(long, value_name = "SEPARATOR", num_args = 0..=1, default_missing_value = "_")]
+    pub flatten: Option<String>,
+    /// SQL query of the `messages` table applied to each written batch
+    /// (e.g. `SELECT *, upper(name) AS name_upper FROM messages WHERE type <> 'HEARTBEAT'`).
+    #[arg(long, value_name = "QUERY")]
+    pub sql: Option<String>,
+    /// Expression selected from the `messages` table instead of a query (e.g. `*`, `upper(name) AS name_upper`).
+    #[arg(long, value_name = "EXPRESSION", conflicts_with = "sql")]
+    pub sql_select: Vec<String>,
+    /// Maximum number of messages buffered per table before flushing.
+    #[arg(long, default_value_t = 5000)]
+    pub max_messages_per_batch: usize,
+    /// Maximum number of seconds a message is buffered before its table is flushed.
+    #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+    pub allowed_latency: u64,
+    /// Maximum number of consumed messages being decoded (in parallel per partition) before they are buffered.
+    #[arg(long, default_value_t = 1000)]
+    pub max_in_flight_messages: usize,
+    /// Maximum number of retries of failed delta table commits.
+    #[arg(long, default_value_t = 5)]
+    pub commit_retries: usize,
+    /// Seconds without processed messages while lagging before the `/healthz` check fails.
+    #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+    pub stall_timeout: u64,
+    /// Seconds to write the buffered messages and commit their offsets on shutdown.
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    pub shutdown_timeout: u64,
+    /// Splits the data of each flush into files of about this size in bytes, a file per partition if not set.
+    #[arg(long, value_name = "BYTES")]
+    pub target_file_size: Option<usize>,
+    /// Creates a table checkpoint every given commits, the table `delta.checkpointInterval` (100) if not set.
+    #[arg(long, value_name = "COMMITS")]
+    pub checkpoint_interval: Option<i64>,
+    /// The `delta.logRetentionDuration` of the created tables in seconds (30 days if not set),
+    /// the log files older than it are deleted after each checkpoint.
+    #[arg(long, value_name = "SECONDS")]
+    pub log_retention: Option<u64>,
+    /// Compacts the small files of the table partitions no longer being written every given seconds,
+    /// disabled if not set.
+    #[arg(long, value_name = "SECONDS")]
+    pub optimize_interval: Option<u64>,
+    /// Target size of the compacted files in bytes.
+    #[arg(long, requires = "optimize_interval")]
+    pub optimize_target_size: Option<i64>,
+    /// Z-orders the compacted files by the columns instead of bin-packing them.
+    #[arg(long, value_delimiter = ',', requires = "optimize_interval")]
+    pub optimize_z_order: Vec<String>,
+    /// Vacuums the tables every given seconds, disabled if not set.
+    #[arg(long, value_name = "SECONDS")]
+    pub vacuum_interval: Option<u64>,
+    /// Seconds the removed data files are retained before being vacuumed,
+    /// the table `delta.deletedFileRetentionDuration` (7 days) if not set.
+    #[arg(long, value_name = "SECONDS", requires = "vacuum_interval")]
+    pub vacuum_retention: Option<u64>,
+    /// Allows a vacuum retention shorter than the table `delta.deletedFileRetentionDuration`,
+    /// which breaks the readers and writers of the older table versions.
+    #[arg(long, requires = "vacuum_retention")]
+    pub vacuum_unsafe_retention: bool,
+    #[command(flatten)]
+    pub parquet: ParquetArgs,
+    #[command(flatten)]
+    pub storage: StorageArgs,
+    #[command(flatten)]
+    pub kafka: KafkaArgs,
+    #[command(flatten)]
+    pub registry: RegistryArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct SchemaArgs {
+    pub topic: String,
+    #[command(flatten)]
+    pub subject: SubjectArgs,
+    #[command(flatten)]
+    pub registry: RegistryArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct TableCreateArgs {
+    pub topic: String,
+    pub table_uri: String,
+    #[command(flatten)]
+    pub subject: SubjectArgs,
+    #[command(flatten)]
+    pub storage: StorageArgs,
+    #[command(flatten)]
+    pub registry: RegistryArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct TableOptimizeArgs {
+    pub table_uri: String,
+    /// Target size of the compacted files in bytes, the table `delta.targetFileSize` (or 100MB) if not set.
+    #[arg(long)]
+    pub target_size: Option<i64>,
+    /// Z-orders the compacted files by the columns instead of bin-packing them.
+    #[arg(long, value_delimiter = ',')]
+    pub z_order: Vec<String>,
+    /// Maximum number of retries of the commits conflicting with concurrent writers.
+    #[arg(long, default_value_t = 5)]
+    pub commit_retries: usize,
+    #[command(flatten)]
+    pub parquet: ParquetArgs,
+    #[command(flatten)]
+    pub storage: StorageArgs,
+}
+
+impl TableOptimizeArgs {
+    pub fn optimize_options(&self) -> OptimizeOptions {
+        OptimizeOptions {
+            target_size: self.target_size,
+            z_order_columns: self.z_order.clone(),
+            parquet: self.parquet.options(),
+            ..Default::default()
+        }
+    }
+
+    pub fn commit_retry(&self) -> RetryOptions {
+        RetryOptions { max_retries: self.commit_retries, ..Default::default() }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct TableVacuumArgs {
+    pub table_uri: String,
+    /// Seconds the removed data files are retained, the table `delta.deletedFileRetentionDuration` (7 days) if not set.
+    #[arg(long, value_name = "SECONDS")]
+    pub retention: Option<u64>,
+    /// Allows a retention shorter than the table `delta.deletedFileRetentionDuration`,
+    /// which breaks the readers and writers of the older table versions.
+    #[arg(long, requires = "retention")]
+    pub unsafe_retention: bool,
+    /// Only lists the files to delete.
+    #[arg(long)]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub storage: StorageArgs,
+}
+
+impl TableVacuumArgs {
+    pub fn vacuum_options(&self) -> VacuumOptions {
+        VacuumOptions {
+            retention: self.retention.map(Duration::from_secs),
+            unsafe_retention: self.unsafe_retention,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct OffsetsArgs {
+    pub topic: String,
+    #[command(flatten)]
+    pub kafka: KafkaArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct OffsetsResetArgs {
+    pub topic: String,
+    /// The new position: earliest, latest or an offset.
+    #[arg(long)]
+    pub to: OffsetReset,
+    /// The partitions to reset, all the topic partitions if not set.
+    #[arg(long = "partition")]
+    pub partitions: Vec<i32>,
+    #[command(flatten)]
+    pub kafka: KafkaArgs,
+}
+
+/// Parquet writer settings of the data files.
+#[derive(Debug, Args)]
+pub struct ParquetArgs {
+    /// Compression codec: uncompressed, snappy, gzip, brotli, lz4 or zstd.
+    #[arg(long, default_value = "snappy")]
+    pub compression: ParquetCompression,
+    /// Level of the gzip (0-10), brotli (0-11) or zstd (1-22) compression.
+    #[arg(long)]
+    pub compression_level: Option<i32>,
+    /// Maximum number of rows per row group.
+    #[arg(long)]
+    pub max_row_group_size: Option<usize>,
+    /// Disables the dictionary encoding of the columns.
+    #[arg(long)]
+    pub no_dictionary: bool,
+    /// Column statistics: none, chunk or page.
+    #[arg(long, default_value = "page")]
+    pub statistics: ParquetStatistics,
+    /// Maximum size in bytes of the min and max statistics of a column.
+    #[arg(long, value_name = "BYTES")]
+    pub max_statistics_size: Option<usize>,
+    /// Columns with bloom filters (dot separated paths of nested fields), e.g. `id`.
+    #[arg(long, value_delimiter = ',')]
+    pub bloom_filter_columns: Vec<String>,
+    /// False positive probability of the bloom filters.
+    #[arg(long, requires = "bloom_filter_columns")]
+    pub bloom_filter_fpp: Option<f64>,
+    /// Number of distinct values the bloom filters are sized for.
+    #[arg(long, requires = "bloom_filter_columns")]
+    pub bloom_filter_ndv: Option<u64>,
+}
+
+impl ParquetArgs {
+    pub fn options(&self) -> ParquetOptions {
+        ParquetOptions {
+            compression: self.compression,
+            compression_level: self.compression_level,
+            max_row_group_size: self.max_row_group_size,
+            dictionary: !self.no_dictionary,
+            statistics: self.statistics,
+            max_statistics_size: self.max_statistics_size,
+            bloom_filter_columns: self.bloom_filter_columns.clone(),
+            bloom_filter_fpp: self.bloom_filter_fpp,
+            bloom_filter_ndv: self.bloom_filter_ndv,
+        }
+    }
+}
+
+/// Object store settings of the table uris.
+#[derive(Debug, Args)]
+pub struct StorageArgs {
+    /// Object store option of the table uris (e.g. `AWS_REGION=eu-west-1`, `AWS_S3_LOCKING_PROVIDER=dynamodb`),
+    /// overrides the `AWS_*`, `GOOGLE_*` and `AZURE_*` environment variables.
+    #[arg(long = "storage-option", value_name = "KEY=VALUE")]
+    pub storage_options: Vec<String>,
+}
+
+impl StorageArgs {
+    pub fn options(&self) -> anyhow::Result<HashMap<String, String>> {
+        self.storage_options.iter().map(|option| split_pair(option)).collect()
+    }
+}
+
+/// The subject of the schema of a topic.
+#[derive(Debug, Args)]
+pub struct SubjectArgs {
+    /// Subject name strategy: topic, record or topic-record.
+    #[arg(long, default_value_t = SubjectStrategy::TopicName)]
+    pub subject_strategy: SubjectStrategy,
+    /// The message full name (e.g. `example.Person`), required by the record strategies.
+    #[arg(long)]
+    pub record_name: Option<String>,
+    /// Use the key schema of the topic.
+    #[arg(long)]
+    pub key: bool,
+}
+
+impl SubjectArgs {
+    pub fn subject(&self, topic: &str) -> anyhow::Result<SubjectNameStrategy> {
+        let record_name = match (self.subject_strategy, &self.record_name) {
+            (SubjectStrategy::TopicName, _) => "",
+            (_, Some(record_name)) => record_name,
+            (strategy, None) => bail!("--record-name is required by the {} subject strategy", strategy),
+        };
+        Ok(self.subject_strategy.subject(topic, record_name, self.key))
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SaslMechanism {
+    Plain,
+    #[value(name = "scram-sha-256")]
+    ScramSha256,
+    #[value(name = "scram-sha-512")]
+    ScramSha512,
+    Oauthbearer,
+}
+
+#[derive(Debug, Args)]
+pub struct KafkaArgs {
+    /// The Kafka broker string to connect to.
+    #[arg(long, default_value = "localhost:9092")]
+    pub kafka_brokers: String,
+    /// The Kafka consumer group id.
+    #[arg(long, default_value = "kafka-delta-ingest")]
+    pub consumer_group_id: String,
+    #[arg(long, value_enum)]
+    pub sasl_mechanism: Option<SaslMechanism>,
+    /// SASL username (PLAIN and SCRAM) or OAuth client id (OAUTHBEARER).
+    #[arg(long)]
+    pub sasl_username: Option<String>,
+    /// SASL password (PLAIN and SCRAM) or OAuth client secret (OAUTHBEARER).
+    #[arg(long, env = "INGEST_SASL_PASSWORD", hide_env_values = true)]
+    pub sasl_password: Option<String>,
+    /// OAuth token endpoint url (OAUTHBEARER).
+    #[arg(long)]
+    pub oauth_token_endpoint_url: Option<String>,
+    /// OAuth scope (OAUTHBEARER).
+    #[arg(long)]
+    pub oauth_scope: Option<String>,
+    /// Connect to the brokers over SSL.
+    #[arg(long)]
+    pub ssl: bool,
+    /// PEM encoded CA certificate of the brokers.
+    #[arg(long)]
+    pub ssl_ca_location: Option<PathBuf>,
+    /// PEM encoded client certificate.
+    #[arg(long)]
+    pub ssl_certificate_location: Option<PathBuf>,
+    /// PEM encoded client private key.
+    #[arg(long)]
+    pub ssl_key_location: Option<PathBuf>,
+    #[arg(long, env = "INGEST_SSL_KEY_PASSWORD", hide_env_values = true)]
+    pub ssl_key_password: Option<String>,
+    /// Java style properties file with extra librdkafka properties.
+    #[arg(long)]
+    pub kafka_properties_file: Option<PathBuf>,
+    /// Extra librdkafka property, overrides the properties file and the `KAFKA_*` environment variables.
+    #[arg(long = "kafka-property", value_name = "KEY=VALUE")]
+    pub kafka_properties: Vec<String>,
+}
+
+impl KafkaArgs {
+    pub fn apply(&self, opts: &mut IngestOptions) -> anyhow::Result<()> {
+        opts.kafka_brokers = self.kafka_brokers.clone();
+        opts.consumer_group_id = self.consumer_group_id.clone();
+        opts.kafka_security = self.security()?;
+
+        let mut properties = match &self.kafka_properties_file {
+            Some(path) => kafka_properties_from_file(path)?,
+            None => HashMap::new(),
+        };
+        properties.extend(kafka_properties_from_env());
+        for property in &self.kafka_properties {
+            let (key, value) = split_pair(property)?;
+            properties.insert(key, value);
+        }
+        opts.kafka_properties = properties;
+        Ok(())
+    }
+
+    fn security(&self) -> anyhow::Result<KafkaSecurity> {
+        let credentials = || -> anyhow::Result<(String, String)> {
+            match (&self.sasl_username, &self.sasl_password) {
+                (Some(username), Some(password)) => Ok((username.clone(), password.clone())),
+                _ => bail!("--sasl-username and --sasl-password are required by the SASL mechanism"),
+            }
+        };
+        let auth = match self.sasl_mechanism {
+            None => KafkaAuth::None,
+            Some(SaslMechanism::Plain) => {
+                let (username, password) = credentials()?;
+                KafkaAuth::Plain { username, password }
+            }
+            Some(SaslMechanism::ScramSha256) => {
+                let (username, password) = credentials()?;
+                KafkaAuth::ScramSha256 { username, password }
+            }
+            Some(SaslMechanism::ScramSha512) => {
+                let (username, password) = credentials()?;
+                KafkaAuth::ScramSha512 { username, password }
+            }
+            Some(SaslMechanism::Oauthbearer) => {
+                let (client_id, client_secret) = credentials()?;
+                KafkaAuth::OAuthBearer {
+                    client_id,
+                    client_secret,
+                    token_endpoint_url: self.oauth_token_endpoint_url.clone()
+                        .ok_or_else(|| anyhow!("--oauth-token-endpoint-url is required by OAUTHBEARER"))?,
+                    scope: self.oauth_scope.clone(),
+                }
+            }
+        };
+
+        let tls = KafkaTls {
+            ca_location: self.ssl_ca_location.clone(),
+            certificate_location: self.ssl_certificate_location.clone(),
+            key_location: self.ssl_key_location.clone(),
+            key_password: self.ssl_key_password.clone(),
+        };
+        let tls = (self.ssl || tls != KafkaTls::default()).then_some(tls);
+        Ok(KafkaSecurity { auth, tls })
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RegistryArgs {
+    /// Schema registry url, repeat for failover urls.
+    #[arg(long = "schema-registry-url", default_value = "http://localhost:8081")]
+    pub schema_registry_urls: Vec<String>,
+    /// Basic authorization username (or `SCHEMA_REGISTRY_USERNAME`).
+    #[arg(long)]
+    pub schema_registry_username: Option<String>,
+    /// Basic authorization password (or `SCHEMA_REGISTRY_PASSWORD`).
+    #[arg(long)]
+    pub schema_registry_password: Option<String>,
+    /// Bearer token (or `SCHEMA_REGISTRY_TOKEN`).
+    #[arg(long)]
+    pub schema_registry_token: Option<String>,
+    /// PEM encoded CA certificate of the registry.
+    #[arg(long)]
+    pub schema_registry_ca_certificate: Option<PathBuf>,
+    /// PEM encoded client certificate, requires `--schema-registry-client-key`.
+    #[arg(long, requires = "schema_registry_client_key")]
+    pub schema_registry_client_certificate: Option<PathBuf>,
+    /// PEM encoded (PKCS #8) client private key.
+    #[arg(long, requires = "schema_registry_client_certificate")]
+    pub schema_registry_client_key: Option<PathBuf>,
+    /// Timeout in seconds of every registry request.
+    #[arg(long, value_name = "SECONDS")]
+    pub schema_registry_timeout: Option<u64>,
+    /// Maximum number of retries of failed registry requests.
+    #[arg(long, default_value_t = 5)]
+    pub schema_registry_retries: usize,
+}
+
+impl RegistryArgs {
+    pub fn settings(&self) -> RegistrySettings {
+        let mut settings = RegistrySettings {
+            urls: self.schema_registry_urls.clone(),
+            ..RegistrySettings::new("")
+        };
+        if let Some(token) = &self.schema_registry_token {
+            settings.auth = RegistryAuth::Bearer { token: token.clone() };
+        } else if let Some(username) = &self.schema_registry_username {
+            settings.auth = RegistryAuth::Basic { username: username.clone(), password: self.schema_registry_password.clone() };
+        }
+        settings.ca_certificate = self.schema_registry_ca_certificate.clone();
+        settings.client_identity = self.schema_registry_client_certificate.clone()
+            .zip(self.schema_registry_client_key.clone());
+        settings.timeout = self.schema_registry_timeout.map(Duration::from_secs);
+        settings.with_env_credentials()
+    }
+
+    pub fn retry_options(&self) -> RetryOptions {
+        RetryOptions { max_retries: self.schema_registry_retries, ..Default::default() }
+    }
+}
+
+impl RunArgs {
+    /// The named ingest options of the config file pipelines or else of the command line arguments.
+    pub fn pipelines(&self) -> anyhow::Result<Vec<(String, IngestOptions)>> {
+        match &self.config {
+            Some(path) => Ok(IngestConfig::from_file(path)?),
+            None => {
+                let opts = self.to_ingest_options()?;
+                Ok(vec![(opts.consumer_group_id.clone(), opts)])
+            }
+        }
+    }
+
+    pub fn to_ingest_options(&self) -> anyhow::Result<IngestOptions> {
+        let (Some(topic), Some(table_uri)) = (&self.topic, &self.table_uri) else {
+            bail!("The topic and table uri are required");
+        };
+        let mut route = TopicRoute::new(topic, table_uri);
+        for message_table in &self.message_tables {
+            let (full_name, table_uri) = split_pair(message_table)?;
+            route = route.with_message_table(full_name, table_uri);
+        }
+        route.subject_strategy = self.subject_strategy;
+        route.key_format = self.key_format;
+        route.tombstones = self.tombstones;
+        if !self.upsert_keys.is_empty() {
+            route.write_mode = WriteMode::Upsert {
+                keys: self.upsert_keys.clone(),
+                event_time: self.upsert_event_time.clone(),
+            };
+        }
+        route.partition_columns = self.partition_by.clone();
+        route.transforms = FieldTransforms {
+            select: self.select_fields.clone(),
+            drop: self.drop_fields.clone(),
+            rename: self.rename_fields.iter().map(|rename| split_pair(rename)).collect::<anyhow::Result<_>>()?,
+            flatten: self.flatten.clone(),
+        };
+        route.sql_transform = match &self.sql {
+            Some(query) => Some(SqlTransform::new(query)),
+            None if !self.sql_select.is_empty() => Some(SqlTransform::select(&self.sql_select)),
+            None => None,
+        };
+        if self.cdc {
+            route.cdc_envelope = Some(CdcEnvelope::default());
+        }
+
+        let mut opts = IngestOptions {
+            input_format: MessageFormat::Protobuf(SchemaSource::SchemaRegistry(self.registry.settings())),
+            routes: vec![route],
+            max_messages_per_batch: self.max_messages_per_batch,
+            allowed_latency: Duration::from_secs(self.allowed_latency),
+            max_in_flight_messages: self.max_in_flight_messages,
+            schema_registry_retry: self.registry.retry_options(),
+            commit_retry: RetryOptions { max_retries: self.commit_retries, ..Default::default() },
+            stall_timeout: Duration::from_secs(self.stall_timeout),
+            shutdown_timeout: Duration::from_secs(self.shutdown_timeout),
+            optimize: self.optimize_interval.map(|interval| OptimizeOptions {
+                interval: Duration::from_secs(interval),
+                target_size: self.optimize_target_size,
+                z_order_columns: self.optimize_z_order.clone(),
+                parquet: self.parquet.options(),
+            }),
+            parquet: self.parquet.options(),
+            target_file_size: self.target_file_size,
+            checkpoint_interval: self.checkpoint_interval,
+            log_retention: self.log_retention.map(Duration::from_secs),
+            storage_options: self.storage.options()?,
+            vacuum: self.vacuum_interval.map(|interval| VacuumOptions {
+                interval: Duration::from_secs(interval),
+                retention: self.vacuum_retention.map(Duration::from_secs),
+                unsafe_retention: self.vacuum_unsafe_retention,
+            }),
+            ..Default::default()
+        };
+        self.kafka.apply(&mut opts)?;
+        Ok(opts)
+    }
+}
+
+/// Splits a `key=value` argument.
+fn split_pair(arg: &str) -> anyhow::Result<(String, String)> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .ok_or_else(|| anyhow!("Invalid argument {}, expected KEY=VALUE", arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn verify_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn global_log_options() {
+        let cli = Cli::parse_from(["ingest", "offsets", "show", "proto.ds.person", "--log-format", "json", "--log-filter", "ingest=debug"]);
+        assert_eq!(cli.log_format, LogFormat::Json);
+        assert_eq!(cli.log_filter, "ingest=debug");
+    }
+
+    #[test]
+    fn run_args_to_ingest_options() {
+        let cli = Cli::parse_from([
+            "ingest", "run", "proto.ds.person", "./data/persons",
+            "--message-table", "example.Contact=./data/contacts",
+            "--subject-strategy", "record",
+            "--key-format", "string",
+            "--upsert-keys", "id,key",
+            "--partition-by", "hour(_kafka_timestamp)",
+            "--drop-fields", "details.email", "--rename-field", "details.physical.age=age", "--flatten",
+            "--sql-select", "*", "--sql-select", "concat(id, '-', key) AS uid",
+            "--allowed-latency", "10",
+            "--optimize-interval", "600", "--optimize-z-order", "id",
+            "--checkpoint-interval", "10", "--vacuum-interval", "86400", "--vacuum-retention", "3600", "--vacuum-unsafe-retention",
+            "--compression", "zstd", "--bloom-filter-columns", "id",
+            "--storage-option", "AWS_REGION=eu-west-1", "--storage-option", "AWS_S3_LOCKING_PROVIDER=dynamodb",
+            "--kafka-brokers", "kafka:9092",
+            "--sasl-mechanism", "scram-sha-512", "--sasl-username", "user", "--sasl-password", "secret",
+            "--kafka-property", "session.timeout.ms=45000",
+            "--schema-registry-url", "http://registry-1:8081", "--schema-registry-url", "http://registry-2:8081",
+            "--schema-registry-token", "token",
+        ]);
+        let Command::Run(args) = cli.command else {
+            panic!("Expected run command");
+        };
+
+        let opts = args.to_ingest_options().unwrap();
+        assert_eq!(opts.kafka_brokers, "kafka:9092");
+        assert_eq!(opts.allowed_latency, Duration::from_secs(10));
+        assert_eq!(opts.optimize, Some(OptimizeOptions {
+            interval: Duration::from_secs(600),
+            target_size: None,
+            z_order_columns: vec!["id".to_string()],
+            parquet: opts.parquet.clone(),
+        }));
+        assert_eq!(opts.parquet.compression, ParquetCompression::Zstd);
+        assert_eq!(opts.parquet.bloom_filter_columns, vec!["id".to_string()]);
+        assert!(opts.parquet.dictionary);
+        assert_eq!(opts.checkpoint_interval, Some(10));
+        assert_eq!(opts.log_retention, None);
+        assert_eq!(opts.vacuum, Some(VacuumOptions {
+            interval: Duration::from_secs(86400),
+            retention: Some(Duration::from_secs(3600)),
+            unsafe_retention: true,
+        }));
+        assert_eq!(opts.kafka_security.auth, KafkaAuth::ScramSha512 { username: "user".to_string(), password: "secret".to_string() });
+        assert_eq!(opts.storage_options.get("AWS_REGION").map(String::as_str), Some("eu-west-1"));
+        assert_eq!(opts.storage_options.get("AWS_S3_LOCKING_PROVIDER").map(String::as_str), Some("dynamodb"));
+        assert_eq!(opts.kafka_properties.get("session.timeout.ms").map(String::as_str), Some("45000"));
+
+        let route = &opts.routes[0];
+        assert_eq!(route.table_uri_of("proto.ds.person", "example.Contact"), Some("./data/contacts".to_string()));
+        assert_eq!(route.subject_strategy, Some(SubjectStrategy::RecordName));
+        assert_eq!(route.key_format, KeyFormat::String);
+        assert_eq!(route.partition_columns[0].name, "_kafka_timestamp_hour");
+        assert_eq!(route.sql_transform, Some(SqlTransform::new("SELECT *, concat(id, '-', key) AS uid FROM messages")));
+        assert_eq!(route.transforms, FieldTransforms {
+            drop: vec!["details.email".to_string()],
+            rename: vec![("details.physical.age".to_string(), "age".to_string())],
+            flatten: Some("_".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(route.write_mode, WriteMode::Upsert { keys: vec!["id".to_string(), "key".to_string()], event_time: None });
+
+        let MessageFormat::Protobuf(SchemaSource::SchemaRegistry(settings)) = &opts.input_format else {
+            panic!("Expected schema registry source");
+        };
+        assert_eq!(settings.urls, vec!["http://registry-1:8081", "http://registry-2:8081"]);
+        assert_eq!(settings.auth, RegistryAuth::Bearer { token: "token".to_string() });
+    }
+}
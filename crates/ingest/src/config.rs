@@ -0,0 +1,943 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use schema_registry::{RegistryAuth, RegistrySettings, RetryOptions, SubjectStrategy};
+use serde::Deserialize;
+
+use crate::routing::TopicRouter;
+use crate::{CdcEnvelope, FieldTransforms, IngestError, IngestOptions, KafkaAuth, KeyFormat, TombstoneMode, KafkaSecurity, KafkaTls, kafka_properties_from_env, kafka_properties_from_file, MessageFormat, OptimizeOptions, ParquetCompression, ParquetOptions, ParquetStatistics, PartitionColumn, SchemaSource, SqlTransform, TopicRoute, VacuumOptions, WriteMode};
+
+/// Pipelines configuration file, in YAML (`.yaml`, `.yml`) or TOML (`.toml`) format.
+/// `${VAR}` and `${VAR:-default}` references in the string values are replaced with the values of the environment
+/// variables, after the file is parsed (the values are never parsed as YAML or TOML).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IngestConfig {
+    pub pipelines: Vec<PipelineConfig>,
+}
+
+/// An ingest pipeline, the source topics of a consumer group and their target tables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub name: String,
+    pub topics: Vec<TopicConfig>,
+    pub schema_registry: RegistryConfig,
+    #[serde(default)]
+    pub kafka: KafkaConfig,
+    #[serde(default)]
+    pub flush: FlushConfig,
+    /// Maximum number of retries of failed delta table commits.
+    #[serde(default = "default_retries")]
+    pub commit_retries: usize,
+    /// Seconds without processed messages while lagging before the pipeline fails the liveness check.
+    #[serde(default = "default_stall_timeout_secs")]
+    pub stall_timeout_secs: u64,
+    /// Maximum number of consumed messages being decoded before they are buffered.
+    #[serde(default = "default_max_in_flight_messages")]
+    pub max_in_flight_messages: usize,
+    /// Seconds to write the buffered messages and commit their offsets on shutdown.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Parquet writer settings of the written data files.
+    #[serde(default)]
+    pub parquet: ParquetConfig,
+    /// Creates a table checkpoint every given commits instead of the table `delta.checkpointInterval`.
+    pub checkpoint_interval: Option<i64>,
+    /// The `delta.logRetentionDuration` of the created tables, expired log files are deleted after each checkpoint.
+    pub log_retention_secs: Option<u64>,
+    /// Periodically compacts the small files of the written tables.
+    pub optimize: Option<OptimizeConfig>,
+    /// Periodically vacuums the written tables.
+    pub vacuum: Option<VacuumConfig>,
+    /// Object store credentials and settings of the table uris.
+    #[serde(default)]
+    pub storage: StorageConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopicConfig {
+    /// The topic name or a regex topic pattern starting with `^`.
+    pub topic: String,
+    pub table_uri: Option<String>,
+    /// Target table uris per proto message full name.
+    #[serde(default)]
+    pub message_tables: HashMap<String, String>,
+    /// `topic`, `record` or `topic-record`.
+    pub subject_strategy: Option<String>,
+    /// `none`, `string`, `bytes` or `protobuf`.
+    pub key_format: Option<String>,
+    /// `skip`, `soft-delete` or `delete`.
+    pub tombstones: Option<String>,
+    /// Merges the messages into the tables instead of appending them.
+    pub upsert: Option<UpsertConfig>,
+    /// Unwraps change data capture envelopes, requires `upsert`.
+    pub cdc: Option<CdcConfig>,
+    /// Partition columns of the created tables, fields or `date(<field>)`, `hour(<field>)` derived columns.
+    #[serde(default)]
+    pub partition_by: Vec<String>,
+    /// Transforms of the message fields before they are written.
+    #[serde(default)]
+    pub transform: TransformConfig,
+    /// SQL query of the `messages` table applied to each written batch, e.g. computing columns or filtering rows.
+    pub sql: Option<String>,
+    /// Expressions selected from the `messages` table (e.g. `["*", "upper(name) AS name_upper"]`) instead of a query.
+    #[serde(default)]
+    pub sql_select: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpsertConfig {
+    /// The key columns the messages are merged on.
+    pub keys: Vec<String>,
+    /// The column resolving duplicate keys, the kafka offset if not set.
+    pub event_time: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct OptimizeConfig {
+    pub interval_secs: u64,
+    /// Target size of the compacted files in bytes.
+    pub target_size: Option<i64>,
+    /// Z-orders the compacted files by the columns instead of bin-packing them.
+    pub z_order: Vec<String>,
+}
+
+impl Default for OptimizeConfig {
+    fn default() -> Self {
+        let opts = OptimizeOptions::default();
+        Self {
+            interval_secs: opts.interval.as_secs(),
+            target_size: opts.target_size,
+            z_order: opts.z_order_columns,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ParquetConfig {
+    /// `uncompressed`, `snappy`, `gzip`, `brotli`, `lz4` or `zstd`.
+    pub compression: Option<String>,
+    /// Level of the gzip, brotli or zstd compression.
+    pub compression_level: Option<i32>,
+    pub max_row_group_size: Option<usize>,
+    pub dictionary: bool,
+    /// `none`, `chunk` or `page`.
+    pub statistics: Option<String>,
+    pub max_statistics_size: Option<usize>,
+    /// Columns with bloom filters, dot separated paths of nested fields.
+    pub bloom_filter_columns: Vec<String>,
+    pub bloom_filter_fpp: Option<f64>,
+    pub bloom_filter_ndv: Option<u64>,
+    /// Splits the data of each flush into files of about this size in bytes.
+    pub target_file_size: Option<usize>,
+}
+
+impl Default for ParquetConfig {
+    fn default() -> Self {
+        let opts = ParquetOptions::default();
+        Self {
+            compression: None,
+            compression_level: opts.compression_level,
+            max_row_group_size: opts.max_row_group_size,
+            dictionary: opts.dictionary,
+            statistics: None,
+            max_statistics_size: opts.max_statistics_size,
+            bloom_filter_columns: opts.bloom_filter_columns,
+            bloom_filter_fpp: opts.bloom_filter_fpp,
+            bloom_filter_ndv: opts.bloom_filter_ndv,
+            target_file_size: None,
+        }
+    }
+}
+
+impl ParquetConfig {
+    fn try_into_parquet_options(self) -> Result<ParquetOptions, IngestError> {
+        let opts = ParquetOptions {
+            compression: self.compression
+                .map(|s| s.parse::<ParquetCompression>())
+                .transpose()
+                .map_err(invalid)?
+                .unwrap_or_default(),
+            compression_level: self.compression_level,
+            max_row_group_size: self.max_row_group_size,
+            dictionary: self.dictionary,
+            statistics: self.statistics
+                .map(|s| s.parse::<ParquetStatistics>())
+                .transpose()
+                .map_err(invalid)?
+                .unwrap_or_default(),
+            max_statistics_size: self.max_statistics_size,
+            bloom_filter_columns: self.bloom_filter_columns,
+            bloom_filter_fpp: self.bloom_filter_fpp,
+            bloom_filter_ndv: self.bloom_filter_ndv,
+        };
+        // Fails on invalid compression levels
+        opts.writer_properties().map_err(invalid)?;
+        Ok(opts)
+    }
+}
+
+/// Field transforms, the fields are dot separated paths of nested fields (e.g. `details.physical.age`).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct TransformConfig {
+    /// The fields kept, all if empty.
+    pub select: Vec<String>,
+    pub drop: Vec<String>,
+    /// New names of the fields, renamed in place.
+    pub rename: HashMap<String, String>,
+    /// Flattens the nested fields into top level columns joined with the separator (e.g. `_`).
+    pub flatten: Option<String>,
+}
+
+impl From<TransformConfig> for FieldTransforms {
+    fn from(config: TransformConfig) -> Self {
+        let mut rename: Vec<_> = config.rename.into_iter().collect();
+        rename.sort();
+        FieldTransforms {
+            select: config.select,
+            drop: config.drop,
+            rename,
+            flatten: config.flatten,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct VacuumConfig {
+    pub interval_secs: u64,
+    /// Retention of the removed data files, the table `delta.deletedFileRetentionDuration` if not set.
+    pub retention_secs: Option<u64>,
+    /// Allows a retention shorter than the table `delta.deletedFileRetentionDuration`.
+    pub unsafe_retention: bool,
+}
+
+impl Default for VacuumConfig {
+    fn default() -> Self {
+        let opts = VacuumOptions::default();
+        Self {
+            interval_secs: opts.interval.as_secs(),
+            retention_secs: opts.retention.map(|retention| retention.as_secs()),
+            unsafe_retention: opts.unsafe_retention,
+        }
+    }
+}
+
+/// Object store settings of the `s3://`, `gs://` and `az://` (`abfss://` etc) table uris, the ones not set
+/// are read from the `AWS_*`, `GOOGLE_*` and `AZURE_*` environment variables.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct StorageConfig {
+    pub s3: Option<S3Config>,
+    pub gcs: Option<GcsConfig>,
+    pub azure: Option<AzureConfig>,
+    /// Extra object store options (e.g. `AWS_S3_ADDRESSING_STYLE: path`), overriding the ones above.
+    pub options: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct S3Config {
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    pub region: Option<String>,
+    /// Endpoint of S3 compatible stores, e.g. `http://localhost:9000` of MinIO.
+    pub endpoint: Option<String>,
+    pub allow_http: bool,
+    /// Locks the concurrent commits of the writers of a table with a DynamoDB table.
+    pub dynamodb_lock: Option<DynamoDbLockConfig>,
+    /// Conditional copy header of the S3 compatible stores supporting it, an alternative to the DynamoDB lock
+    /// (e.g. `header:cf-copy-destination-if-none-match:*` of Cloudflare R2).
+    pub copy_if_not_exists: Option<String>,
+    /// Commits without a lock, safe only with a single writer per table.
+    pub allow_unsafe_rename: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DynamoDbLockConfig {
+    /// The lock table, `delta_log` if not set.
+    pub table_name: Option<String>,
+    pub endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct GcsConfig {
+    /// Path of the service account JSON file.
+    pub service_account: Option<PathBuf>,
+    /// The service account JSON key.
+    pub service_account_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct AzureConfig {
+    pub account_name: Option<String>,
+    pub access_key: Option<String>,
+    pub sas_token: Option<String>,
+    /// Uses the Azurite storage emulator.
+    pub use_emulator: bool,
+}
+
+impl StorageConfig {
+    /// The object store options of the table uris.
+    fn try_into_storage_options(self) -> Result<HashMap<String, String>, IngestError> {
+        let mut options = HashMap::new();
+        let mut set = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                options.insert(key.to_string(), value);
+            }
+        };
+        if let Some(s3) = self.s3 {
+            let locks = [s3.dynamodb_lock.is_some(), s3.copy_if_not_exists.is_some(), s3.allow_unsafe_rename];
+            if locks.into_iter().filter(|lock| *lock).count() > 1 {
+                return Err(invalid("Only one of dynamodb_lock, copy_if_not_exists or allow_unsafe_rename can be set"));
+            }
+            set("AWS_ACCESS_KEY_ID", s3.access_key_id);
+            set("AWS_SECRET_ACCESS_KEY", s3.secret_access_key);
+            set("AWS_SESSION_TOKEN", s3.session_token);
+            set("AWS_REGION", s3.region);
+            set("AWS_ENDPOINT_URL", s3.endpoint);
+            set("AWS_ALLOW_HTTP", s3.allow_http.then(|| "true".to_string()));
+            if let Some(lock) = s3.dynamodb_lock {
+                set("AWS_S3_LOCKING_PROVIDER", Some("dynamodb".to_string()));
+                set("DELTA_DYNAMO_TABLE_NAME", lock.table_name);
+                set("AWS_ENDPOINT_URL_DYNAMODB", lock.endpoint);
+            }
+            set("aws_copy_if_not_exists", s3.copy_if_not_exists);
+            set("AWS_S3_ALLOW_UNSAFE_RENAME", s3.allow_unsafe_rename.then(|| "true".to_string()));
+        }
+        if let Some(gcs) = self.gcs {
+            set("GOOGLE_SERVICE_ACCOUNT", gcs.service_account.map(|path| path.to_string_lossy().into_owned()));
+            set("GOOGLE_SERVICE_ACCOUNT_KEY", gcs.service_account_key);
+        }
+        if let Some(azure) = self.azure {
+            set("AZURE_STORAGE_ACCOUNT_NAME", azure.account_name);
+            set("AZURE_STORAGE_ACCOUNT_KEY", azure.access_key);
+            set("AZURE_STORAGE_SAS_KEY", azure.sas_token);
+            set("AZURE_STORAGE_USE_EMULATOR", azure.use_emulator.then(|| "true".to_string()));
+        }
+        options.extend(self.options);
+        Ok(options)
+    }
+}
+
+/// The field names of the change data capture envelope, Debezium's by default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct CdcConfig {
+    pub before: String,
+    pub after: String,
+    pub op: String,
+}
+
+impl Default for CdcConfig {
+    fn default() -> Self {
+        let envelope = CdcEnvelope::default();
+        Self {
+            before: envelope.before,
+            after: envelope.after,
+            op: envelope.op,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
+    pub urls: Vec<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    pub ca_certificate: Option<PathBuf>,
+    pub client_certificate: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub timeout_secs: Option<u64>,
+    #[serde(default = "default_retries")]
+    pub retries: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct KafkaConfig {
+    pub brokers: String,
+    pub group_id: String,
+    pub sasl: Option<SaslConfig>,
+    pub ssl: Option<SslConfig>,
+    /// Java style properties file with extra librdkafka properties.
+    pub properties_file: Option<PathBuf>,
+    /// Extra librdkafka properties, overriding the properties file and the `KAFKA_*` environment variables.
+    pub properties: HashMap<String, String>,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        let opts = IngestOptions::default();
+        Self {
+            brokers: opts.kafka_brokers,
+            group_id: opts.consumer_group_id,
+            sasl: None,
+            ssl: None,
+            properties_file: None,
+            properties: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SaslConfig {
+    /// `plain`, `scram-sha-256`, `scram-sha-512` or `oauthbearer`.
+    pub mechanism: String,
+    /// Username or OAuth client id.
+    pub username: String,
+    /// Password or OAuth client secret.
+    pub password: String,
+    pub oauth_token_endpoint_url: Option<String>,
+    pub oauth_scope: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SslConfig {
+    pub ca_location: Option<PathBuf>,
+    pub certificate_location: Option<PathBuf>,
+    pub key_location: Option<PathBuf>,
+    pub key_password: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct FlushConfig {
+    pub max_messages_per_batch: usize,
+    pub allowed_latency_secs: u64,
+}
+
+impl Default for FlushConfig {
+    fn default() -> Self {
+        let opts = IngestOptions::default();
+        Self {
+            max_messages_per_batch: opts.max_messages_per_batch,
+            allowed_latency_secs: opts.allowed_latency.as_secs(),
+        }
+    }
+}
+
+fn default_retries() -> usize {
+    RetryOptions::default().max_retries
+}
+
+fn default_stall_timeout_secs() -> u64 {
+    IngestOptions::default().stall_timeout.as_secs()
+}
+
+fn default_max_in_flight_messages() -> usize {
+    IngestOptions::default().max_in_flight_messages
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    IngestOptions::default().shutdown_timeout.as_secs()
+}
+
+impl IngestConfig {
+    /// Reads, interpolates and validates the pipelines of a configuration file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Vec<(String, IngestOptions)>, IngestError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| invalid(format!("Failed to read config {:?}: {}", path, e)))?;
+        let var = |name: &str| std::env::var(name).ok();
+
+        let config = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => Self::from_yaml(&content, &var),
+            Some("toml") => Self::from_toml(&content, &var),
+            _ => return Err(invalid(format!("Unsupported config format {:?}, expected yaml or toml", path))),
+        };
+        config.map_err(|e| invalid(format!("Invalid config {:?}: {}", path, e)))?
+            .into_ingest_options()
+    }
+
+    fn from_yaml<F: Fn(&str) -> Option<String>>(content: &str, var: &F) -> Result<Self, IngestError> {
+        let mut value: serde_yaml::Value = serde_yaml::from_str(content).map_err(invalid)?;
+        interpolate_yaml(&mut value, var)?;
+        serde_yaml::from_value(value).map_err(invalid)
+    }
+
+    fn from_toml<F: Fn(&str) -> Option<String>>(content: &str, var: &F) -> Result<Self, IngestError> {
+        let mut value: toml::Value = toml::from_str(content).map_err(invalid)?;
+        interpolate_toml(&mut value, var)?;
+        value.try_into().map_err(invalid)
+    }
+
+    /// Validates and converts the pipelines to their ingest options.
+    pub fn into_ingest_options(self) -> Result<Vec<(String, IngestOptions)>, IngestError> {
+        if self.pipelines.is_empty() {
+            return Err(invalid("At least one pipeline is required".to_string()));
+        }
+        let mut names = HashSet::new();
+        self.pipelines.into_iter()
+            .map(|pipeline| {
+                if !names.insert(pipeline.name.clone()) {
+                    return Err(invalid(format!("Duplicate pipeline {}", pipeline.name)));
+                }
+                let name = pipeline.name.clone();
+                let opts = pipeline.try_into_ingest_options()
+                    .map_err(|e| invalid(format!("Pipeline {}: {}", name, e)))?;
+                Ok((name, opts))
+            })
+            .collect()
+    }
+}
+
+impl PipelineConfig {
+    fn try_into_ingest_options(self) -> Result<IngestOptions, IngestError> {
+        let routes = self.topics.into_iter()
+            .map(|t| {
+                let subject_strategy = t.subject_strategy
+                    .map(|s| s.parse::<SubjectStrategy>())
+                    .transpose()
+                    .map_err(invalid)?;
+                let key_format = t.key_format
+                    .map(|s| s.parse::<KeyFormat>())
+                    .transpose()
+                    .map_err(invalid)?
+                    .unwrap_or_default();
+                let tombstones = t.tombstones
+                    .map(|s| s.parse::<TombstoneMode>())
+                    .transpose()
+                    .map_err(invalid)?
+                    .unwrap_or_default();
+                let sql_transform = match (t.sql, t.sql_select.is_empty()) {
+                    (Some(_), false) => return Err(invalid(format!("Both sql and sql_select of topic {} are set", t.topic))),
+                    (Some(query), true) => Some(SqlTransform::new(query)),
+                    (None, false) => Some(SqlTransform::select(&t.sql_select)),
+                    (None, true) => None,
+                };
+                let partition_columns = t.partition_by.iter()
+                    .map(|s| s.parse::<PartitionColumn>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(invalid)?;
+                Ok(TopicRoute {
+                    topic: t.topic,
+                    table_uri: t.table_uri,
+                    message_tables: t.message_tables,
+                    subject_strategy,
+                    key_format,
+                    tombstones,
+                    write_mode: t.upsert.map_or(WriteMode::Append, |upsert| WriteMode::Upsert {
+                        keys: upsert.keys,
+                        event_time: upsert.event_time,
+                    }),
+                    cdc_envelope: t.cdc.map(|cdc| CdcEnvelope {
+                        before: cdc.before,
+                        after: cdc.after,
+                        op: cdc.op,
+                    }),
+                    partition_columns,
+                    transforms: t.transform.into(),
+                    sql_transform,
+                })
+            })
+            .collect::<Result<Vec<_>, IngestError>>()?;
+        TopicRouter::try_new(&routes)?;
+
+        let registry = self.schema_registry;
+        let mut settings = RegistrySettings {
+            urls: registry.urls,
+            ..RegistrySettings::new("")
+        };
+        if let Some(token) = registry.token {
+            settings.auth = RegistryAuth::Bearer { token };
+        } else if let Some(username) = registry.username {
+            settings.auth = RegistryAuth::Basic { username, password: registry.password };
+        }
+        settings.ca_certificate = registry.ca_certificate;
+        settings.client_identity = match (registry.client_certificate, registry.client_key) {
+            (Some(certificate), Some(key)) => Some((certificate, key)),
+            (None, None) => None,
+            _ => return Err(invalid("Both the registry client certificate and key are required".to_string())),
+        };
+        settings.timeout = registry.timeout_secs.map(Duration::from_secs);
+        let settings = settings.with_env_credentials();
+        // Fails on missing urls or invalid certificates
+        settings.build()?;
+
+        if let Some(retention) = self.vacuum.as_ref().and_then(|vacuum| vacuum.retention_secs) {
+            chrono::Duration::from_std(Duration::from_secs(retention))
+                .map_err(|_| invalid(format!("The vacuum retention_secs {} is out of range", retention)))?;
+        }
+        let storage_options = self.storage.try_into_storage_options()?;
+        let target_file_size = self.parquet.target_file_size;
+        let parquet = self.parquet.try_into_parquet_options()?;
+
+        let kafka = self.kafka;
+        let mut kafka_properties = match &kafka.properties_file {
+            Some(path) => kafka_properties_from_file(path)?,
+            None => HashMap::new(),
+        };
+        kafka_properties.extend(kafka_properties_from_env());
+        kafka_properties.extend(kafka.properties);
+
+        Ok(IngestOptions {
+            kafka_brokers: kafka.brokers,
+            consumer_group_id: kafka.group_id,
+            kafka_security: KafkaSecurity {
+                auth: kafka.sasl.map(SaslConfig::try_into_auth).transpose()?.unwrap_or_default(),
+                tls: kafka.ssl.map(|ssl| KafkaTls {
+                    ca_location: ssl.ca_location,
+                    certificate_location: ssl.certificate_location,
+                    key_location: ssl.key_location,
+                    key_password: ssl.key_password,
+                }),
+            },
+            kafka_properties,
+            input_format: MessageFormat::Protobuf(SchemaSource::SchemaRegistry(settings)),
+            routes,
+            max_messages_per_batch: self.flush.max_messages_per_batch,
+            allowed_latency: Duration::from_secs(self.flush.allowed_latency_secs),
+            max_in_flight_messages: self.max_in_flight_messages,
+            schema_registry_retry: RetryOptions { max_retries: registry.retries, ..Default::default() },
+            commit_retry: RetryOptions { max_retries: self.commit_retries, ..Default::default() },
+            stall_timeout: Duration::from_secs(self.stall_timeout_secs),
+            shutdown_timeout: Duration::from_secs(self.shutdown_timeout_secs),
+            storage_options,
+            target_file_size,
+            checkpoint_interval: self.checkpoint_interval,
+            log_retention: self.log_retention_secs.map(Duration::from_secs),
+            optimize: self.optimize.map(|optimize| OptimizeOptions {
+                interval: Duration::from_secs(optimize.interval_secs),
+                target_size: optimize.target_size,
+                z_order_columns: optimize.z_order,
+                parquet: parquet.clone(),
+            }),
+            parquet,
+            vacuum: self.vacuum.map(|vacuum| VacuumOptions {
+                interval: Duration::from_secs(vacuum.interval_secs),
+                retention: vacuum.retention_secs.map(Duration::from_secs),
+                unsafe_retention: vacuum.unsafe_retention,
+            }),
+        })
+    }
+}
+
+impl SaslConfig {
+    fn try_into_auth(self) -> Result<KafkaAuth, IngestError> {
+        let SaslConfig { mechanism, username, password, oauth_token_endpoint_url, oauth_scope } = self;
+        match mechanism.to_lowercase().as_str() {
+            "plain" => Ok(KafkaAuth::Plain { username, password }),
+            "scram-sha-256" => Ok(KafkaAuth::ScramSha256 { username, password }),
+            "scram-sha-512" => Ok(KafkaAuth::ScramSha512 { username, password }),
+            "oauthbearer" => Ok(KafkaAuth::OAuthBearer {
+                client_id: username,
+                client_secret: password,
+                token_endpoint_url: oauth_token_endpoint_url
+                    .ok_or_else(|| invalid("oauth_token_endpoint_url is required by oauthbearer".to_string()))?,
+                scope: oauth_scope,
+            }),
+            _ => Err(invalid(format!("Unknown SASL mechanism {}", mechanism))),
+        }
+    }
+}
+
+/// Interpolates the string values of a parsed YAML config, the mapping keys are kept as is.
+fn interpolate_yaml<F: Fn(&str) -> Option<String>>(value: &mut serde_yaml::Value, var: &F) -> Result<(), IngestError> {
+    match value {
+        serde_yaml::Value::String(s) => *s = interpolate_env(s, var)?,
+        serde_yaml::Value::Sequence(values) => values.iter_mut().try_for_each(|v| interpolate_yaml(v, var))?,
+        serde_yaml::Value::Mapping(values) => values.values_mut().try_for_each(|v| interpolate_yaml(v, var))?,
+        serde_yaml::Value::Tagged(tagged) => interpolate_yaml(&mut tagged.value, var)?,
+        serde_yaml::Value::Null | serde_yaml::Value::Bool(_) | serde_yaml::Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+/// Interpolates the string values of a parsed TOML config, the table keys are kept as is.
+fn interpolate_toml<F: Fn(&str) -> Option<String>>(value: &mut toml::Value, var: &F) -> Result<(), IngestError> {
+    match value {
+        toml::Value::String(s) => *s = interpolate_env(s, var)?,
+        toml::Value::Array(values) => values.iter_mut().try_for_each(|v| interpolate_toml(v, var))?,
+        toml::Value::Table(values) => values.iter_mut().try_for_each(|(_, v)| interpolate_toml(v, var))?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces the `${VAR}` and `${VAR:-default}` references of a value, fails on unset variables without default.
+fn interpolate_env<F: Fn(&str) -> Option<String>>(content: &str, var: &F) -> Result<String, IngestError> {
+    let mut result = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = rest[start..].find('}')
+            .ok_or_else(|| invalid("Unterminated ${ reference in config".to_string()))? + start;
+        let reference = &rest[start + 2..end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        let value = var(name)
+            .or_else(|| default.map(str::to_string))
+            .ok_or_else(|| invalid(format!("Environment variable {} is not set", name)))?;
+        result.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn invalid<E: ToString>(e: E) -> IngestError {
+    IngestError::InvalidConfig(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PartitionTransform;
+
+    use super::*;
+
+    const YAML_CONFIG: &str = r#"
+pipelines:
+  - name: persons
+    topics:
+      - topic: proto.ds.person
+        table_uri: ./data/persons
+        message_tables:
+          example.Contact: ./data/contacts
+        subject_strategy: record
+        key_format: protobuf
+        tombstones: soft-delete
+        upsert:
+          keys: [id]
+          event_time: updated_date
+        cdc:
+          op: operation
+        partition_by: [status, day=date(created_date)]
+        transform:
+          drop: [details.email]
+          rename:
+            details.physical.age: age
+          flatten: _
+        sql_select: ["*", "upper(op) AS op_upper"]
+    schema_registry:
+      urls: [http://registry-1:8081, http://registry-2:8081]
+      username: ingest
+      password: ${REGISTRY_PASSWORD}
+    kafka:
+      brokers: ${BROKERS:-localhost:9092}
+      group_id: persons-ingest
+      sasl:
+        mechanism: scram-sha-512
+        username: ingest
+        password: secret
+      properties:
+        session.timeout.ms: "45000"
+    flush:
+      allowed_latency_secs: 60
+    parquet:
+      compression: zstd
+      compression_level: 3
+      bloom_filter_columns: [id]
+      target_file_size: 67108864
+    checkpoint_interval: 10
+    optimize:
+      z_order: [id]
+    vacuum:
+      retention_secs: 86400
+      unsafe_retention: true
+    storage:
+      s3:
+        region: eu-west-1
+        endpoint: http://localhost:9000
+        allow_http: true
+        dynamodb_lock:
+          table_name: delta_lock
+      options:
+        AWS_S3_ADDRESSING_STYLE: path
+  - name: claims
+    topics:
+      - topic: ^proto\.ds\.claim.*
+        table_uri: ./data/{topic}
+        sql: SELECT * FROM messages WHERE status <> 'HEARTBEAT'
+    schema_registry:
+      urls: [http://registry-1:8081]
+"#;
+
+    #[test]
+    fn interpolate_environment_variables() {
+        let var = |name: &str| (name == "USER").then(|| "ingest".to_string());
+        assert_eq!(interpolate_env("user: ${USER}, port: ${PORT:-8081}", &var).unwrap(), "user: ingest, port: 8081");
+        assert!(interpolate_env("password: ${PASSWORD}", &var).is_err());
+        assert!(interpolate_env("password: ${PASSWORD", &var).is_err());
+    }
+
+    #[test]
+    fn interpolate_only_string_values() {
+        let var = |name: &str| match name {
+            "PASSWORD" => Some("p#ss: \"word\"\ncommit_retries: 0".to_string()),
+            "HOST" => Some("registry".to_string()),
+            _ => None,
+        };
+        let yaml = r#"
+pipelines:
+  - name: persons  # ${UNSET} in a comment
+    topics: [{topic: proto.ds.person, table_uri: ./data/persons}]
+    schema_registry:
+      urls: ["http://${HOST}:8081"]
+      username: ingest
+      password: ${PASSWORD}
+"#;
+        let config = IngestConfig::from_yaml(yaml, &var).unwrap();
+        assert_eq!(config.pipelines[0].commit_retries, default_retries());
+        assert_eq!(config.pipelines[0].schema_registry.urls, vec!["http://registry:8081".to_string()]);
+        assert_eq!(config.pipelines[0].schema_registry.password.as_deref(), Some("p#ss: \"word\"\ncommit_retries: 0"));
+
+        let toml = r#"
+            # ${UNSET} in a comment
+            [[pipelines]]
+            name = "persons"
+            topics = [{ topic = "proto.ds.person", table_uri = "./data/persons" }]
+            schema_registry = { urls = ["http://${HOST}:8081"], username = "ingest", password = "${PASSWORD}" }
+        "#;
+        let config = IngestConfig::from_toml(toml, &var).unwrap();
+        assert_eq!(config.pipelines[0].schema_registry.password.as_deref(), Some("p#ss: \"word\"\ncommit_retries: 0"));
+
+        assert!(IngestConfig::from_yaml("pipelines: [{name: ${UNSET}}]", &var).is_err());
+    }
+
+    #[test]
+    fn yaml_pipelines_to_ingest_options() {
+        let var = |name: &str| (name == "REGISTRY_PASSWORD").then(|| "secret".to_string());
+        let config = IngestConfig::from_yaml(YAML_CONFIG, &var).unwrap();
+        let pipelines = config.into_ingest_options().unwrap();
+        assert_eq!(pipelines.len(), 2);
+
+        let (name, opts) = &pipelines[0];
+        assert_eq!(name, "persons");
+        assert_eq!(opts.kafka_brokers, "localhost:9092");
+        assert_eq!(opts.allowed_latency, Duration::from_secs(60));
+        assert_eq!(opts.shutdown_timeout, Duration::from_secs(30));
+        assert_eq!(opts.max_messages_per_batch, 5000);
+        assert_eq!(opts.parquet.compression, ParquetCompression::Zstd);
+        assert_eq!(opts.parquet.compression_level, Some(3));
+        assert_eq!(opts.parquet.bloom_filter_columns, vec!["id".to_string()]);
+        assert_eq!(opts.target_file_size, Some(64 * 1024 * 1024));
+        assert_eq!(opts.checkpoint_interval, Some(10));
+        assert_eq!(opts.optimize, Some(OptimizeOptions {
+            z_order_columns: vec!["id".to_string()],
+            parquet: opts.parquet.clone(),
+            ..Default::default()
+        }));
+        assert_eq!(opts.vacuum, Some(VacuumOptions {
+            retention: Some(Duration::from_secs(86400)),
+            unsafe_retention: true,
+            ..Default::default()
+        }));
+        let storage_option = |key: &str| opts.storage_options.get(key).map(String::as_str);
+        assert_eq!(storage_option("AWS_REGION"), Some("eu-west-1"));
+        assert_eq!(storage_option("AWS_ENDPOINT_URL"), Some("http://localhost:9000"));
+        assert_eq!(storage_option("AWS_ALLOW_HTTP"), Some("true"));
+        assert_eq!(storage_option("AWS_S3_LOCKING_PROVIDER"), Some("dynamodb"));
+        assert_eq!(storage_option("DELTA_DYNAMO_TABLE_NAME"), Some("delta_lock"));
+        assert_eq!(storage_option("AWS_S3_ADDRESSING_STYLE"), Some("path"));
+        assert_eq!(storage_option("AWS_ACCESS_KEY_ID"), None);
+        assert_eq!(opts.kafka_security.auth, KafkaAuth::ScramSha512 { username: "ingest".to_string(), password: "secret".to_string() });
+        assert_eq!(opts.kafka_properties.get("session.timeout.ms").map(String::as_str), Some("45000"));
+        assert_eq!(opts.routes[0].subject_strategy, Some(SubjectStrategy::RecordName));
+        assert_eq!(opts.routes[0].key_format, KeyFormat::Protobuf);
+        assert_eq!(opts.routes[0].tombstones, TombstoneMode::SoftDelete);
+        assert_eq!(opts.routes[0].write_mode, WriteMode::Upsert {
+            keys: vec!["id".to_string()],
+            event_time: Some("updated_date".to_string()),
+        });
+        assert_eq!(opts.routes[0].partition_columns, vec![
+            "status".parse::<PartitionColumn>().unwrap(),
+            PartitionColumn { name: "day".to_string(), field: "created_date".to_string(), transform: PartitionTransform::Date },
+        ]);
+        assert_eq!(opts.routes[0].transforms, FieldTransforms {
+            drop: vec!["details.email".to_string()],
+            rename: vec![("details.physical.age".to_string(), "age".to_string())],
+            flatten: Some("_".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(opts.routes[0].sql_transform, Some(SqlTransform::new("SELECT *, upper(op) AS op_upper FROM messages")));
+        assert_eq!(opts.routes[0].cdc_envelope, Some(CdcEnvelope { op: "operation".to_string(), ..Default::default() }));
+        let MessageFormat::Protobuf(SchemaSource::SchemaRegistry(settings)) = &opts.input_format else {
+            panic!("Expected schema registry source");
+        };
+        assert_eq!(settings.auth, RegistryAuth::Basic { username: "ingest".to_string(), password: Some("secret".to_string()) });
+
+        let (_, opts) = &pipelines[1];
+        assert_eq!(opts.consumer_group_id, "kafka-delta-ingest");
+        assert_eq!(opts.optimize, None);
+        assert_eq!(opts.vacuum, None);
+        assert_eq!(opts.parquet, ParquetOptions::default());
+        assert!(opts.storage_options.is_empty());
+        assert!(opts.routes[0].is_pattern());
+        assert_eq!(opts.routes[0].sql_transform, Some(SqlTransform::new("SELECT * FROM messages WHERE status <> 'HEARTBEAT'")));
+    }
+
+    #[test]
+    fn toml_pipelines_to_ingest_options() {
+        let config: IngestConfig = toml::from_str(r#"
+            [[pipelines]]
+            name = "persons"
+            commit_retries = 2
+
+            [[pipelines.topics]]
+            topic = "proto.ds.person"
+            table_uri = "./data/persons"
+
+            [pipelines.schema_registry]
+            urls = ["http://registry:8081"]
+            token = "token"
+        "#).unwrap();
+        let pipelines = config.into_ingest_options().unwrap();
+        assert_eq!(pipelines[0].1.commit_retry.max_retries, 2);
+    }
+
+    #[test]
+    fn example_config_is_valid() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("pipelines.example.yaml");
+        let pipelines = IngestConfig::from_file(path).unwrap();
+        assert_eq!(pipelines[0].0, "claims");
+    }
+
+    #[test]
+    fn reject_invalid_pipelines() {
+        let parse = |yaml: &str| serde_yaml::from_str::<IngestConfig>(yaml).map_err(invalid).and_then(IngestConfig::into_ingest_options);
+
+        // Unknown fields
+        assert!(parse("pipelines: [{name: a, topics: [{topic: a, table: ./a}], schema_registry: {urls: [http://r]}}]").is_err());
+        // Invalid topic routes
+        assert!(parse("pipelines: [{name: a, topics: [], schema_registry: {urls: [http://r]}}]").is_err());
+        assert!(parse("pipelines: [{name: a, topics: [{topic: a}], schema_registry: {urls: [http://r]}}]").is_err());
+        // Invalid subject strategy
+        assert!(parse("pipelines: [{name: a, topics: [{topic: a, table_uri: ./a, subject_strategy: x}], schema_registry: {urls: [http://r]}}]").is_err());
+        // Missing registry urls
+        assert!(parse("pipelines: [{name: a, topics: [{topic: a, table_uri: ./a}], schema_registry: {urls: []}}]").is_err());
+        // Duplicate names
+        assert!(parse("pipelines: [{name: a, topics: [{topic: a, table_uri: ./a}], schema_registry: {urls: [http://r]}}, \
+                       {name: a, topics: [{topic: b, table_uri: ./b}], schema_registry: {urls: [http://r]}}]").is_err());
+        // Both a SQL query and expressions
+        assert!(parse("pipelines: [{name: a, topics: [{topic: a, table_uri: ./a, sql: SELECT * FROM messages, sql_select: [id]}], \
+                       schema_registry: {urls: [http://r]}}]").is_err());
+        // Invalid SQL transform
+        assert!(parse("pipelines: [{name: a, topics: [{topic: a, table_uri: ./a, sql: DELETE FROM messages}], \
+                       schema_registry: {urls: [http://r]}}]").is_err());
+        // Conflicting s3 locks
+        assert!(parse("pipelines: [{name: a, topics: [{topic: a, table_uri: ./a}], schema_registry: {urls: [http://r]}, \
+                       storage: {s3: {dynamodb_lock: {}, allow_unsafe_rename: true}}}]").is_err());
+        // Out of range vacuum retention
+        assert!(parse("pipelines: [{name: a, topics: [{topic: a, table_uri: ./a}], schema_registry: {urls: [http://r]}, \
+                       vacuum: {retention_secs: 18446744073709551615}}]").is_err());
+        assert!(parse("pipelines: []").is_err());
+    }
+}
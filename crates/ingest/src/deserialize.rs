@@ -1,33 +1,58 @@
-use schema_registry_converter::async_impl::easy_proto_decoder::EasyProtoDecoder;
-use schema_registry_converter::async_impl::schema_registry::SrSettings;
-use schema_registry_converter::error::SRCError;
-use crate::{IngestError, IngestOptions, SchemaSource};
+use std::sync::Arc;
+
+use rdkafka::Message;
+
+use schema_registry::{DecodedMessage, ProtoDecoder, SchemaRegistry, SchemaRegistryError};
+use serde_json::Value as JsonValue;
+
+use crate::{IngestError, IngestOptions, KeyFormat, SchemaSource};
 use crate::MessageFormat::Protobuf;
-use schema_registry_converter::async_impl::proto_decoder::DecodeResultWithContext;
 
 
 #[derive(Debug, thiserror::Error)]
 pub enum DeserializeError {
     #[error("Kafka message contained empty payload")]
     EmptyPayload,
-    #[error("Kafka message proto deserialization failed")]
-    ProtoDecodeError(#[from] SRCError),
-    #[error("Kafka message proto deserialization failed")]
-    ProtoDecodeContextError
+    #[error("Kafka message proto deserialization failed: {0}")]
+    ProtoDecodeError(#[from] SchemaRegistryError),
+    #[error("Kafka message key is not valid UTF-8")]
+    InvalidKey,
+}
+
+/// A decoded kafka message key.
+#[derive(Debug, Clone)]
+pub enum DecodedKey {
+    String(String),
+    Bytes(Vec<u8>),
+    Protobuf(DecodedMessage),
 }
 
+impl DecodedKey {
+    /// The json value of the key column, raw keys are hex encoded.
+    pub fn into_json(self) -> JsonValue {
+        match self {
+            DecodedKey::String(key) => JsonValue::String(key),
+            DecodedKey::Bytes(key) => JsonValue::String(key.iter().map(|b| format!("{:02x}", b)).collect()),
+            DecodedKey::Protobuf(key) => key.value,
+        }
+    }
+}
+
+/// Deserializes schema registry encoded proto messages.
+/// All the messages (of every topic) are decoded using the schemas of a single shared [`SchemaRegistry`] cache.
 pub struct ProtoDeserializer {
-    decoder: EasyProtoDecoder,
+    decoder: ProtoDecoder,
 }
 
 impl ProtoDeserializer {
 
-    pub fn build_from(opts: IngestOptions) -> Result<Self, IngestError> {
+    pub fn build_from(opts: &IngestOptions) -> Result<Self, IngestError> {
         match &opts.input_format {
-            Protobuf(SchemaSource::SchemaRegistry(url)) => {
-                let sr_settings = SrSettings::new(url.to_string());
+            Protobuf(SchemaSource::SchemaRegistry(settings)) => {
+                let registry = SchemaRegistry::new(settings.build()?)
+                    .with_retry_options(opts.schema_registry_retry.clone());
                 Ok(Self {
-                    decoder: EasyProtoDecoder::new(sr_settings),
+                    decoder: ProtoDecoder::new(Arc::new(registry)),
                 })
             },
             _ => {
@@ -37,10 +62,41 @@ impl ProtoDeserializer {
 
     }
 
-    pub async fn deserialize(&self, bytes: &[u8]) -> Result<DecodeResultWithContext, DeserializeError> {
-        self.decoder.decode_with_context(Some(bytes)).await?.ok_or(DeserializeError::ProtoDecodeContextError)
+    /// The schema registry (and cache) of the decoded message schemas.
+    #[inline]
+    pub fn registry(&self) -> &Arc<SchemaRegistry> {
+        self.decoder.registry()
     }
 
-}
+    pub async fn deserialize(&self, bytes: &[u8]) -> Result<DecodedMessage, DeserializeError> {
+        Ok(self.decoder.decode(bytes).await?)
+    }
 
+    pub async fn deserialize_message<M>(&self, message: &M) -> Result<DecodedMessage, DeserializeError>
+        where
+            M: Message + Send + Sync
+    {
+        let payload = message.payload().ok_or(DeserializeError::EmptyPayload)?;
+        self.deserialize(payload).await
+    }
 
+    /// Decodes the message key in the given format, `None` if the message has no key or keys are ignored.
+    pub async fn deserialize_key<M>(&self, message: &M, format: KeyFormat) -> Result<Option<DecodedKey>, DeserializeError>
+        where
+            M: Message + Send + Sync
+    {
+        let Some(key) = message.key() else {
+            return Ok(None);
+        };
+        let key = match format {
+            KeyFormat::None => return Ok(None),
+            KeyFormat::String => DecodedKey::String(
+                String::from_utf8(key.to_vec()).map_err(|_| DeserializeError::InvalidKey)?
+            ),
+            KeyFormat::Bytes => DecodedKey::Bytes(key.to_vec()),
+            KeyFormat::Protobuf => DecodedKey::Protobuf(self.deserialize(key).await?),
+        };
+        Ok(Some(key))
+    }
+
+}
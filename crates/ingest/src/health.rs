@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use deltalake::DeltaTableError;
+use schema_registry::{SchemaRegistry, SubjectStrategy};
+use serde_json::{json, Value as JsonValue};
+use tracing::{debug, warn};
+
+use crate::{TOPIC_PLACEHOLDER, TopicRoute};
+use crate::storage::open_table;
+
+/// How often the readiness probe is retried until the schemas and tables are reachable.
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The health of the running pipelines, served by the `/healthz` and `/readyz` endpoints.
+pub(crate) fn health() -> &'static Health {
+    static HEALTH: OnceLock<Health> = OnceLock::new();
+    HEALTH.get_or_init(Health::default)
+}
+
+#[derive(Default)]
+pub(crate) struct Health {
+    pipelines: Mutex<Vec<Arc<PipelineHealth>>>,
+}
+
+impl Health {
+    pub fn register(&self, pipeline: Arc<PipelineHealth>) {
+        self.pipelines.lock().unwrap().push(pipeline);
+    }
+
+    pub fn unregister(&self, pipeline: &Arc<PipelineHealth>) {
+        self.pipelines.lock().unwrap().retain(|p| !Arc::ptr_eq(p, pipeline));
+    }
+
+    /// Ready if there are running pipelines and all of them are ready.
+    pub fn readiness(&self) -> (bool, JsonValue) {
+        let pipelines = self.pipelines.lock().unwrap();
+        let ready = !pipelines.is_empty() && pipelines.iter().all(|p| p.is_ready());
+        (ready, json!({ "ready": ready, "pipelines": pipelines.iter().map(|p| p.status()).collect::<Vec<_>>() }))
+    }
+
+    /// Live unless a pipeline has stalled.
+    pub fn liveness(&self) -> (bool, JsonValue) {
+        let pipelines = self.pipelines.lock().unwrap();
+        let live = pipelines.iter().all(|p| p.is_live());
+        (live, json!({ "live": live, "pipelines": pipelines.iter().map(|p| p.status()).collect::<Vec<_>>() }))
+    }
+}
+
+/// The health state of a running pipeline, updated by the run loop, the consumer context and the readiness probe.
+pub(crate) struct PipelineHealth {
+    name: String,
+    /// The pipeline is not live if no message is processed within this window while there is consumer lag.
+    stall_timeout: Duration,
+    started: Instant,
+    subscribed: AtomicBool,
+    assigned: AtomicBool,
+    schemas_resolved: AtomicBool,
+    tables_reachable: AtomicBool,
+    /// Milliseconds since `started` of the last processed message.
+    last_progress: AtomicU64,
+    /// Total consumer lag of the assigned partitions, -1 if unknown.
+    lag: AtomicI64,
+}
+
+impl PipelineHealth {
+    pub fn new(name: String, stall_timeout: Duration) -> Self {
+        Self {
+            name,
+            stall_timeout,
+            started: Instant::now(),
+            subscribed: AtomicBool::new(false),
+            assigned: AtomicBool::new(false),
+            schemas_resolved: AtomicBool::new(false),
+            tables_reachable: AtomicBool::new(false),
+            last_progress: AtomicU64::new(0),
+            lag: AtomicI64::new(-1),
+        }
+    }
+
+    pub fn set_subscribed(&self, subscribed: bool) {
+        self.subscribed.store(subscribed, Ordering::Relaxed);
+    }
+
+    pub fn set_assigned(&self, assigned: bool) {
+        self.assigned.store(assigned, Ordering::Relaxed);
+    }
+
+    pub fn set_lag(&self, lag: i64) {
+        self.lag.store(lag, Ordering::Relaxed);
+    }
+
+    /// Marks a processed message, which also proves its schema has been resolved.
+    pub fn progress(&self) {
+        self.last_progress.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.subscribed.load(Ordering::Relaxed)
+            && self.assigned.load(Ordering::Relaxed)
+            && self.schemas_resolved.load(Ordering::Relaxed)
+            && self.tables_reachable.load(Ordering::Relaxed)
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.lag.load(Ordering::Relaxed) <= 0 || self.since_progress() <= self.stall_timeout
+    }
+
+    fn since_progress(&self) -> Duration {
+        self.started.elapsed().saturating_sub(Duration::from_millis(self.last_progress.load(Ordering::Relaxed)))
+    }
+
+    fn status(&self) -> JsonValue {
+        json!({
+            "name": self.name,
+            "subscribed": self.subscribed.load(Ordering::Relaxed),
+            "assigned": self.assigned.load(Ordering::Relaxed),
+            "schemas_resolved": self.schemas_resolved.load(Ordering::Relaxed),
+            "tables_reachable": self.tables_reachable.load(Ordering::Relaxed),
+            "lag": self.lag.load(Ordering::Relaxed),
+            "seconds_since_progress": self.since_progress().as_secs(),
+        })
+    }
+}
+
+/// Resolves the latest schemas of the routed topics and opens their tables until all of them succeed.
+/// Pattern topics and table uris with the `{topic}` placeholder are only known once messages arrive.
+pub(crate) async fn probe_readiness(
+    health: Arc<PipelineHealth>,
+    registry: Arc<SchemaRegistry>,
+    routes: Vec<TopicRoute>,
+    storage_options: HashMap<String, String>,
+) {
+    loop {
+        if !health.schemas_resolved.load(Ordering::Relaxed) && probe_schemas(&registry, &routes).await {
+            health.schemas_resolved.store(true, Ordering::Relaxed);
+        }
+        if !health.tables_reachable.load(Ordering::Relaxed) && probe_tables(&routes, &storage_options).await {
+            health.tables_reachable.store(true, Ordering::Relaxed);
+        }
+        if health.schemas_resolved.load(Ordering::Relaxed) && health.tables_reachable.load(Ordering::Relaxed) {
+            debug!("Pipeline {} schemas and tables are reachable", health.name);
+            return;
+        }
+        tokio::time::sleep(PROBE_INTERVAL).await;
+    }
+}
+
+async fn probe_schemas(registry: &SchemaRegistry, routes: &[TopicRoute]) -> bool {
+    for route in routes.iter().filter(|r| !r.is_pattern()) {
+        let strategy = route.subject_strategy.unwrap_or_default();
+        let record_names: Vec<String> = match strategy {
+            SubjectStrategy::TopicName => vec![String::new()],
+            _ => route.message_tables.keys().cloned().collect(),
+        };
+        for record_name in record_names {
+            let subject = strategy.subject(&route.topic, &record_name, false);
+            if let Err(e) = registry.latest_schema_id(&subject).await {
+                warn!("Failed to resolve the schema of topic {}: {}", route.topic, e);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+async fn probe_tables(routes: &[TopicRoute], storage_options: &HashMap<String, String>) -> bool {
+    let table_uris: Vec<String> = routes.iter()
+        .flat_map(|r| r.table_uri.iter().chain(r.message_tables.values()))
+        .filter(|uri| !uri.contains(TOPIC_PLACEHOLDER))
+        .cloned()
+        .collect();
+    for table_uri in table_uris {
+        match open_table(&table_uri, storage_options).await {
+            // Missing tables are created with the first written messages
+            Ok(_) | Err(DeltaTableError::NotATable(_)) | Err(DeltaTableError::InvalidTableLocation(_)) => {}
+            Err(e) => {
+                warn!("Table {} is not reachable: {}", table_uri, e);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readiness_and_liveness() {
+        let health = PipelineHealth::new("persons".to_string(), Duration::ZERO);
+        assert!(!health.is_ready());
+        health.set_subscribed(true);
+        health.set_assigned(true);
+        health.schemas_resolved.store(true, Ordering::Relaxed);
+        health.tables_reachable.store(true, Ordering::Relaxed);
+        assert!(health.is_ready());
+
+        // Stalled only while lagging
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(health.is_live());
+        health.set_lag(10);
+        assert!(!health.is_live());
+        health.set_lag(0);
+        assert!(health.is_live());
+    }
+
+    #[tokio::test]
+    async fn probe_local_tables() {
+        let routes = vec![
+            TopicRoute::new("persons", "./target/missing-health-test-table"),
+            TopicRoute::new("^claims.*", "./data/{topic}"),
+        ];
+        assert!(probe_tables(&routes, &HashMap::new()).await);
+    }
+}
@@ -1,60 +1,1575 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+use std::time::Instant;
+
+use deltalake::arrow::datatypes::{DataType, Field as ArrowField, Schema as ArrowSchema};
+use deltalake::{DeltaConfigKey, DeltaTableError};
+use deltalake::parquet::file::properties::WriterProperties;
 use rdkafka::Message;
-use schema_registry_converter::async_impl::proto_decoder::DecodeResultWithContext;
-use tracing::{info, trace};
-use crate::{IngestError, IngestOptions};
-use crate::deserialize::{DeserializeError, ProtoDeserializer};
+use rdkafka::message::OwnedMessage;
+use schema_registry::{ProtoSchema, SchemaRegistry, SubjectStrategy};
+use deltalake::datafusion::common::Column;
+use deltalake::datafusion::prelude::{Expr, lit};
+use serde_json::{json, Value as JsonValue};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{debug, field, info, info_span, Instrument, Span, trace, warn};
+use crate::{CdcEnvelope, DataWriter, DataWriterError, DELETED_COLUMN, IngestError, IngestOptions, KAFKA_TIMESTAMP_FIELD, KEY_COLUMN, KeyFormat, PartitionColumn, PartitionTransform, TombstoneMode, TableOptions, TopicRoute, WriteMode};
+use crate::writer::app_transaction_versions;
+use crate::deserialize::{DecodedKey, ProtoDeserializer};
+use crate::metrics::metrics;
+use crate::partition::{DecodedRecord, PartitionWorkers};
+use crate::routing::TopicRouter;
+use crate::storage::open_table;
+
+/// Offsets per topic partition.
+pub type PartitionOffsets = HashMap<(String, i32), i64>;
+
+/// The values of the partition columns of a table partition, `None` for null values.
+pub type PartitionValues = Vec<Option<String>>;
+
+/// The buffered messages of a target delta table.
+struct TableState {
+    table_uri: String,
+    /// Created lazily from the schema of the first decoded message.
+    writer: Option<DataWriter>,
+    /// The write mode of the route of the first decoded message.
+    write_mode: WriteMode,
+    /// The partition columns of the table.
+    partition_columns: Vec<String>,
+    /// The partitions written since they were last taken by the table maintenance.
+    written_partitions: HashSet<PartitionValues>,
+    buffer: Vec<JsonValue>,
+    /// The offset of the first buffered message per topic partition.
+    pending_offsets: PartitionOffsets,
+    /// The offset of the last buffered message per topic partition.
+    last_offsets: PartitionOffsets,
+    /// The key column values (objects) of the buffered deletes, whose committed rows are deleted when flushing.
+    deleted_keys: Vec<JsonValue>,
+    /// The last offset per assigned topic partition written to the table, as recorded in the delta log.
+    /// Messages up to these offsets are already in the table and skipped.
+    written_offsets: PartitionOffsets,
+    /// When the oldest buffered message was processed.
+    pending_since: Option<Instant>,
+}
+
+impl TableState {
+    fn new(table_uri: String) -> Self {
+        Self {
+            table_uri,
+            writer: None,
+            write_mode: WriteMode::Append,
+            partition_columns: Vec::new(),
+            written_partitions: HashSet::new(),
+            buffer: Vec::new(),
+            deleted_keys: Vec::new(),
+            pending_offsets: HashMap::new(),
+            last_offsets: HashMap::new(),
+            written_offsets: HashMap::new(),
+            pending_since: None,
+        }
+    }
+
+    /// Drops the buffered rows with the key column values, the committed ones are deleted with the next flush.
+    fn delete(&mut self, key: JsonValue) {
+        if let JsonValue::Object(columns) = &key {
+            self.buffer.retain(|row| !columns.iter().all(|(column, value)| row.get(column) == Some(value)));
+        }
+        if !self.deleted_keys.contains(&key) {
+            self.deleted_keys.push(key);
+        }
+    }
+}
 
 pub struct IngestProcessor {
-    topic: String,
     opts: IngestOptions,
-    deserializer: ProtoDeserializer,
+    router: TopicRouter,
+    deserializer: Arc<ProtoDeserializer>,
+    /// Decode tasks of the assigned partitions.
+    workers: PartitionWorkers,
+    /// The records of the decode tasks, until taken by the run loop.
+    decoded_records: Option<UnboundedReceiver<DecodedRecord>>,
+    /// Target tables by uri, each with its own writer (and arrow schema) and buffer.
+    tables: HashMap<String, TableState>,
+    /// The offset of the last processed message per topic partition.
+    processed_offsets: PartitionOffsets,
+    /// The offsets returned by the last [`IngestProcessor::take_committable_offsets`] call.
+    committed_offsets: PartitionOffsets,
+    /// Number of skipped messages per message type without a target table.
+    unrouted_messages: HashMap<String, u64>,
+    /// The offsets to seek newly assigned partitions to, the first offset not yet written to all their tables.
+    seek_offsets: PartitionOffsets,
+    /// The schema registry cache stats last reported to the metrics.
+    reported_cache_stats: (u64, u64),
+    /// The parquet writer properties of the written data files.
+    writer_properties: WriterProperties,
 }
 
 impl IngestProcessor {
-    pub fn new(topic: String, opts: IngestOptions) -> Result<Self, IngestError> {
-        let deserializer = ProtoDeserializer::build_from(opts.clone())?;
+    pub fn new(opts: IngestOptions) -> Result<Self, IngestError> {
+        let router = TopicRouter::try_new(&opts.routes)?;
+        let writer_properties = opts.parquet.writer_properties()
+            .map_err(|e| IngestError::InvalidConfig(e.to_string()))?;
+        let deserializer = Arc::new(ProtoDeserializer::build_from(&opts)?);
+        let (workers, decoded_records) = PartitionWorkers::new(deserializer.clone());
         Ok(Self {
-            topic,
             opts,
+            router,
             deserializer,
+            workers,
+            decoded_records: Some(decoded_records),
+            tables: HashMap::new(),
+            processed_offsets: HashMap::new(),
+            committed_offsets: HashMap::new(),
+            unrouted_messages: HashMap::new(),
+            seek_offsets: HashMap::new(),
+            reported_cache_stats: (0, 0),
+            writer_properties,
         })
     }
 
-    pub async fn process_message<M>(&self, message: M) -> Result<(), IngestError>
-        where M: Message + Send + Sync
-    {
+    /// Checks the tables of the topic routes against the latest schemas of the schema registry before consuming,
+    /// planning their SQL transforms and checking their upsert and partition columns.
+    ///
+    /// The tables of topic patterns and the default tables of the topics whose message types are only known from
+    /// their messages are checked when their first message is received, like the tables whose schema can not be
+    /// fetched from the registry yet.
+    pub async fn check_routes(&mut self) -> Result<(), IngestError> {
+        for route in self.opts.routes.iter().filter(|r| !r.is_pattern()) {
+            let topic = route.topic.as_str();
+            let mut messages: Vec<Option<&str>> = route.message_tables.keys().map(|name| Some(name.as_str())).collect();
+            if route.message_tables.is_empty() && route.subject_strategy.unwrap_or_default() == SubjectStrategy::TopicName {
+                messages.push(None);
+            }
+            for full_name in messages {
+                let Some(table_uri) = route.table_uri_of(topic, full_name.unwrap_or_default()) else {
+                    continue;
+                };
+                let arrow_schema = match table_schema(&self.deserializer, topic, full_name, None, None, route).await {
+                    Ok(arrow_schema) => arrow_schema,
+                    Err(IngestError::Schema { source }) => {
+                        warn!("Failed to check table {} of topic {} against the schema registry: {}", table_uri, topic, source);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                check_table_columns(topic, &table_uri, &arrow_schema, route).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Topic names and patterns to subscribe to.
+    pub fn subscription(&self) -> Vec<&str> {
+        self.router.subscription()
+    }
+
+    /// Number of skipped messages per message type without a target table.
+    pub fn unrouted_messages(&self) -> &HashMap<String, u64> {
+        &self.unrouted_messages
+    }
+
+    /// The channel of the decoded records of the dispatched messages, to be passed to
+    /// [`IngestProcessor::process_decoded`] in the received order. Can only be taken once.
+    pub fn take_decoded_records(&mut self) -> Option<UnboundedReceiver<DecodedRecord>> {
+        self.decoded_records.take()
+    }
+
+    /// Number of dispatched messages whose decoded records are not processed yet.
+    #[inline]
+    pub fn in_flight(&self) -> usize {
+        self.workers.in_flight()
+    }
+
+    /// Sends the message to the decode task of its partition.
+    pub fn dispatch(&mut self, message: OwnedMessage) {
+        let key_format = self.router.route_of(message.topic()).map_or(KeyFormat::None, |route| route.key_format);
+        self.workers.dispatch(message, key_format);
+    }
+
+    /// Buffers the decoded message to the buffer of its target table.
+    /// Records of partitions revoked since the message was dispatched are discarded.
+    pub async fn process_decoded(&mut self, record: DecodedRecord) -> Result<(), IngestError> {
+        if !self.workers.receive(&record) {
+            trace!("Discarding message of revoked partition {}/{}", record.message.topic(), record.message.partition());
+            return Ok(());
+        }
+        let span = info_span!("message",
+            topic = record.message.topic(),
+            partition = record.message.partition(),
+            offset = record.message.offset(),
+            schema_id = field::Empty,
+            table = field::Empty,
+        );
+        self.buffer_record(record).instrument(span).await
+    }
+
+    async fn buffer_record(&mut self, record: DecodedRecord) -> Result<(), IngestError> {
+        let DecodedRecord { message, decoded, key, .. } = record;
+        let topic = message.topic();
         let partition = message.partition();
         let offset = message.offset();
-        trace!("Received message from partition {} at offset {}", partition, offset);
+        trace!("Received message");
+        metrics().messages_consumed.with_label_values(&[topic]).inc();
+        self.processed_offsets.insert((topic.to_string(), partition), offset);
 
+        let Some(route) = self.router.route_of(topic) else {
+            warn!("No route for topic {}, skipping message", topic);
+            return Ok(());
+        };
 
-        match self.deserialize_message(&message).await {
-            Ok(value) => {
+        let key = match key {
+            Ok(key) => key,
+            Err(e) => {
+                info!("Failed to deserialize message key: {:?}", e);
+                metrics().messages_failed.with_label_values(&[topic]).inc();
+                return Ok(());
+            }
+        };
 
-                info!("Deserialized message: {:?}", value.full_name);
-                let full_name = value.full_name;
-                let info = value.context.context.get_message(&full_name).unwrap();
-                value.value.fields.iter().for_each(|v| {
-                    let field = info.get_field(v.number).unwrap();
+        // Tombstones are applied to the default table, the other messages to the table of their type
+        let is_tombstone = message.payload().is_none() && route.tombstones != TombstoneMode::Skip;
+        let (table_uri, decoded, deleted_row) = if is_tombstone {
+            if key.is_none() {
+                debug!("Tombstone without key, skipping message");
+                metrics().messages_skipped.with_label_values(&[topic]).inc();
+                return Ok(());
+            }
+            (route.table_uri_of(topic, "").expect("default table of tombstone routes"), None, None)
+        } else {
+            let mut decoded = match decoded {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    info!("Failed to deserialize message: {:?}", e);
+                    metrics().messages_failed.with_label_values(&[topic]).inc();
+                    return Ok(());
+                }
+            };
+            Span::current().record("schema_id", decoded.schema_id);
+            metrics().messages_decoded.with_label_values(&[topic]).inc();
 
-                    info!("FieldName: {:?} Value: {:?} ", field.name,  v.value);
+            let Some(table_uri) = route.table_uri_of(topic, &decoded.full_name) else {
+                debug!("No target table for {} messages of topic {}, skipping message", decoded.full_name, topic);
+                *self.unrouted_messages.entry(decoded.full_name).or_default() += 1;
+                metrics().messages_skipped.with_label_values(&[topic]).inc();
+                return Ok(());
+            };
 
-                });
+            // The rows of change data capture messages are unwrapped from their envelope
+            let deleted_row = match &route.cdc_envelope {
+                None => None,
+                Some(envelope) => match unwrap_change(std::mem::take(&mut decoded.value), envelope) {
+                    Ok(Change::Upsert(row)) => {
+                        decoded.value = row;
+                        None
+                    }
+                    Ok(Change::Delete(row)) => Some(row),
+                    Err(e) => {
+                        info!("Failed to unwrap change of message: {}", e);
+                        metrics().messages_failed.with_label_values(&[topic]).inc();
+                        return Ok(());
+                    }
+                },
+            };
+            route.transforms.apply(&mut decoded.value);
+            let deleted_row = deleted_row.map(|mut row| {
+                route.transforms.apply(&mut row);
+                row
+            });
+            (table_uri, Some(decoded), deleted_row)
+        };
+        Span::current().record("table", table_uri.as_str());
+
+        let tp = (topic.to_string(), partition);
+        let state = self.tables.entry(table_uri.clone())
+            .or_insert_with(|| TableState::new(table_uri));
+        if state.written_offsets.get(&tp).is_some_and(|written| offset <= *written) {
+            debug!("Message already written to table {}, skipping message", state.table_uri);
+            return Ok(());
+        }
+        if state.writer.is_none() {
+            let arrow_schema = table_schema(
+                &self.deserializer,
+                topic,
+                decoded.as_ref().map(|d| d.full_name.as_str()),
+                decoded.as_ref().map(|d| d.schema.clone()),
+                key.as_ref(),
+                route,
+            ).await?;
+            check_table_columns(topic, &state.table_uri, &arrow_schema, route).await?;
+            state.write_mode = route.write_mode.clone();
+            let mut table_options = TableOptions {
+                storage_options: self.opts.storage_options.clone(),
+                partition_columns: route.partition_columns.iter().map(|c| c.name.clone()).collect(),
+                sql_transform: route.sql_transform.clone(),
+                ..Default::default()
+            };
+            if let Some(retention) = self.opts.log_retention {
+                table_options.configuration.insert(
+                    DeltaConfigKey::LogRetentionDuration.as_ref().to_string(), format!("interval {} seconds", retention.as_secs()),
+                );
             }
-            Err(e) => {
-                info!("Failed to deserialize message: {:?}", e);
+            info!("Writing messages of topic {} to table {}", topic, state.table_uri);
+            let writer = DataWriter::try_new_with_options(
+                &state.table_uri, Arc::new(arrow_schema), table_options, self.opts.commit_retry.clone(),
+            ).await?.with_writer_properties(self.writer_properties.clone());
+            let writer = match self.opts.checkpoint_interval {
+                Some(interval) => writer.with_checkpoint_interval(interval),
+                None => writer,
+            };
+            let writer = match self.opts.target_file_size {
+                Some(size) => writer.with_target_file_size(size),
+                None => writer,
+            };
+            state.partition_columns = writer.table().metadata().map_err(DataWriterError::from)?.partition_columns.clone();
+            state.writer = Some(writer);
+        }
+
+        let key = key.map_or(JsonValue::Null, DecodedKey::into_json);
+        match (decoded, deleted_row, route.tombstones) {
+            (Some(_), Some(row), _) => {
+                let keys = match &route.write_mode {
+                    WriteMode::Upsert { keys, .. } => keys.as_slice(),
+                    WriteMode::Append => &[],
+                };
+                let columns = keys.iter()
+                    .map(|k| {
+                        let value = if k == KEY_COLUMN { key.clone() } else { row.get(k).cloned().unwrap_or_default() };
+                        (k.clone(), value)
+                    })
+                    .collect();
+                state.delete(JsonValue::Object(columns));
+            }
+            (Some(decoded), None, tombstones) => {
+                let mut value = decoded.value;
+                if let JsonValue::Object(fields) = &mut value {
+                    if route.key_format != KeyFormat::None {
+                        fields.insert(KEY_COLUMN.to_string(), key);
+                    }
+                    if tombstones == TombstoneMode::SoftDelete {
+                        fields.insert(DELETED_COLUMN.to_string(), JsonValue::Bool(false));
+                    }
+                }
+                derive_partition_values(&mut value, &route.partition_columns, message.timestamp().to_millis());
+                state.buffer.push(value);
+            }
+            (None, _, TombstoneMode::SoftDelete) => {
+                let mut value = json!({ KEY_COLUMN: key, DELETED_COLUMN: true });
+                derive_partition_values(&mut value, &route.partition_columns, message.timestamp().to_millis());
+                state.buffer.push(value);
+            }
+            (None, _, _) => {
+                state.delete(json!({ KEY_COLUMN: key }));
             }
         }
+        metrics().buffered_messages.with_label_values(&[&state.table_uri]).set(state.buffer.len() as i64);
+        state.pending_offsets.entry(tp.clone()).or_insert(offset);
+        state.last_offsets.insert(tp, offset);
+        state.pending_since.get_or_insert_with(Instant::now);
         Ok(())
     }
 
-    pub async fn deserialize_message<M>(&self, message: &M) -> Result<DecodeResultWithContext, DeserializeError>
-        where
-            M: Message + Send + Sync
+    /// Flushes and commits the tables with full buffers or buffered messages older than the allowed latency
+    /// (or all the tables with buffered messages if `force` is set).
+    /// Returns the number of flushed tables.
+    pub async fn flush(&mut self, force: bool) -> Result<usize, IngestError> {
+        let max_messages = self.opts.max_messages_per_batch;
+        let allowed_latency = self.opts.allowed_latency;
+        self.flush_tables(|state, pending_since| {
+            force
+                || state.buffer.len() + state.deleted_keys.len() >= max_messages
+                || pending_since.elapsed() >= allowed_latency
+        }).await
+    }
+
+    /// Flushes and commits the tables with buffered messages that match the predicate.
+    async fn flush_tables<F>(&mut self, should_flush: F) -> Result<usize, IngestError>
+        where F: Fn(&TableState, Instant) -> bool
     {
-        let payload = message.payload().ok_or(DeserializeError::EmptyPayload)?;
-        let value = self.deserializer.deserialize(payload).await?;
+        let mut flushed = 0;
+        for state in self.tables.values_mut() {
+            let Some(pending_since) = state.pending_since else {
+                continue;
+            };
+            if !should_flush(state, pending_since) {
+                continue;
+            }
+
+            if let Some(writer) = state.writer.as_mut() {
+                let span = info_span!("flush", table = %state.table_uri, messages = state.buffer.len());
+                async {
+                    let timer = metrics().commit_duration.with_label_values(&[&state.table_uri]).start_timer();
+                    let version = writer.table().version();
+                    // The offsets of the other partitions are kept by the previous commits and the checkpoints
+                    let app_transactions: HashMap<String, i64> = state.last_offsets.iter()
+                        .map(|((topic, partition), offset)| (txn_app_id(&self.opts.consumer_group_id, topic, *partition), *offset))
+                        .collect();
+                    // The offsets are recorded by the last commit of the batch: the delete if there are no rows to
+                    // write, else the merge or write. A crash after the delete replays the batch from the offsets
+                    // of the previous one, deleting the same keys again before the rows are written.
+                    let mut recorded = false;
+                    if !state.deleted_keys.is_empty() {
+                        let delete_transactions = if state.buffer.is_empty() { app_transactions.clone() } else { HashMap::new() };
+                        if let Some(deleted) = writer.delete_with_transactions(key_predicate(&state.deleted_keys)?, &delete_transactions).await? {
+                            debug!("Deleted {} rows of {} keys", deleted, state.deleted_keys.len());
+                            recorded = !delete_transactions.is_empty();
+                        }
+                    }
+                    match &state.write_mode {
+                        WriteMode::Append => writer.write(&state.buffer).await?,
+                        WriteMode::Upsert { keys, event_time } => {
+                            let rows = latest_rows(&state.buffer, keys, event_time.as_deref());
+                            if let Some(metrics) = writer.merge_with_transactions(&rows, keys, event_time.as_deref(), &app_transactions).await? {
+                                debug!("Merged {} of {} messages, {} rows inserted and {} updated", rows.len(),
+                                    state.buffer.len(), metrics.num_target_rows_inserted, metrics.num_target_rows_updated);
+                                recorded = true;
+                            }
+                        }
+                    }
+                    // The appended rows and their offsets are committed at once, the offsets alone if nothing was
+                    // committed (e.g. all the rows were filtered out or the merge updated none)
+                    let app_transactions = if recorded { HashMap::new() } else { app_transactions };
+                    let stats = writer.flush_and_commit_transactions(app_transactions).await?;
+                    if writer.table().version() > version {
+                        timer.observe_duration();
+                        debug!("Flushed {} messages to table version {}", state.buffer.len(), writer.table().version());
+                        metrics().rows_written.with_label_values(&[&state.table_uri]).inc_by(state.buffer.len() as u64);
+                        if let Some(stats) = stats {
+                            metrics().bytes_written.with_label_values(&[&state.table_uri]).inc_by(stats.bytes as u64);
+                        }
+                    } else {
+                        timer.stop_and_discard();
+                    }
+                    Ok::<_, IngestError>(())
+                }.instrument(span).await?;
+            }
+            metrics().buffered_messages.with_label_values(&[&state.table_uri]).set(0);
+            // The single (empty) partition values of unpartitioned tables
+            let partitions = state.buffer.iter().map(|row| partition_values(row, &state.partition_columns));
+            state.written_partitions.extend(partitions);
+            state.buffer.clear();
+            state.deleted_keys.clear();
+            state.pending_offsets.clear();
+            state.written_offsets.extend(state.last_offsets.drain());
+            state.pending_since = None;
+            flushed += 1;
+        }
+
+        let (hits, misses) = self.deserializer.registry().cache_stats();
+        let (reported_hits, reported_misses) = self.reported_cache_stats;
+        metrics().registry_cache_hits.inc_by(hits - reported_hits);
+        metrics().registry_cache_misses.inc_by(misses - reported_misses);
+        self.reported_cache_stats = (hits, misses);
+        Ok(flushed)
+    }
+
+    /// The partitions written to each table since the last call, per table uri.
+    pub fn take_written_partitions(&mut self) -> HashMap<String, HashSet<PartitionValues>> {
+        self.tables.values_mut()
+            .filter(|state| state.writer.is_some())
+            .map(|state| (state.table_uri.clone(), std::mem::take(&mut state.written_partitions)))
+            .collect()
+    }
+
+    /// The uris of the tables written by the processor.
+    pub fn table_uris(&self) -> Vec<String> {
+        self.tables.values()
+            .filter(|state| state.writer.is_some())
+            .map(|state| state.table_uri.clone())
+            .collect()
+    }
+
+    /// The next offsets to consume per topic partition that are safe to commit to kafka.
+    /// That is the first offset still buffered in any table, or the offset after the last processed message
+    /// when all the messages of the partition are written. Only the offsets changed since the last call are returned.
+    pub fn take_committable_offsets(&mut self) -> PartitionOffsets {
+        let mut offsets = HashMap::new();
+        for (tp, processed) in &self.processed_offsets {
+            let next = self.tables.values()
+                .filter_map(|state| state.pending_offsets.get(tp))
+                .min()
+                .copied()
+                .unwrap_or(processed + 1);
+            if self.committed_offsets.get(tp) != Some(&next) {
+                offsets.insert(tp.clone(), next);
+            }
+        }
+        self.committed_offsets.extend(offsets.clone());
+        offsets
+    }
+
+    /// The schema registry client of the message deserializer.
+    pub fn registry(&self) -> &Arc<SchemaRegistry> {
+        self.deserializer.registry()
+    }
+
+    /// Loads the offsets written to the tables of newly assigned partitions from the delta log, reading the log
+    /// of each table once for all the partitions. A partition is only sought past its kafka committed offset when
+    /// every table of its route has a recorded offset, otherwise it continues from the committed offset and the
+    /// messages already written to some of the tables are skipped by them.
+    pub async fn assign_partitions(&mut self, partitions: &[(String, i32)]) -> Result<(), IngestError> {
+        let mut partition_tables: Vec<((String, i32), Vec<String>)> = Vec::new();
+        let mut table_partitions: HashMap<String, Vec<(String, i32)>> = HashMap::new();
+        for (topic, partition) in partitions {
+            let Some(route) = self.router.route_of(topic) else {
+                continue;
+            };
+            let tp = (topic.clone(), *partition);
+            let table_uris = route.table_uris_of(topic);
+            for table_uri in &table_uris {
+                table_partitions.entry(table_uri.clone()).or_default().push(tp.clone());
+            }
+            partition_tables.push((tp, table_uris));
+        }
+
+        for (table_uri, tps) in table_partitions {
+            let app_ids: HashSet<String> = tps.iter()
+                .map(|(topic, partition)| txn_app_id(&self.opts.consumer_group_id, topic, *partition))
+                .collect();
+            let state = self.tables.entry(table_uri.clone())
+                .or_insert_with(|| TableState::new(table_uri));
+            let written = match state.writer.as_mut() {
+                Some(writer) => writer.app_transaction_versions(&app_ids).await?,
+                None => match open_table(&state.table_uri, &self.opts.storage_options).await {
+                    Ok(table) => app_transaction_versions(&table, &app_ids).await.map_err(DataWriterError::from)?,
+                    // Not created yet
+                    Err(DeltaTableError::NotATable(_)) | Err(DeltaTableError::InvalidTableLocation(_)) => HashMap::new(),
+                    Err(e) => return Err(DataWriterError::from(e).into()),
+                },
+            };
+            for tp in tps {
+                match written.get(&txn_app_id(&self.opts.consumer_group_id, &tp.0, tp.1)) {
+                    Some(offset) => state.written_offsets.insert(tp, *offset),
+                    None => state.written_offsets.remove(&tp),
+                };
+            }
+        }
+
+        for (tp, table_uris) in partition_tables {
+            let written: Option<Vec<i64>> = table_uris.iter()
+                .map(|table_uri| self.tables[table_uri].written_offsets.get(&tp).copied())
+                .collect();
+            if let Some(offset) = written.and_then(|offsets| offsets.into_iter().min()) {
+                info!("Partition {}/{} assigned, written up to offset {} in the delta log", tp.0, tp.1, offset);
+                self.seek_offsets.insert(tp, offset + 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// The offset to seek a newly assigned partition to on its first message, if the delta log records all the
+    /// messages before it as written. Partitions are only sought forward, as the kafka committed offsets never
+    /// exceed the written ones. Returns `None` for the following messages of the partition.
+    pub fn seek_offset(&mut self, topic: &str, partition: i32, offset: i64) -> Option<i64> {
+        self.seek_offsets.remove(&(topic.to_string(), partition))
+            .filter(|next| *next > offset)
+    }
+
+    /// Flushes and commits the tables with buffered messages of the revoked partitions and forgets their state.
+    /// Returns the committable offsets, to commit to kafka before the partitions are released.
+    pub async fn revoke_partitions(&mut self, partitions: &[(String, i32)]) -> Result<PartitionOffsets, IngestError> {
+        self.workers.stop(partitions);
+        let revoked: HashSet<&(String, i32)> = partitions.iter().collect();
+        self.flush_tables(|state, _| state.pending_offsets.keys().any(|tp| revoked.contains(tp))).await?;
+        let offsets = self.take_committable_offsets();
+
+        self.processed_offsets.retain(|tp, _| !revoked.contains(tp));
+        self.committed_offsets.retain(|tp, _| !revoked.contains(tp));
+        self.seek_offsets.retain(|tp, _| !revoked.contains(tp));
+        for state in self.tables.values_mut() {
+            state.written_offsets.retain(|tp, _| !revoked.contains(tp));
+        }
+        Ok(offsets)
+    }
+}
+
+/// The delta log transaction application id recording the last written offset of a consumer group partition.
+fn txn_app_id(consumer_group_id: &str, topic: &str, partition: i32) -> String {
+    format!("{}-{}-{}", consumer_group_id, topic, partition)
+}
+
+/// The arrow schema of the table of the `full_name` messages, resolved from the latest schema of the message subject
+/// if the topic has a subject strategy or no message writer `schema` is given, or else from the writer schema.
+/// Without a message full name (tombstones), the first message of the latest topic value subject schema is used.
+/// The rows of change data capture routes are the `after` field of the messages, the route field transforms are
+/// applied to the rows.
+/// Includes the `key` column if the route decodes the message keys and the `_is_deleted` column for soft deletes.
+async fn table_schema(
+    deserializer: &ProtoDeserializer,
+    topic: &str,
+    full_name: Option<&str>,
+    schema: Option<Arc<ProtoSchema>>,
+    key: Option<&DecodedKey>,
+    route: &TopicRoute,
+) -> Result<ArrowSchema, IngestError> {
+    let (schema, full_name) = match (route.subject_strategy, schema, full_name) {
+        (None, Some(schema), Some(full_name)) => (schema, full_name.to_string()),
+        (strategy, _, Some(full_name)) => {
+            let subject = strategy.unwrap_or_default().subject(topic, full_name, false);
+            (deserializer.registry().latest_proto_schema_of(&subject).await?, full_name.to_string())
+        }
+        (_, _, None) => {
+            let subject = SubjectStrategy::TopicName.subject(topic, "", false);
+            let schema = deserializer.registry().latest_proto_schema_of(&subject).await?;
+            let full_name = schema.full_name().to_string();
+            (schema, full_name)
+        }
+    };
+    let value_schema = schema.message_to_arrow_schema(&full_name)?;
+    let value_schema = match &route.cdc_envelope {
+        None => value_schema,
+        Some(envelope) => match value_schema.field_with_name(&envelope.after).map(|f| f.data_type()) {
+            Ok(DataType::Struct(fields)) => ArrowSchema::new_with_metadata(fields.clone(), value_schema.metadata().clone()),
+            _ => return Err(IngestError::InvalidRoute(format!(
+                "The {} messages of topic {} have no {} message field", full_name, topic, envelope.after,
+            ))),
+        },
+    };
+    let value_schema = route.transforms.apply_to_schema(&value_schema).map_err(|e| IngestError::InvalidRoute(format!(
+        "Invalid field transforms of the {} messages of topic {}: {}", full_name, topic, e,
+    )))?;
+    let mut fields = value_schema.fields().to_vec();
+
+    let key_type = match route.key_format {
+        KeyFormat::None => None,
+        KeyFormat::String | KeyFormat::Bytes => Some(DataType::Utf8),
+        KeyFormat::Protobuf => {
+            let (schema, full_name) = match (route.subject_strategy, key) {
+                (None, Some(DecodedKey::Protobuf(key))) => (key.schema.clone(), key.full_name.clone()),
+                (Some(strategy), Some(DecodedKey::Protobuf(key))) => {
+                    let subject = strategy.subject(topic, &key.full_name, true);
+                    (deserializer.registry().latest_proto_schema_of(&subject).await?, key.full_name.clone())
+                }
+                // Without a decoded key, the record name is unknown and the topic key subject is used
+                _ => {
+                    let subject = SubjectStrategy::TopicName.subject(topic, "", true);
+                    let schema = deserializer.registry().latest_proto_schema_of(&subject).await?;
+                    let full_name = schema.full_name().to_string();
+                    (schema, full_name)
+                }
+            };
+            Some(DataType::Struct(schema.message_to_arrow_schema(&full_name)?.fields().clone()))
+        }
+    };
+    let deleted_type = (route.tombstones == TombstoneMode::SoftDelete).then_some(DataType::Boolean);
+
+    let mut columns = vec![(KEY_COLUMN, key_type), (DELETED_COLUMN, deleted_type)];
+    for column in route.partition_columns.iter().filter(|c| c.is_derived() && c.field != KAFKA_TIMESTAMP_FIELD) {
+        match value_schema.field_with_name(&column.field).map(|f| f.data_type()) {
+            Ok(data_type) if is_timestamp_type(data_type) => {}
+            Ok(data_type) => return Err(IngestError::InvalidRoute(format!(
+                "The {} field of partition column {} of topic {} is a {}, not a timestamp, epoch millis or RFC 3339 string",
+                column.field, column.name, topic, data_type,
+            ))),
+            Err(_) => return Err(IngestError::InvalidRoute(format!(
+                "The {} messages of topic {} have no {} field of partition column {}",
+                full_name, topic, column.field, column.name,
+            ))),
+        }
+    }
+    for column in route.partition_columns.iter().filter(|c| c.is_derived()) {
+        let data_type = match column.transform {
+            PartitionTransform::Date => DataType::Date32,
+            _ => DataType::Utf8,
+        };
+        columns.push((column.name.as_str(), Some(data_type)));
+    }
+
+    for (column, data_type) in columns {
+        let Some(data_type) = data_type else {
+            continue;
+        };
+        if value_schema.field_with_name(column).is_ok() {
+            return Err(IngestError::InvalidRoute(format!(
+                "The {} messages of topic {} have a {} field, conflicting with the column of the same name",
+                full_name, topic, column,
+            )));
+        }
+        fields.push(Arc::new(ArrowField::new(column, data_type, true)));
+    }
+    // The partition columns computed by a SQL transform are checked against its output
+    let missing_column = route.partition_columns.iter().find(|c| !fields.iter().any(|f| f.name() == &c.name));
+    if let (Some(column), None) = (missing_column, &route.sql_transform) {
+        return Err(IngestError::InvalidRoute(format!(
+            "The {} messages of topic {} have no {} field of partition column {}",
+            full_name, topic, column.field, column.name,
+        )));
+    }
+    Ok(ArrowSchema::new_with_metadata(fields, value_schema.metadata().clone()))
+}
+
+/// Checks that the table rows of the route, of the given schema, have the upsert and partition columns,
+/// after the SQL transform of the route if set.
+async fn check_table_columns(
+    topic: &str,
+    table_uri: &str,
+    arrow_schema: &ArrowSchema,
+    route: &TopicRoute,
+) -> Result<(), IngestError> {
+    let table_columns = match &route.sql_transform {
+        Some(transform) => transform.output_schema(Arc::new(arrow_schema.clone())).await.map_err(|e| {
+            IngestError::InvalidRoute(format!("Invalid SQL transform of topic {}: {}", topic, e))
+        })?,
+        None => arrow_schema.clone(),
+    };
+    if let WriteMode::Upsert { keys, event_time } = &route.write_mode {
+        if let Some(column) = keys.iter().chain(event_time).find(|c| table_columns.field_with_name(c).is_err()) {
+            return Err(IngestError::InvalidRoute(format!(
+                "The upsert column {} of topic {} is not a column of table {}", column, topic, table_uri,
+            )));
+        }
+    }
+    if let Some(column) = route.partition_columns.iter().find(|c| table_columns.field_with_name(&c.name).is_err()) {
+        return Err(IngestError::InvalidRoute(format!(
+            "The partition column {} of topic {} is not a column of table {}", column.name, topic, table_uri,
+        )));
+    }
+    // The rows of a key are matched by comparing each key column value, repeated fields are not comparable
+    let mut key_columns: Vec<&str> = match &route.write_mode {
+        WriteMode::Upsert { keys, .. } => keys.iter().map(String::as_str).collect(),
+        WriteMode::Append => vec![],
+    };
+    if route.tombstones == TombstoneMode::Delete {
+        key_columns.push(KEY_COLUMN);
+    }
+    let repeated = key_columns.into_iter()
+        .filter_map(|c| table_columns.field_with_name(c).ok())
+        .find(|f| !is_comparable(f.data_type()));
+    if let Some(field) = repeated {
+        return Err(IngestError::InvalidRoute(format!(
+            "The key column {} of topic {} has repeated fields, not comparable to match the rows of table {}",
+            field.name(), topic, table_uri,
+        )));
+    }
+    Ok(())
+}
+
+/// Returns true if the values of the type are compared field by field by [`value_predicate`].
+fn is_comparable(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::List(_) | DataType::LargeList(_) | DataType::FixedSizeList(_, _) | DataType::Map(_, _) => false,
+        DataType::Struct(fields) => fields.iter().all(|f| is_comparable(f.data_type())),
+        _ => true,
+    }
+}
+
+/// The partition values of a row, formatted like the partition values of the delta log.
+fn partition_values(row: &JsonValue, partition_columns: &[String]) -> PartitionValues {
+    partition_columns.iter()
+        .map(|column| match row.get(column) {
+            None | Some(JsonValue::Null) => None,
+            Some(JsonValue::String(s)) => Some(s.clone()),
+            Some(value) => Some(value.to_string()),
+        })
+        .collect()
+}
+
+/// Adds the values of the derived partition columns to the row, from its timestamp fields
+/// (see [`timestamp_millis`]) or the kafka message timestamp.
+fn derive_partition_values(row: &mut JsonValue, columns: &[PartitionColumn], kafka_timestamp: Option<i64>) {
+    let JsonValue::Object(fields) = row else {
+        return;
+    };
+    for column in columns.iter().filter(|c| c.is_derived()) {
+        let millis = match column.field.as_str() {
+            KAFKA_TIMESTAMP_FIELD => kafka_timestamp,
+            field => fields.get(field).and_then(timestamp_millis),
+        };
+        let value = millis.map_or(JsonValue::Null, |millis| {
+            let (year, month, day) = civil_date(millis.div_euclid(MILLIS_PER_DAY));
+            match column.transform {
+                PartitionTransform::Hour => {
+                    let hour = millis.rem_euclid(MILLIS_PER_DAY) / 3_600_000;
+                    JsonValue::from(format!("{:04}-{:02}-{:02}-{:02}", year, month, day, hour))
+                }
+                _ => JsonValue::from(format!("{:04}-{:02}-{:02}", year, month, day)),
+            }
+        });
+        fields.insert(column.name.clone(), value);
+    }
+}
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// The epoch millis of a timestamp field value: epoch millis (decoded `google.protobuf.Timestamp` and integer fields),
+/// a `google.protobuf.Timestamp` decoded as a `seconds` and `nanos` object (when a field is not set) or an RFC 3339 string.
+fn timestamp_millis(value: &JsonValue) -> Option<i64> {
+    match value {
+        JsonValue::Number(n) => n.as_i64(),
+        JsonValue::Object(fields) => {
+            let seconds = fields.get("seconds").and_then(JsonValue::as_i64).unwrap_or_default();
+            let nanos = fields.get("nanos").and_then(JsonValue::as_i64).unwrap_or_default();
+            Some(seconds * 1000 + nanos / 1_000_000)
+        }
+        JsonValue::String(s) => chrono::DateTime::parse_from_rfc3339(s).ok().map(|t| t.timestamp_millis()),
+        _ => None,
+    }
+}
+
+/// Returns true if the values of the type are read by [`timestamp_millis`].
+fn is_timestamp_type(data_type: &DataType) -> bool {
+    data_type.is_integer() || matches!(data_type, DataType::Timestamp(_, _) | DataType::Utf8 | DataType::LargeUtf8)
+}
+
+/// The (year, month, day) of the days since the unix epoch, in the proleptic gregorian calendar.
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The latest row per key of the buffered rows, in the buffered order. Rows of the same key are resolved
+/// to the last buffered one (the latest offset), unless it has an older event time.
+fn latest_rows(rows: &[JsonValue], keys: &[String], event_time: Option<&str>) -> Vec<JsonValue> {
+    let mut latest: HashMap<String, usize> = HashMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        let key = keys.iter().map(|k| row.get(k).unwrap_or(&JsonValue::Null)).collect::<Vec<_>>();
+        let key = serde_json::to_string(&key).expect("json values serialize");
+        match latest.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(i);
+            }
+            Entry::Occupied(mut entry) => {
+                let is_older = event_time.is_some_and(|column| {
+                    compare_values(row.get(column), rows[*entry.get()].get(column)) == Ordering::Less
+                });
+                if !is_older {
+                    entry.insert(i);
+                }
+            }
+        }
+    }
+    let mut latest: Vec<usize> = latest.into_values().collect();
+    latest.sort_unstable();
+    latest.into_iter().map(|i| rows[i].clone()).collect()
+}
+
+/// Orders numbers and strings (e.g. decoded timestamps), with nulls first.
+fn compare_values(a: Option<&JsonValue>, b: Option<&JsonValue>) -> Ordering {
+    match (a.unwrap_or(&JsonValue::Null), b.unwrap_or(&JsonValue::Null)) {
+        (JsonValue::Number(a), JsonValue::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+        },
+        (JsonValue::String(a), JsonValue::String(b)) => a.cmp(b),
+        (JsonValue::Null, JsonValue::Null) => Ordering::Equal,
+        (JsonValue::Null, _) => Ordering::Less,
+        (_, JsonValue::Null) => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
 
-        Ok(value)
+/// The operation of a change data capture message.
+enum Change {
+    /// Upserts the row.
+    Upsert(JsonValue),
+    /// Deletes the row of the upsert keys of the row.
+    Delete(JsonValue),
+}
+
+/// Unwraps the operation and row of a change data capture envelope, see [`CdcEnvelope`].
+fn unwrap_change(value: JsonValue, envelope: &CdcEnvelope) -> Result<Change, String> {
+    let JsonValue::Object(mut fields) = value else {
+        return Err("The message is not an envelope".to_string());
+    };
+    let op = fields.get(&envelope.op).and_then(JsonValue::as_str).unwrap_or_default().to_ascii_lowercase();
+    let (field, change): (&str, fn(JsonValue) -> Change) = match op.as_str() {
+        "c" | "r" | "u" | "create" | "read" | "update" => (&envelope.after, Change::Upsert),
+        "d" | "delete" => (&envelope.before, Change::Delete),
+        _ => return Err(format!("Unknown change operation {:?}", op)),
+    };
+    match fields.remove(field) {
+        Some(row @ JsonValue::Object(_)) => Ok(change(row)),
+        _ => Err(format!("No {} row in {:?} change", field, op)),
+    }
+}
+
+/// The predicate matching the rows of any of the keys (objects of the key column values),
+/// comparing each field of struct columns.
+fn key_predicate(keys: &[JsonValue]) -> Result<Expr, IngestError> {
+    let mut predicate = None;
+    for columns in keys.iter().filter_map(JsonValue::as_object) {
+        let mut key = None;
+        for (column, value) in columns {
+            let column = value_predicate(Expr::Column(Column::from_name(column)), value)?;
+            key = Some(key.map_or(column.clone(), |key: Expr| key.and(column)));
+        }
+        if let Some(key) = key {
+            predicate = Some(predicate.map_or(key.clone(), |predicate: Expr| predicate.or(key)));
+        }
+    }
+    Ok(predicate.unwrap_or(lit(false)))
+}
+
+/// The predicate comparing the expression to the value, an error for the repeated fields
+/// (rejected for the key columns by [`check_table_columns`]).
+fn value_predicate(expr: Expr, value: &JsonValue) -> Result<Expr, IngestError> {
+    Ok(match value {
+        JsonValue::Null => expr.is_null(),
+        JsonValue::Bool(b) => expr.eq(lit(*b)),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => expr.eq(lit(i)),
+            (None, Some(u)) => expr.eq(lit(u)),
+            _ => expr.eq(lit(n.as_f64().unwrap_or_default())),
+        },
+        JsonValue::String(s) => expr.eq(lit(s.as_str())),
+        JsonValue::Object(fields) => fields.iter()
+            .map(|(name, value)| value_predicate(expr.clone().field(name), value))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .reduce(Expr::and)
+            .unwrap_or(lit(true)),
+        JsonValue::Array(_) => return Err(IngestError::InvalidRoute(format!(
+            "The key field {} is repeated, not comparable to match the rows", expr,
+        ))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use deltalake::arrow::array::{Int32Array, Int64Array, StringArray};
+    use deltalake::datafusion::prelude::col;
+    use rdkafka::message::Timestamp;
+    use schema_registry::RegistrySettings;
+
+    use crate::test_utils::TestDir;
+    use crate::{FieldTransforms, MessageFormat, SchemaSource, SqlTransform};
+
+    use super::*;
+
+    const SCHEMA_ID: u32 = 1;
+
+    fn schema() -> Vec<String> {
+        vec![r#"
+        syntax = "proto3";
+        package example;
+
+        message Person {
+            int32 id = 1;
+        }
+
+        message Contact {
+            string email = 1;
+        }
+
+        message Heartbeat {
+            int64 beat = 1;
+        }
+
+        message PersonChange {
+            Person before = 1;
+            Person after = 2;
+            string op = 3;
+        }
+
+        message Tagged {
+            repeated string tags = 1;
+        }
+        "#.to_string()]
     }
-}
\ No newline at end of file
+
+    // Confluent payloads of schema 1 with the message index (zigzag encoded) followed by the message data
+    const PERSON: &[u8] = &[0, 0, 0, 0, 1, 0, 8, 1];
+    const CONTACT: &[u8] = &[0, 0, 0, 0, 1, 2, 2, 10, 3, b'a', b'@', b'b'];
+    const PERSON_2: &[u8] = &[0, 0, 0, 0, 1, 0, 8, 2];
+    const PERSON_3: &[u8] = &[0, 0, 0, 0, 1, 0, 8, 3];
+    const CREATE_1: &[u8] = &[0, 0, 0, 0, 1, 2, 6, 0x12, 2, 8, 1, 0x1a, 1, b'c'];
+    const CREATE_3: &[u8] = &[0, 0, 0, 0, 1, 2, 6, 0x12, 2, 8, 3, 0x1a, 1, b'r'];
+    const UPDATE_2: &[u8] = &[0, 0, 0, 0, 1, 2, 6, 0x0a, 2, 8, 1, 0x12, 2, 8, 2, 0x1a, 1, b'u'];
+    const DELETE_3: &[u8] = &[0, 0, 0, 0, 1, 2, 6, 0x0a, 2, 8, 3, 0x1a, 1, b'd'];
+    const TRUNCATE: &[u8] = &[0, 0, 0, 0, 1, 2, 6, 0x1a, 1, b't'];
+    const HEARTBEAT: &[u8] = &[0, 0, 0, 0, 1, 2, 4, 8, 1];
+
+    fn message(offset: i64, payload: &[u8]) -> OwnedMessage {
+        OwnedMessage::new(Some(payload.to_vec()), None, "persons".to_string(), Timestamp::NotAvailable, 0, offset, None)
+    }
+
+    fn keyed_message(offset: i64, key: &[u8], payload: &[u8]) -> OwnedMessage {
+        OwnedMessage::new(Some(payload.to_vec()), Some(key.to_vec()), "persons".to_string(), Timestamp::NotAvailable, 0, offset, None)
+    }
+
+    fn tombstone(offset: i64, key: &[u8]) -> OwnedMessage {
+        OwnedMessage::new(None, Some(key.to_vec()), "persons".to_string(), Timestamp::NotAvailable, 0, offset, None)
+    }
+
+    /// The (key, id) rows of the table, ordered by key.
+    async fn table_rows(processor: &IngestProcessor, table_uri: &str) -> Vec<(String, i32)> {
+        let table = processor.tables[table_uri].writer.as_ref().unwrap().table().clone();
+        let (_, stream) = deltalake::DeltaOps(table).load().await.unwrap();
+        let batches = deltalake::datafusion::physical_plan::common::collect(stream).await.unwrap();
+        let mut rows: Vec<_> = batches.iter()
+            .flat_map(|batch| {
+                let keys = batch.column_by_name("key").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+                let ids = batch.column_by_name("id").unwrap().as_any().downcast_ref::<Int32Array>().unwrap();
+                keys.iter().zip(ids.iter()).map(|(key, id)| (key.unwrap().to_string(), id.unwrap())).collect::<Vec<_>>()
+            })
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    fn test_processor(route: TopicRoute, max_messages_per_batch: usize) -> IngestProcessor {
+        test_processor_with_registry(route, max_messages_per_batch, "http://localhost:1/")
+    }
+
+    fn test_processor_with_registry(route: TopicRoute, max_messages_per_batch: usize, url: &str) -> IngestProcessor {
+        let registry = RegistrySettings::new(url);
+        let processor = IngestProcessor::new(IngestOptions {
+            input_format: MessageFormat::Protobuf(SchemaSource::SchemaRegistry(registry)),
+            routes: vec![route],
+            max_messages_per_batch,
+            ..Default::default()
+        }).unwrap();
+        processor.deserializer.registry().insert_raw_schemas(SCHEMA_ID, schema()).unwrap();
+        processor
+    }
+
+    /// Url of a schema registry serving the test schema as the latest schema of every subject.
+    fn latest_schema_registry() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let body = json!({"subject": "persons-value", "version": 1, "id": SCHEMA_ID, "schema": schema()[0]}).to_string();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 4096];
+                let _ = std::io::Read::read(&mut stream, &mut request);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(), body,
+                );
+                let _ = std::io::Write::write_all(&mut stream, response.as_bytes());
+            }
+        });
+        url
+    }
+
+    impl IngestProcessor {
+        /// Dispatches the message and processes its decoded record.
+        async fn process_message(&mut self, message: OwnedMessage) -> Result<(), IngestError> {
+            self.dispatch(message);
+            let record = self.decoded_records.as_mut().unwrap().recv().await.unwrap();
+            self.process_decoded(record).await
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_messages_by_type() {
+        let dir = TestDir::new("routes-by-type");
+        let persons_uri = dir.table_uri("persons");
+        let contacts_uri = dir.table_uri("contacts");
+        let route = TopicRoute::by_message_type("persons")
+            .with_message_table("example.Person", &persons_uri)
+            .with_message_table("example.Contact", &contacts_uri);
+        let mut processor = test_processor(route, 100);
+
+        processor.process_message(message(0, PERSON)).await.unwrap();
+        processor.process_message(message(1, CONTACT)).await.unwrap();
+        processor.process_message(message(2, HEARTBEAT)).await.unwrap();
+        processor.process_message(message(3, PERSON)).await.unwrap();
+
+        assert_eq!(processor.unrouted_messages().get("example.Heartbeat"), Some(&1));
+        assert_eq!(processor.tables[&persons_uri].buffer.len(), 2);
+        assert_eq!(processor.tables[&contacts_uri].buffer.len(), 1);
+
+        assert_eq!(processor.flush(true).await.unwrap(), 2);
+        for uri in [&persons_uri, &contacts_uri] {
+            let table = processor.tables[uri].writer.as_ref().unwrap().table();
+            assert_eq!(table.version(), 1);
+            assert_eq!(table.get_schema().unwrap().fields().len(), 1);
+        }
+
+        assert_eq!(processor.take_committable_offsets(), HashMap::from([(("persons".to_string(), 0), 4)]));
+        assert!(processor.take_committable_offsets().is_empty());
+    }
+
+    #[tokio::test]
+    async fn checks_routes_against_registry_schemas() {
+        let dir = TestDir::new("check-routes");
+        let url = latest_schema_registry();
+        let persons = || TopicRoute::new("persons", dir.table_uri("persons"));
+
+        let mut processor = test_processor_with_registry(persons(), 100, &url);
+        processor.check_routes().await.unwrap();
+        let mut processor = test_processor_with_registry(
+            persons().with_write_mode(WriteMode::Upsert { keys: vec!["id".to_string()], event_time: None }),
+            100,
+            &url,
+        );
+        processor.check_routes().await.unwrap();
+
+        // Only the columns of the first message of the topic value schema
+        let route = persons().with_write_mode(WriteMode::Upsert { keys: vec!["email".to_string()], event_time: None });
+        let mut processor = test_processor_with_registry(route, 100, &url);
+        let err = processor.check_routes().await.unwrap_err();
+        assert!(err.to_string().contains("upsert column email"), "{}", err);
+
+        // The mapped message types are checked against their schemas, after the SQL transform
+        let route = TopicRoute::by_message_type("persons")
+            .with_message_table("example.Contact", dir.table_uri("contacts"))
+            .with_sql_transform(SqlTransform::new("SELECT email AS mail FROM messages"))
+            .with_write_mode(WriteMode::Upsert { keys: vec!["email".to_string()], event_time: None });
+        let mut processor = test_processor_with_registry(route, 100, &url);
+        let err = processor.check_routes().await.unwrap_err();
+        assert!(err.to_string().contains("upsert column email"), "{}", err);
+
+        let route = TopicRoute::new("persons", dir.table_uri("persons"))
+            .with_sql_transform(SqlTransform::new("SELECT missing FROM messages"));
+        let mut processor = test_processor_with_registry(route, 100, &url);
+        let err = processor.check_routes().await.unwrap_err();
+        assert!(err.to_string().contains("Invalid SQL transform of topic persons"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn rejects_repeated_key_columns() {
+        let dir = TestDir::new("repeated-keys");
+        let route = TopicRoute::by_message_type("persons")
+            .with_message_table("example.Tagged", dir.table_uri("tagged"))
+            .with_write_mode(WriteMode::Upsert { keys: vec!["tags".to_string()], event_time: None });
+        let mut processor = test_processor_with_registry(route, 100, &latest_schema_registry());
+        let err = processor.check_routes().await.unwrap_err();
+        assert!(err.to_string().contains("The key column tags of topic persons has repeated fields"), "{}", err);
+
+        assert!(key_predicate(&[json!({"id": 1, "tags": ["a"]})]).is_err());
+        assert_eq!(
+            key_predicate(&[json!({"id": 1}), json!({"id": 2})]).unwrap(),
+            col("id").eq(lit(1i64)).or(col("id").eq(lit(2i64))),
+        );
+    }
+
+    #[tokio::test]
+    async fn commits_offsets_of_written_messages() {
+        let dir = TestDir::new("partial-flush");
+        let route = TopicRoute::new("persons", dir.join("persons").to_str().unwrap())
+            .with_message_table("example.Contact", dir.join("contacts").to_str().unwrap());
+        let mut processor = test_processor(route, 2);
+
+        processor.process_message(message(10, PERSON)).await.unwrap();
+        processor.process_message(message(11, CONTACT)).await.unwrap();
+        processor.process_message(message(12, PERSON)).await.unwrap();
+
+        // Only the persons buffer is full, the contact at offset 11 is not written yet
+        assert_eq!(processor.flush(false).await.unwrap(), 1);
+        assert_eq!(processor.take_committable_offsets(), HashMap::from([(("persons".to_string(), 0), 11)]));
+
+        assert_eq!(processor.flush(true).await.unwrap(), 1);
+        assert_eq!(processor.take_committable_offsets(), HashMap::from([(("persons".to_string(), 0), 13)]));
+    }
+
+    #[tokio::test]
+    async fn resumes_assigned_partitions_from_delta_log() {
+        let dir = TestDir::new("rebalance");
+        let persons_uri = dir.table_uri("persons");
+        let contacts_uri = dir.table_uri("contacts");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_message_table("example.Contact", &contacts_uri);
+        let partition = ("persons".to_string(), 0);
+
+        let mut processor = test_processor(route.clone(), 100);
+        processor.process_message(message(10, PERSON)).await.unwrap();
+        processor.process_message(message(11, CONTACT)).await.unwrap();
+        processor.process_message(message(12, PERSON)).await.unwrap();
+        processor.flush(true).await.unwrap();
+
+        // A new owner of the partition continues after the last offset written to all the tables
+        let mut processor = test_processor(route, 100);
+        processor.assign_partitions(std::slice::from_ref(&partition)).await.unwrap();
+        assert_eq!(processor.seek_offset("persons", 0, 5), Some(12));
+        assert_eq!(processor.seek_offset("persons", 0, 12), None);
+        // Never backwards of the kafka committed offset
+        processor.assign_partitions(std::slice::from_ref(&partition)).await.unwrap();
+        assert_eq!(processor.seek_offset("persons", 0, 14), None);
+
+        // The person at offset 12 is already written
+        processor.process_message(message(12, PERSON)).await.unwrap();
+        processor.process_message(message(13, CONTACT)).await.unwrap();
+        assert!(processor.tables[&persons_uri].buffer.is_empty());
+        assert_eq!(processor.tables[&contacts_uri].buffer.len(), 1);
+
+        // Revoking flushes the buffered messages of the partition and forgets its offsets
+        let offsets = processor.revoke_partitions(std::slice::from_ref(&partition)).await.unwrap();
+        assert_eq!(offsets, HashMap::from([(partition, 14)]));
+        assert!(processor.tables[&contacts_uri].buffer.is_empty());
+        assert!(processor.take_committable_offsets().is_empty());
+    }
+
+    #[tokio::test]
+    async fn continues_from_committed_offset_without_offsets_of_all_tables() {
+        let dir = TestDir::new("rebalance-partial");
+        let persons_uri = dir.table_uri("persons");
+        let contacts_uri = dir.table_uri("contacts");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_message_table("example.Contact", &contacts_uri);
+        let partition = ("persons".to_string(), 0);
+
+        let mut processor = test_processor(route.clone(), 2);
+        // The contacts table only records the offsets of partition 1
+        let contact = OwnedMessage::new(Some(CONTACT.to_vec()), None, "persons".to_string(), Timestamp::NotAvailable, 1, 0, None);
+        processor.process_message(contact).await.unwrap();
+        processor.flush(true).await.unwrap();
+        // Only the persons are written, the contact at offset 9 is still buffered when the processor stops
+        processor.process_message(message(9, CONTACT)).await.unwrap();
+        processor.process_message(message(10, PERSON)).await.unwrap();
+        processor.process_message(message(11, PERSON)).await.unwrap();
+        assert_eq!(processor.flush(false).await.unwrap(), 1);
+        drop(processor);
+
+        // The partition continues from the kafka committed offset 9 instead of after the written persons
+        let mut processor = test_processor(route, 100);
+        processor.assign_partitions(std::slice::from_ref(&partition)).await.unwrap();
+        assert_eq!(processor.seek_offset("persons", 0, 9), None);
+        assert_eq!(processor.tables[&persons_uri].written_offsets.get(&partition), Some(&11));
+        assert_eq!(processor.tables[&contacts_uri].written_offsets.get(&partition), None);
+
+        processor.process_message(message(9, CONTACT)).await.unwrap();
+        processor.process_message(message(10, PERSON)).await.unwrap();
+        processor.process_message(message(11, PERSON)).await.unwrap();
+        assert!(processor.tables[&persons_uri].buffer.is_empty());
+        assert_eq!(processor.tables[&contacts_uri].buffer.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stores_decoded_keys() {
+        let dir = TestDir::new("keys");
+        let contacts_uri = dir.table_uri("contacts");
+        let route = TopicRoute::new("persons", &contacts_uri).with_key_format(KeyFormat::Protobuf);
+        let mut processor = test_processor(route, 100);
+
+        // Contacts keyed by person
+        processor.process_message(keyed_message(0, PERSON, CONTACT)).await.unwrap();
+        processor.process_message(message(1, CONTACT)).await.unwrap();
+        let state = &processor.tables[&contacts_uri];
+        assert_eq!(state.buffer[0]["key"], serde_json::json!({"id": 1}));
+        assert_eq!(state.buffer[1]["key"], JsonValue::Null);
+        processor.flush(true).await.unwrap();
+        let schema = processor.tables[&contacts_uri].writer.as_ref().unwrap().table().get_schema().unwrap().clone();
+        assert_eq!(schema.fields().len(), 2);
+        assert!(matches!(schema.field_with_name("key").unwrap().data_type(), deltalake::kernel::DataType::Struct(_)));
+
+        let route = TopicRoute::new("persons", dir.join("persons").to_str().unwrap()).with_key_format(KeyFormat::Bytes);
+        let mut processor = test_processor(route, 100);
+        processor.process_message(keyed_message(0, &[0, 255], PERSON)).await.unwrap();
+        assert_eq!(processor.tables.values().next().unwrap().buffer[0]["key"], "00ff");
+    }
+
+    #[tokio::test]
+    async fn soft_deletes_tombstones() {
+        let dir = TestDir::new("soft-delete");
+        let persons_uri = dir.table_uri("persons");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_key_format(KeyFormat::String)
+            .with_tombstones(TombstoneMode::SoftDelete);
+        let mut processor = test_processor(route, 100);
+
+        processor.process_message(keyed_message(0, b"a", PERSON)).await.unwrap();
+        processor.process_message(tombstone(1, b"a")).await.unwrap();
+        processor.process_message(OwnedMessage::new(None, None, "persons".to_string(), Timestamp::NotAvailable, 0, 2, None))
+            .await.unwrap();
+        let state = &processor.tables[&persons_uri];
+        assert_eq!(state.buffer, vec![
+            serde_json::json!({"id": 1, "key": "a", "_is_deleted": false}),
+            serde_json::json!({"key": "a", "_is_deleted": true}),
+        ]);
+        assert_eq!(processor.flush(true).await.unwrap(), 1);
+        let schema = processor.tables[&persons_uri].writer.as_ref().unwrap().table().get_schema().unwrap().clone();
+        assert_eq!(schema.fields().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn deletes_rows_of_tombstones() {
+        let dir = TestDir::new("hard-delete");
+        let persons_uri = dir.table_uri("persons");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_key_format(KeyFormat::String)
+            .with_tombstones(TombstoneMode::Delete);
+        let mut processor = test_processor(route, 100);
+
+        processor.process_message(keyed_message(0, b"a", PERSON)).await.unwrap();
+        processor.process_message(keyed_message(1, b"b", PERSON)).await.unwrap();
+        processor.flush(true).await.unwrap();
+
+        // The written row of key a is deleted, the buffered row of key c is dropped
+        processor.process_message(tombstone(2, b"a")).await.unwrap();
+        processor.process_message(keyed_message(3, b"c", PERSON)).await.unwrap();
+        processor.process_message(tombstone(4, b"c")).await.unwrap();
+        let state = &processor.tables[&persons_uri];
+        assert!(state.buffer.is_empty());
+        assert_eq!(state.deleted_keys, vec![json!({"key": "a"}), json!({"key": "c"})]);
+        processor.flush(true).await.unwrap();
+        assert_eq!(processor.take_committable_offsets(), HashMap::from([(("persons".to_string(), 0), 5)]));
+
+        let keys: Vec<_> = table_rows(&processor, &persons_uri).await.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn upserts_latest_rows_by_offset() {
+        let dir = TestDir::new("upsert");
+        let persons_uri = dir.table_uri("persons");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_key_format(KeyFormat::String)
+            .with_write_mode(WriteMode::Upsert { keys: vec!["key".to_string()], event_time: None });
+        let mut processor = test_processor(route, 100);
+
+        processor.process_message(keyed_message(0, b"a", PERSON)).await.unwrap();
+        processor.process_message(keyed_message(1, b"b", PERSON)).await.unwrap();
+        processor.process_message(keyed_message(2, b"a", PERSON_2)).await.unwrap();
+        processor.flush(true).await.unwrap();
+        assert_eq!(table_rows(&processor, &persons_uri).await, vec![("a".to_string(), 2), ("b".to_string(), 1)]);
+
+        processor.process_message(keyed_message(3, b"b", PERSON_3)).await.unwrap();
+        processor.process_message(keyed_message(4, b"c", PERSON)).await.unwrap();
+        processor.flush(true).await.unwrap();
+        assert_eq!(table_rows(&processor, &persons_uri).await, vec![
+            ("a".to_string(), 2), ("b".to_string(), 3), ("c".to_string(), 1),
+        ]);
+
+        // The offsets are recorded along with the merged rows
+        let app_ids = HashSet::from([txn_app_id(&processor.opts.consumer_group_id, "persons", 0)]);
+        let table = processor.tables[&persons_uri].writer.as_ref().unwrap().table();
+        assert_eq!(app_transaction_versions(table, &app_ids).await.unwrap().into_values().collect::<Vec<_>>(), vec![4]);
+    }
+
+    #[tokio::test]
+    async fn upserts_latest_rows_by_event_time() {
+        let dir = TestDir::new("upsert-event-time");
+        let persons_uri = dir.table_uri("persons");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_key_format(KeyFormat::String)
+            .with_write_mode(WriteMode::Upsert { keys: vec!["key".to_string()], event_time: Some("id".to_string()) });
+        let mut processor = test_processor(route, 100);
+
+        // The ids stand in for the event times
+        processor.process_message(keyed_message(0, b"a", PERSON_2)).await.unwrap();
+        processor.process_message(keyed_message(1, b"a", PERSON)).await.unwrap();
+        processor.flush(true).await.unwrap();
+        assert_eq!(table_rows(&processor, &persons_uri).await, vec![("a".to_string(), 2)]);
+
+        processor.process_message(keyed_message(2, b"a", PERSON)).await.unwrap();
+        processor.flush(true).await.unwrap();
+        assert_eq!(table_rows(&processor, &persons_uri).await, vec![("a".to_string(), 2)]);
+        processor.process_message(keyed_message(3, b"a", PERSON_3)).await.unwrap();
+        processor.flush(true).await.unwrap();
+        assert_eq!(table_rows(&processor, &persons_uri).await, vec![("a".to_string(), 3)]);
+    }
+
+    #[tokio::test]
+    async fn applies_change_data_capture_operations() {
+        let dir = TestDir::new("cdc");
+        let persons_uri = dir.table_uri("persons");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_key_format(KeyFormat::String)
+            .with_write_mode(WriteMode::Upsert { keys: vec!["key".to_string()], event_time: None })
+            .with_cdc_envelope(CdcEnvelope::default());
+        let mut processor = test_processor(route, 100);
+
+        processor.process_message(keyed_message(0, b"a", CREATE_1)).await.unwrap();
+        processor.process_message(keyed_message(1, b"b", CREATE_3)).await.unwrap();
+        processor.flush(true).await.unwrap();
+        assert_eq!(table_rows(&processor, &persons_uri).await, vec![("a".to_string(), 1), ("b".to_string(), 3)]);
+
+        processor.process_message(keyed_message(2, b"a", UPDATE_2)).await.unwrap();
+        processor.process_message(keyed_message(3, b"b", DELETE_3)).await.unwrap();
+        processor.process_message(keyed_message(4, b"c", TRUNCATE)).await.unwrap();
+        processor.flush(true).await.unwrap();
+        assert_eq!(table_rows(&processor, &persons_uri).await, vec![("a".to_string(), 2)]);
+
+        let table = processor.tables[&persons_uri].writer.as_ref().unwrap().table();
+        // The delete and the merge recording the offsets, without a separate commit of the offsets
+        assert_eq!(table.version(), 3);
+        let schema = table.get_schema().unwrap();
+        assert_eq!(schema.fields().iter().map(|f| f.name().as_str()).collect::<Vec<_>>(), vec!["id", "key"]);
+        // No change data files are written, the change data feed is not enabled
+        let configuration = &table.metadata().unwrap().configuration;
+        assert!(!configuration.contains_key("delta.enableChangeDataFeed"));
+    }
+
+    #[tokio::test]
+    async fn replays_batches_interrupted_after_the_delete() {
+        let dir = TestDir::new("cdc-replay");
+        let persons_uri = dir.table_uri("persons");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_key_format(KeyFormat::String)
+            .with_write_mode(WriteMode::Upsert { keys: vec!["key".to_string()], event_time: None })
+            .with_cdc_envelope(CdcEnvelope::default());
+        let partition = ("persons".to_string(), 0);
+
+        let mut processor = test_processor(route.clone(), 100);
+        processor.process_message(keyed_message(0, b"a", CREATE_1)).await.unwrap();
+        processor.process_message(keyed_message(1, b"b", CREATE_3)).await.unwrap();
+        processor.flush(true).await.unwrap();
+
+        // The processor stops after committing the delete of the next batch, without its offsets
+        processor.process_message(keyed_message(2, b"a", UPDATE_2)).await.unwrap();
+        processor.process_message(keyed_message(3, b"b", DELETE_3)).await.unwrap();
+        let state = processor.tables.get_mut(&persons_uri).unwrap();
+        let predicate = key_predicate(&state.deleted_keys).unwrap();
+        state.writer.as_mut().unwrap().delete(predicate).await.unwrap();
+        drop(processor);
+
+        // The batch is replayed from the offsets of the merge, deleting nothing more and merging the update once
+        let mut processor = test_processor(route, 100);
+        processor.assign_partitions(std::slice::from_ref(&partition)).await.unwrap();
+        assert_eq!(processor.seek_offset("persons", 0, 0), Some(2));
+        processor.process_message(keyed_message(2, b"a", UPDATE_2)).await.unwrap();
+        processor.process_message(keyed_message(3, b"b", DELETE_3)).await.unwrap();
+        processor.flush(true).await.unwrap();
+        assert_eq!(table_rows(&processor, &persons_uri).await, vec![("a".to_string(), 2)]);
+
+        // No change rows are committed twice: a single delete and merge of the replayed batch
+        let table = processor.tables[&persons_uri].writer.as_ref().unwrap().table();
+        let operations: Vec<String> = table.history(None).await.unwrap().into_iter()
+            .filter_map(|commit| commit.operation)
+            .collect();
+        assert_eq!(operations, vec!["MERGE", "DELETE", "MERGE", "CREATE TABLE"]);
+        let app_ids = HashSet::from([txn_app_id(&processor.opts.consumer_group_id, "persons", 0)]);
+        assert_eq!(app_transaction_versions(table, &app_ids).await.unwrap().into_values().collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn splits_partitioned_tables() {
+        let dir = TestDir::new("partitioned");
+        let persons_uri = dir.table_uri("persons");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_key_format(KeyFormat::String)
+            .with_partition_column("key".parse().unwrap())
+            .with_partition_column("date(_kafka_timestamp)".parse().unwrap())
+            .with_partition_column("hour(_kafka_timestamp)".parse().unwrap());
+        let mut processor = test_processor(route, 100);
+
+        let timestamped = |offset, key: &[u8], millis| OwnedMessage::new(
+            Some(PERSON.to_vec()), Some(key.to_vec()), "persons".to_string(), Timestamp::CreateTime(millis), 0, offset, None,
+        );
+        processor.process_message(timestamped(0, b"a", 1715276726099)).await.unwrap();
+        processor.process_message(timestamped(1, b"a", 1715276726099 + MILLIS_PER_DAY)).await.unwrap();
+        processor.process_message(timestamped(2, b"b", 1715276726099)).await.unwrap();
+        processor.process_message(timestamped(3, b"b", 1715276726099 + 1000)).await.unwrap();
+        assert_eq!(processor.tables[&persons_uri].buffer[0], json!({
+            "id": 1, "key": "a", "_kafka_timestamp_date": "2024-05-09", "_kafka_timestamp_hour": "2024-05-09-17",
+        }));
+        processor.flush(true).await.unwrap();
+
+        let table = processor.tables[&persons_uri].writer.as_ref().unwrap().table();
+        assert_eq!(table.metadata().unwrap().partition_columns, vec!["key", "_kafka_timestamp_date", "_kafka_timestamp_hour"]);
+        let mut files: Vec<_> = table.get_files_iter().unwrap()
+            .map(|path| path.parts().take(3).map(|part| part.as_ref().to_string()).collect::<Vec<_>>().join("/"))
+            .collect();
+        files.sort();
+        assert_eq!(files, vec![
+            "key=a/_kafka_timestamp_date=2024-05-09/_kafka_timestamp_hour=2024-05-09-17",
+            "key=a/_kafka_timestamp_date=2024-05-10/_kafka_timestamp_hour=2024-05-10-17",
+            "key=b/_kafka_timestamp_date=2024-05-09/_kafka_timestamp_hour=2024-05-09-17",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn transforms_message_fields() {
+        let dir = TestDir::new("transforms");
+        let changes_uri = dir.table_uri("changes");
+        let route = TopicRoute::new("persons", &changes_uri).with_transforms(FieldTransforms {
+            drop: vec!["before".to_string()],
+            rename: vec![("after.id".to_string(), "person_id".to_string())],
+            flatten: Some("_".to_string()),
+            ..Default::default()
+        });
+        let mut processor = test_processor(route, 100);
+
+        processor.process_message(message(0, CREATE_1)).await.unwrap();
+        assert_eq!(processor.tables[&changes_uri].buffer[0], json!({"after_person_id": 1, "op": "c"}));
+        processor.flush(true).await.unwrap();
+
+        let table = processor.tables[&changes_uri].writer.as_ref().unwrap().table();
+        let columns: Vec<_> = table.get_schema().unwrap().fields().iter().map(|f| f.name().clone()).collect();
+        assert_eq!(columns, vec!["after_person_id", "op"]);
+    }
+
+    #[tokio::test]
+    async fn applies_sql_transforms() {
+        let dir = TestDir::new("sql-transform");
+        let persons_uri = dir.table_uri("persons");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_sql_transform(SqlTransform::new("SELECT id, CAST(id AS BIGINT) * 10 AS score FROM messages WHERE id <> 2"));
+        let mut processor = test_processor(route, 100);
+
+        for (offset, payload) in [PERSON, PERSON_2, PERSON_3].into_iter().enumerate() {
+            processor.process_message(message(offset as i64, payload)).await.unwrap();
+        }
+        processor.flush(true).await.unwrap();
+
+        let table = processor.tables[&persons_uri].writer.as_ref().unwrap().table().clone();
+        let columns: Vec<_> = table.get_schema().unwrap().fields().iter().map(|f| f.name().clone()).collect();
+        assert_eq!(columns, vec!["id", "score"]);
+        let (_, stream) = deltalake::DeltaOps(table).load().await.unwrap();
+        let batches = deltalake::datafusion::physical_plan::common::collect(stream).await.unwrap();
+        let mut rows: Vec<_> = batches.iter()
+            .flat_map(|batch| {
+                let ids = batch.column_by_name("id").unwrap().as_any().downcast_ref::<Int32Array>().unwrap();
+                let scores = batch.column_by_name("score").unwrap().as_any().downcast_ref::<Int64Array>().unwrap();
+                ids.iter().zip(scores.iter()).map(|(id, score)| (id.unwrap(), score.unwrap())).collect::<Vec<_>>()
+            })
+            .collect();
+        rows.sort();
+        assert_eq!(rows, vec![(1, 10), (3, 30)]);
+        // The offsets of the filtered messages are committed too
+        assert_eq!(processor.take_committable_offsets(), HashMap::from([(("persons".to_string(), 0), 3)]));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_sql_transforms() {
+        let dir = TestDir::new("invalid-sql-transform");
+        let persons_uri = dir.table_uri("persons");
+        let route = TopicRoute::new("persons", &persons_uri)
+            .with_write_mode(WriteMode::Upsert { keys: vec!["id".to_string()], event_time: None })
+            .with_sql_transform(SqlTransform::new("SELECT id AS person_id FROM messages"));
+        let mut processor = test_processor(route, 100);
+
+        assert!(matches!(processor.process_message(message(0, PERSON)).await, Err(IngestError::InvalidRoute(_))));
+        assert!(!dir.join("persons").exists());
+    }
+
+    #[test]
+    fn derives_partition_values_of_timestamps() {
+        let columns = ["day=date(created)".parse().unwrap(), "hour(created)".parse().unwrap()];
+        let partitions = |created: JsonValue| {
+            let mut row = json!({ "created": created });
+            derive_partition_values(&mut row, &columns, None);
+            (row["day"].clone(), row["created_hour"].clone())
+        };
+        let expected = (json!("2024-05-09"), json!("2024-05-09-17"));
+        assert_eq!(partitions(json!(1_715_275_800_000i64)), expected);
+        assert_eq!(partitions(json!({ "seconds": 1_715_275_800i64 })), expected);
+        assert_eq!(partitions(json!({ "seconds": 1_715_275_800i64, "nanos": 5_000_000 })), expected);
+        assert_eq!(partitions(json!("2024-05-09T17:30:00Z")), expected);
+        assert_eq!(partitions(json!("2024-05-09T19:30:00+02:00")), expected);
+        assert_eq!(partitions(json!("yesterday")), (JsonValue::Null, JsonValue::Null));
+        assert_eq!(partitions(JsonValue::Null), (JsonValue::Null, JsonValue::Null));
+    }
+
+    #[tokio::test]
+    async fn rejects_derived_partitions_of_non_timestamp_fields() {
+        let dir = TestDir::new("non-timestamp-partition");
+        let route = TopicRoute::by_message_type("persons")
+            .with_message_table("example.PersonChange", dir.table_uri("changes"))
+            .with_partition_column("date(before)".parse().unwrap());
+        let mut processor = test_processor(route, 100);
+
+        let err = processor.process_message(message(0, CREATE_1)).await.unwrap_err();
+        assert!(err.to_string().contains("not a timestamp"), "{}", err);
+    }
+
+    #[test]
+    fn civil_dates_of_epoch_days() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(19852), (2024, 5, 9));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(11016), (2000, 2, 29));
+    }
+}
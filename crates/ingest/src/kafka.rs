@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use rdkafka::ClientConfig;
+
+use crate::{IngestError, IngestOptions};
+
+/// Prefix of the environment variables mapped to librdkafka properties (e.g. `KAFKA_SESSION_TIMEOUT_MS`).
+pub const KAFKA_ENV_PREFIX: &str = "KAFKA_";
+
+/// The `KAFKA_*` environment variables that are not librdkafka properties: the settings of the Kafka scripts
+/// and JVM of the container images.
+const KAFKA_ENV_EXCLUDED: &[&str] = &[
+    "KAFKA_HOME", "KAFKA_OPTS", "KAFKA_HEAP_OPTS", "KAFKA_JVM_PERFORMANCE_OPTS", "KAFKA_GC_LOG_OPTS",
+    "KAFKA_LOG4J_OPTS", "KAFKA_JMX_OPTS", "KAFKA_DEBUG", "KAFKA_VERSION", "KAFKA_PORT",
+];
+
+/// Prefixes of the `KAFKA_*` environment variables that are not librdkafka properties: the links of a
+/// Kubernetes service named `kafka` (e.g. `KAFKA_SERVICE_HOST` or `KAFKA_PORT_9092_TCP`).
+const KAFKA_ENV_EXCLUDED_PREFIXES: &[&str] = &["KAFKA_SERVICE_", "KAFKA_PORT_"];
+
+/// Kafka SASL authentication.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum KafkaAuth {
+    #[default]
+    None,
+    Plain {
+        username: String,
+        password: String,
+    },
+    ScramSha256 {
+        username: String,
+        password: String,
+    },
+    ScramSha512 {
+        username: String,
+        password: String,
+    },
+    /// OAUTHBEARER using the OIDC client credentials flow.
+    OAuthBearer {
+        client_id: String,
+        client_secret: String,
+        token_endpoint_url: String,
+        scope: Option<String>,
+    },
+}
+
+/// Kafka SSL (TLS) settings, the system trust store is used when no CA certificate is set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KafkaTls {
+    /// PEM encoded CA certificate of the brokers.
+    pub ca_location: Option<PathBuf>,
+    /// PEM encoded client certificate and private key for mutual TLS.
+    pub certificate_location: Option<PathBuf>,
+    pub key_location: Option<PathBuf>,
+    pub key_password: Option<String>,
+}
+
+/// Kafka connection security, the security protocol is derived from the configured authentication and TLS.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KafkaSecurity {
+    pub auth: KafkaAuth,
+    pub tls: Option<KafkaTls>,
+}
+
+impl KafkaSecurity {
+    fn apply(&self, config: &mut ClientConfig) {
+        let protocol = match (&self.auth, &self.tls) {
+            (KafkaAuth::None, None) => "plaintext",
+            (KafkaAuth::None, Some(_)) => "ssl",
+            (_, None) => "sasl_plaintext",
+            (_, Some(_)) => "sasl_ssl",
+        };
+        config.set("security.protocol", protocol);
+
+        match &self.auth {
+            KafkaAuth::None => {}
+            KafkaAuth::Plain { username, password } => set_sasl(config, "PLAIN", username, password),
+            KafkaAuth::ScramSha256 { username, password } => set_sasl(config, "SCRAM-SHA-256", username, password),
+            KafkaAuth::ScramSha512 { username, password } => set_sasl(config, "SCRAM-SHA-512", username, password),
+            KafkaAuth::OAuthBearer { client_id, client_secret, token_endpoint_url, scope } => {
+                config.set("sasl.mechanism", "OAUTHBEARER")
+                    .set("sasl.oauthbearer.method", "oidc")
+                    .set("sasl.oauthbearer.client.id", client_id)
+                    .set("sasl.oauthbearer.client.secret", client_secret)
+                    .set("sasl.oauthbearer.token.endpoint.url", token_endpoint_url);
+                if let Some(scope) = scope {
+                    config.set("sasl.oauthbearer.scope", scope);
+                }
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            set_path(config, "ssl.ca.location", &tls.ca_location);
+            set_path(config, "ssl.certificate.location", &tls.certificate_location);
+            set_path(config, "ssl.key.location", &tls.key_location);
+            if let Some(password) = &tls.key_password {
+                config.set("ssl.key.password", password);
+            }
+        }
+    }
+}
+
+fn set_sasl(config: &mut ClientConfig, mechanism: &str, username: &str, password: &str) {
+    config.set("sasl.mechanism", mechanism)
+        .set("sasl.username", username)
+        .set("sasl.password", password);
+}
+
+fn set_path(config: &mut ClientConfig, key: &str, path: &Option<PathBuf>) {
+    if let Some(path) = path {
+        config.set(key, path.to_string_lossy());
+    }
+}
+
+/// The consumer config of the ingest options.
+/// The extra `kafka_properties` override every other setting except `enable.auto.commit`,
+/// since the offsets are only committed after the messages are written to the delta tables.
+pub(crate) fn consumer_config(opts: &IngestOptions) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config.set("group.id", &opts.consumer_group_id)
+        .set("bootstrap.servers", &opts.kafka_brokers)
+        .set("enable.partition.eof", "false")
+        .set("session.timeout.ms", "6000")
+        .set("auto.offset.reset", "earliest")
+        // Statistics of the consumer lag metrics
+        .set("statistics.interval.ms", "15000");
+    opts.kafka_security.apply(&mut config);
+    for (key, value) in &opts.kafka_properties {
+        config.set(key, value);
+    }
+    config.set("enable.auto.commit", "false");
+    config
+}
+
+/// Reads librdkafka properties from a java style properties file (`key=value` lines, `#` or `!` comments).
+pub fn kafka_properties_from_file<P: AsRef<Path>>(path: P) -> Result<HashMap<String, String>, IngestError> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| IngestError::InvalidConfig(format!("Failed to read kafka properties {:?}: {}", path, e)))?;
+
+    let mut properties = HashMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| IngestError::InvalidConfig(format!("Invalid kafka property at {:?} line {}", path, number + 1)))?;
+        properties.insert(key.trim().to_string(), value.trim().to_string());
+    }
+    Ok(properties)
+}
+
+/// Reads librdkafka properties from the `KAFKA_*` environment variables, the remaining name is lower cased with
+/// underscores replaced by dots (e.g. `KAFKA_SASL_PASSWORD` sets `sasl.password`).
+/// The settings of the Kafka scripts and JVM (`KAFKA_HOME`, `KAFKA_OPTS` etc) and the Kubernetes service links are ignored.
+pub fn kafka_properties_from_env() -> HashMap<String, String> {
+    kafka_properties_from_vars(std::env::vars())
+}
+
+fn kafka_properties_from_vars<I: IntoIterator<Item=(String, String)>>(vars: I) -> HashMap<String, String> {
+    vars.into_iter()
+        .filter(|(key, _)| !KAFKA_ENV_EXCLUDED.contains(&key.as_str())
+            && !KAFKA_ENV_EXCLUDED_PREFIXES.iter().any(|prefix| key.starts_with(prefix)))
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix(KAFKA_ENV_PREFIX)?;
+            Some((name.to_lowercase().replace('_', "."), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn consumer_config_with_security_and_properties() {
+        let opts = IngestOptions {
+            kafka_security: KafkaSecurity {
+                auth: KafkaAuth::ScramSha512 { username: "user".to_string(), password: "secret".to_string() },
+                tls: Some(KafkaTls { ca_location: Some("/etc/kafka/ca.pem".into()), ..Default::default() }),
+            },
+            kafka_properties: HashMap::from([
+                ("session.timeout.ms".to_string(), "45000".to_string()),
+                ("enable.auto.commit".to_string(), "true".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let config = consumer_config(&opts);
+        assert_eq!(config.get("security.protocol"), Some("sasl_ssl"));
+        assert_eq!(config.get("sasl.mechanism"), Some("SCRAM-SHA-512"));
+        assert_eq!(config.get("sasl.username"), Some("user"));
+        assert_eq!(config.get("ssl.ca.location"), Some("/etc/kafka/ca.pem"));
+        assert_eq!(config.get("session.timeout.ms"), Some("45000"));
+        assert_eq!(config.get("enable.auto.commit"), Some("false"));
+        assert_eq!(config.get("auto.offset.reset"), Some("earliest"));
+    }
+
+    #[test]
+    fn properties_from_file_and_env() {
+        let dir = TestDir::new("kafka");
+        let path = dir.join("kafka.properties");
+        std::fs::write(&path, "# comment\nsasl.mechanism = PLAIN\n\nclient.id=ingest=1\n").unwrap();
+        let properties = kafka_properties_from_file(&path).unwrap();
+        assert_eq!(properties, HashMap::from([
+            ("sasl.mechanism".to_string(), "PLAIN".to_string()),
+            ("client.id".to_string(), "ingest=1".to_string()),
+        ]));
+
+        std::fs::write(&path, "invalid").unwrap();
+        assert!(kafka_properties_from_file(&path).is_err());
+
+        let properties = kafka_properties_from_vars([
+            ("KAFKA_SASL_PASSWORD".to_string(), "secret".to_string()),
+            ("KAFKA_HOME".to_string(), "/opt/kafka".to_string()),
+            ("KAFKA_OPTS".to_string(), "-Xmx1g".to_string()),
+            ("KAFKA_PORT".to_string(), "tcp://10.0.0.1:9092".to_string()),
+            ("KAFKA_PORT_9092_TCP_ADDR".to_string(), "10.0.0.1".to_string()),
+            ("KAFKA_SERVICE_HOST".to_string(), "10.0.0.1".to_string()),
+            ("RUST_LOG".to_string(), "info".to_string()),
+        ]);
+        assert_eq!(properties, HashMap::from([("sasl.password".to_string(), "secret".to_string())]));
+    }
+}
@@ -1,22 +1,100 @@
+mod admin;
+mod config;
 mod ingest;
+mod kafka;
+mod maintenance;
+mod metrics;
+mod parquet;
+mod partition;
 mod deserialize;
+mod health;
+mod routing;
+mod server;
+mod sql;
+mod storage;
+#[cfg(test)]
+mod test_utils;
+mod transactions;
+mod transform;
 mod writer;
 
-use std::sync::Arc;
-use rdkafka::{ClientConfig, ClientContext};
-use rdkafka::consumer::{Consumer, ConsumerContext, StreamConsumer};
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::{Duration, Instant};
+use rdkafka::{ClientContext, Message, Offset, Statistics, TopicPartitionList};
+use rdkafka::consumer::{CommitMode, Consumer, ConsumerContext, Rebalance, StreamConsumer};
+use rdkafka::error::KafkaError;
+use schema_registry::{RegistrySettings, RetryOptions, SchemaRegistryError, SubjectStrategy};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info};
-use url::Url;
-use crate::ingest::IngestProcessor;
+use tracing::{error, info, warn};
+use tracing::Instrument;
+use crate::health::PipelineHealth;
+use crate::ingest::{IngestProcessor, PartitionOffsets};
 
 // Re-exports
-pub use writer::{DataWriter, record_batch_from_json};
+pub use admin::{ConsumerOffset, create_table, consumer_offsets, OffsetReset, reset_consumer_offsets};
+pub use config::{AzureConfig, CdcConfig, DynamoDbLockConfig, FlushConfig, GcsConfig, IngestConfig, KafkaConfig, OptimizeConfig, ParquetConfig, PipelineConfig, RegistryConfig, S3Config, SaslConfig, SslConfig, StorageConfig, TopicConfig, TransformConfig, UpsertConfig, VacuumConfig};
+pub use maintenance::{optimize_table, OptimizeOptions, OptimizeStats, vacuum_table, VacuumOptions};
+pub use kafka::{KafkaAuth, KafkaSecurity, KafkaTls, kafka_properties_from_env, kafka_properties_from_file};
+pub use parquet::{ParquetCompression, ParquetOptions, ParquetStatistics};
+pub use server::serve_endpoints;
+pub use sql::SqlTransform;
+pub use transform::FieldTransforms;
+pub use writer::{CommitStats, DataWriter, DataWriterError, record_batch_from_json, TableOptions, to_delta_compatible_schema};
+
+/// Placeholder of the route table uris substituted with the (matched) topic name.
+const TOPIC_PLACEHOLDER: &str = "{topic}";
+
+/// The table column of the decoded message keys.
+const KEY_COLUMN: &str = "key";
+
+/// The table column flagging the soft deleted keys.
+const DELETED_COLUMN: &str = "_is_deleted";
+
+/// The message field of the kafka message timestamp, a source of derived partition columns.
+const KAFKA_TIMESTAMP_FIELD: &str = "_kafka_timestamp";
+
+/// How often the table buffers are checked for flushing when no messages arrive.
+const FLUSH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Timeout of seeking newly assigned partitions to the offsets stored in the delta log.
+const SEEK_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Debug, thiserror::Error)]
 pub enum IngestError {
     #[error("Ingest error")]
     IngestError,
+
+    #[error("Kafka error: {source}")]
+    Kafka {
+        #[from]
+        source: KafkaError,
+    },
+
+    #[error("Delta table write error: {source}")]
+    Write {
+        #[from]
+        source: DataWriterError,
+    },
+
+    #[error("Schema error: {source}")]
+    Schema {
+        #[from]
+        source: SchemaRegistryError,
+    },
+
+    #[error("Invalid topic route: {0}")]
+    InvalidRoute(String),
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Buffered messages were not written within the shutdown timeout of {0:?}")]
+    ShutdownTimeout(Duration),
 }
 
 
@@ -26,8 +104,43 @@ pub struct IngestOptions {
     pub kafka_brokers: String,
     /// The Kafka consumer group id to set to allow for multiple consumers per topic.
     pub consumer_group_id: String,
+    /// SASL authentication and SSL settings of the Kafka connection.
+    pub kafka_security: KafkaSecurity,
+    /// Extra librdkafka properties (e.g. `session.timeout.ms`), overriding the defaults and security settings.
+    pub kafka_properties: HashMap<String, String>,
     /// Input format
     pub input_format: MessageFormat,
+    /// The source topics (or topic patterns) and their target delta tables.
+    pub routes: Vec<TopicRoute>,
+    /// Maximum number of messages buffered per table before flushing.
+    pub max_messages_per_batch: usize,
+    /// Maximum time a message is buffered before its table is flushed.
+    pub allowed_latency: Duration,
+    /// Maximum number of consumed messages being decoded (by the per partition tasks) before they are buffered.
+    pub max_in_flight_messages: usize,
+    /// Retry options of the schema registry calls.
+    pub schema_registry_retry: RetryOptions,
+    /// Retry options of the delta table commits.
+    pub commit_retry: RetryOptions,
+    /// The pipeline fails the liveness check if no message is processed within this window while lagging.
+    pub stall_timeout: Duration,
+    /// Deadline to write the buffered messages, commit their offsets and close the consumer on shutdown.
+    pub shutdown_timeout: Duration,
+    /// Credentials and settings of the table object stores (deltalake storage options), e.g. `AWS_REGION`.
+    pub storage_options: HashMap<String, String>,
+    /// Parquet writer settings of the written data files.
+    pub parquet: ParquetOptions,
+    /// Splits the data of each flush into files of about this size in bytes, a file per partition if not set.
+    pub target_file_size: Option<usize>,
+    /// Creates a table checkpoint every given commits instead of the table `delta.checkpointInterval`.
+    pub checkpoint_interval: Option<i64>,
+    /// The `delta.logRetentionDuration` of the created tables (30 days by default), the log files older than it
+    /// are deleted after each checkpoint.
+    pub log_retention: Option<Duration>,
+    /// Periodically compacts the small files of the written tables, disabled if not set.
+    pub optimize: Option<OptimizeOptions>,
+    /// Periodically vacuums the written tables, disabled if not set.
+    pub vacuum: Option<VacuumOptions>,
 }
 
 impl Default for IngestOptions {
@@ -35,7 +148,24 @@ impl Default for IngestOptions {
         Self {
             kafka_brokers: "localhost:9092".to_string(),
             consumer_group_id: "kafka-delta-ingest".to_string(),
+            kafka_security: KafkaSecurity::default(),
+            kafka_properties: HashMap::new(),
             input_format: MessageFormat::Protobuf(SchemaSource::None),
+            routes: vec![],
+            max_messages_per_batch: 5000,
+            allowed_latency: Duration::from_secs(300),
+            max_in_flight_messages: 1000,
+            schema_registry_retry: RetryOptions::default(),
+            commit_retry: RetryOptions::default(),
+            stall_timeout: Duration::from_secs(300),
+            shutdown_timeout: Duration::from_secs(30),
+            storage_options: HashMap::new(),
+            parquet: ParquetOptions::default(),
+            target_file_size: None,
+            checkpoint_interval: None,
+            log_retention: None,
+            optimize: None,
+            vacuum: None,
         }
     }
 }
@@ -49,60 +179,548 @@ pub enum MessageFormat {
 #[derive(Clone, Debug)]
 pub enum SchemaSource {
     None,
-    SchemaRegistry(Url),
+    /// Confluent schema registry (urls, authorization and TLS settings).
+    SchemaRegistry(RegistrySettings),
 }
 
+/// How the kafka message keys are decoded and stored in the `key` column of the route tables.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyFormat {
+    /// The keys are ignored.
+    #[default]
+    None,
+    /// UTF-8 string keys, stored in a string column.
+    String,
+    /// Raw keys, stored hex encoded in a string column.
+    Bytes,
+    /// Schema registry encoded proto keys (e.g. of the `<topic>-key` subject), stored in a struct column.
+    Protobuf,
+}
 
-pub struct KafkaContext;
+impl FromStr for KeyFormat {
+    type Err = String;
 
-impl ClientContext for KafkaContext {}
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(KeyFormat::None),
+            "string" => Ok(KeyFormat::String),
+            "bytes" => Ok(KeyFormat::Bytes),
+            "protobuf" | "proto" => Ok(KeyFormat::Protobuf),
+            _ => Err(format!("Unknown key format {}, expected none, string, bytes or protobuf", s)),
+        }
+    }
+}
 
-impl ConsumerContext for KafkaContext {}
+/// How the tombstones (messages with a key and a null payload) of compacted topics are handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TombstoneMode {
+    /// Tombstones are counted as failed messages.
+    #[default]
+    Skip,
+    /// A row with the key and the `_is_deleted` flag set is appended, the flag is unset for the other rows.
+    SoftDelete,
+    /// The rows of the key are deleted from the table.
+    Delete,
+}
+
+impl FromStr for TombstoneMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(TombstoneMode::Skip),
+            "soft-delete" => Ok(TombstoneMode::SoftDelete),
+            "delete" => Ok(TombstoneMode::Delete),
+            _ => Err(format!("Unknown tombstone mode {}, expected skip, soft-delete or delete", s)),
+        }
+    }
+}
+
+/// How the buffered messages are written to the tables of a route.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    /// The messages are appended to the tables.
+    #[default]
+    Append,
+    /// Each flushed batch is merged into the tables on the `keys` columns, inserting new keys and updating
+    /// the rows of existing ones. Duplicate keys of a batch resolve to the latest message by offset, or by
+    /// the `event_time` column if set, in which case rows are only updated by messages with newer event times.
+    Upsert {
+        keys: Vec<String>,
+        event_time: Option<String>,
+    },
+}
+
+/// The field names of a change data capture envelope (e.g. Debezium) wrapping the rows of the messages.
+///
+/// The `op` field of the envelope selects the operation: `c`/`r` (create, snapshot read) and `u` (update)
+/// upsert the `after` row and `d` deletes the row of the upsert keys of the `before` row.
+/// Operations can also be enum names (e.g. `CREATE`, `UPDATE`, `DELETE`, `READ`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CdcEnvelope {
+    pub before: String,
+    pub after: String,
+    pub op: String,
+}
+
+impl Default for CdcEnvelope {
+    fn default() -> Self {
+        Self {
+            before: "before".to_string(),
+            after: "after".to_string(),
+            op: "op".to_string(),
+        }
+    }
+}
+
+/// How the values of a partition column are derived from a message field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionTransform {
+    /// The field is the partition column.
+    Identity,
+    /// The UTC date (`2024-05-09`) of a timestamp field, in a date column.
+    Date,
+    /// The UTC date and hour (`2024-05-09-17`) of a timestamp field, in a string column.
+    Hour,
+}
+
+/// A partition column of the route tables, parsed from a field (`status`) or a transform of a timestamp field
+/// (`date(created_date)`, `hour(_kafka_timestamp)`), where `_kafka_timestamp` is the kafka message timestamp.
+/// Derived columns are named `<field>_<transform>` (e.g. `created_date_date`) unless named as `day=date(created_date)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartitionColumn {
+    pub name: String,
+    pub field: String,
+    pub transform: PartitionTransform,
+}
+
+impl PartitionColumn {
+    /// Whether the column is added to the table rows, rather than being a field of the messages.
+    #[inline]
+    pub fn is_derived(&self) -> bool {
+        self.transform != PartitionTransform::Identity
+    }
+}
+
+impl FromStr for PartitionColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, expr) = match s.split_once('=') {
+            Some((name, expr)) => (Some(name.trim()), expr.trim()),
+            None => (None, s.trim()),
+        };
+        let (transform, field) = match expr.split_once('(').map(|(f, rest)| (f.trim(), rest.strip_suffix(')'))) {
+            None => (PartitionTransform::Identity, expr),
+            Some(("date", Some(field))) => (PartitionTransform::Date, field.trim()),
+            Some(("hour", Some(field))) => (PartitionTransform::Hour, field.trim()),
+            Some(_) => return Err(format!("Unknown partition column {}, expected a field, date(<field>) or hour(<field>)", s)),
+        };
+        if field.is_empty() || name.is_some_and(str::is_empty) {
+            return Err(format!("Invalid partition column {}", s));
+        }
+        let name = match (name, transform) {
+            (Some(name), PartitionTransform::Identity) if name != field => {
+                return Err(format!("Partition column {} can not rename a field", s));
+            }
+            (Some(name), _) => name.to_string(),
+            (None, PartitionTransform::Identity) => field.to_string(),
+            (None, PartitionTransform::Date) => format!("{}_date", field),
+            (None, PartitionTransform::Hour) => format!("{}_hour", field),
+        };
+        Ok(PartitionColumn { name, field: field.to_string(), transform })
+    }
+}
+
+/// Routes the messages of a topic to target delta tables.
+///
+/// Topics carrying multiple message types (e.g. using the `RecordNameStrategy`) can be routed to
+/// a separate table per message type through `message_tables`.
+#[derive(Clone, Debug)]
+pub struct TopicRoute {
+    /// The topic name or a regex topic pattern starting with `^` (e.g. `^proto\..*`).
+    pub topic: String,
+    /// The default target delta table uri of the topic messages whose type is not mapped in `message_tables`.
+    /// Such messages are skipped (and counted) if not set.
+    /// For topic patterns, `{topic}` is replaced with the matched topic name (e.g. `./data/{topic}`).
+    pub table_uri: Option<String>,
+    /// Target delta table uris per proto message full name (e.g. `example.Person`).
+    pub message_tables: HashMap<String, String>,
+    /// The subject naming strategy of the topic value schemas. When set, the table schemas are resolved from the
+    /// latest schema registered under the message subject, otherwise from the writer schema of the first message.
+    pub subject_strategy: Option<SubjectStrategy>,
+    /// How the message keys are decoded and stored in the `key` column of the tables, ignored by default.
+    pub key_format: KeyFormat,
+    /// How the tombstones (null payloads) of compacted topics are applied to the default table, skipped by default.
+    pub tombstones: TombstoneMode,
+    /// How the messages are written to the tables, appended by default.
+    pub write_mode: WriteMode,
+    /// Unwraps the rows and operations of change data capture messages, requires an upsert write mode.
+    /// The offsets of each batch are recorded by its merge (or delete) commit, so replayed batches add no change rows twice.
+    pub cdc_envelope: Option<CdcEnvelope>,
+    /// The partition columns of the created tables, existing tables keep their partitioning.
+    pub partition_columns: Vec<PartitionColumn>,
+    /// Transforms of the message fields (the unwrapped rows of change data capture messages) before they are written.
+    pub transforms: FieldTransforms,
+    /// SQL query of the table rows (with the key and derived partition columns) applied to each written batch.
+    /// The upsert keys must be selected unchanged, as the deletes match the key values of the messages.
+    pub sql_transform: Option<SqlTransform>,
+}
+
+impl TopicRoute {
+    pub fn new<T: Into<String>, U: Into<String>>(topic: T, table_uri: U) -> Self {
+        Self {
+            topic: topic.into(),
+            table_uri: Some(table_uri.into()),
+            message_tables: HashMap::new(),
+            subject_strategy: None,
+            key_format: KeyFormat::None,
+            tombstones: TombstoneMode::Skip,
+            write_mode: WriteMode::Append,
+            cdc_envelope: None,
+            partition_columns: Vec::new(),
+            transforms: FieldTransforms::default(),
+            sql_transform: None,
+        }
+    }
+
+    /// A route that only writes the message types mapped with [`TopicRoute::with_message_table`].
+    pub fn by_message_type<T: Into<String>>(topic: T) -> Self {
+        Self {
+            topic: topic.into(),
+            table_uri: None,
+            message_tables: HashMap::new(),
+            subject_strategy: None,
+            key_format: KeyFormat::None,
+            tombstones: TombstoneMode::Skip,
+            write_mode: WriteMode::Append,
+            cdc_envelope: None,
+            partition_columns: Vec::new(),
+            transforms: FieldTransforms::default(),
+            sql_transform: None,
+        }
+    }
+
+    /// Routes the messages of the given proto message type to a separate table.
+    pub fn with_message_table<N: Into<String>, U: Into<String>>(mut self, full_name: N, table_uri: U) -> Self {
+        self.message_tables.insert(full_name.into(), table_uri.into());
+        self
+    }
+
+    /// Resolves the table schemas from the latest schemas registered under the subjects of the given strategy.
+    pub fn with_subject_strategy(mut self, subject_strategy: SubjectStrategy) -> Self {
+        self.subject_strategy = Some(subject_strategy);
+        self
+    }
+
+    /// Decodes the message keys in the given format and stores them in the `key` column of the tables.
+    pub fn with_key_format(mut self, key_format: KeyFormat) -> Self {
+        self.key_format = key_format;
+        self
+    }
+
+    /// Applies the tombstones of the message keys to the default table, requires a key format.
+    pub fn with_tombstones(mut self, tombstones: TombstoneMode) -> Self {
+        self.tombstones = tombstones;
+        self
+    }
 
+    /// Merges the messages into the tables on the key columns, see [`WriteMode::Upsert`].
+    pub fn with_write_mode(mut self, write_mode: WriteMode) -> Self {
+        self.write_mode = write_mode;
+        self
+    }
+
+    /// Applies the operations of the change data capture envelopes of the messages, see [`CdcEnvelope`].
+    pub fn with_cdc_envelope(mut self, envelope: CdcEnvelope) -> Self {
+        self.cdc_envelope = Some(envelope);
+        self
+    }
+
+    /// Partitions the created tables by the column, see [`PartitionColumn`].
+    pub fn with_partition_column(mut self, column: PartitionColumn) -> Self {
+        self.partition_columns.push(column);
+        self
+    }
+
+    /// Transforms the message fields before they are written, see [`FieldTransforms`].
+    pub fn with_transforms(mut self, transforms: FieldTransforms) -> Self {
+        self.transforms = transforms;
+        self
+    }
+
+    /// Transforms the batches of rows with the SQL query before they are written, see [`SqlTransform`].
+    pub fn with_sql_transform(mut self, transform: SqlTransform) -> Self {
+        self.sql_transform = Some(transform);
+        self
+    }
+
+    /// Returns true if the route topic is a regex topic pattern.
+    #[inline]
+    pub fn is_pattern(&self) -> bool {
+        self.topic.starts_with('^')
+    }
+
+    /// The target table uri of a message of the given (matched) topic and proto message type,
+    /// `None` if the message type is not mapped and there is no default table.
+    pub fn table_uri_of(&self, topic: &str, full_name: &str) -> Option<String> {
+        self.message_tables.get(full_name)
+            .or(self.table_uri.as_ref())
+            .map(|table_uri| table_uri.replace(TOPIC_PLACEHOLDER, topic))
+    }
+
+    /// All the target table uris of the messages of the given (matched) topic.
+    pub fn table_uris_of(&self, topic: &str) -> Vec<String> {
+        let mut table_uris: Vec<String> = self.table_uri.iter()
+            .chain(self.message_tables.values())
+            .map(|table_uri| table_uri.replace(TOPIC_PLACEHOLDER, topic))
+            .collect();
+        table_uris.sort();
+        table_uris.dedup();
+        table_uris
+    }
+}
+
+
+/// The consumer context, flushing the revoked partitions before they are released
+/// and seeking the newly assigned partitions to the offsets stored in the delta log.
+///
+/// The rebalance callbacks run synchronously while the run loop polls the consumer,
+/// blocking the (multi-threaded) runtime worker until the ingest processor has handled the rebalance.
+pub struct KafkaContext {
+    health: Arc<PipelineHealth>,
+    processor: Arc<Mutex<IngestProcessor>>,
+    /// Set once the consumer is created, to commit the offsets of the revoked partitions.
+    consumer: OnceLock<Weak<StreamConsumer<KafkaContext>>>,
+    /// A failed rebalance handling, failing the run loop.
+    rebalance_error: std::sync::Mutex<Option<IngestError>>,
+}
+
+impl KafkaContext {
+    fn fail_on(&self, res: Result<(), IngestError>) {
+        if let Err(e) = res {
+            error!("Failed to handle rebalance: {}", e);
+            self.rebalance_error.lock().unwrap().get_or_insert(e);
+        }
+    }
+
+    fn take_rebalance_error(&self) -> Option<IngestError> {
+        self.rebalance_error.lock().unwrap().take()
+    }
+}
+
+impl ClientContext for KafkaContext {
+    fn stats(&self, statistics: Statistics) {
+        metrics::metrics().update_consumer_lag(&statistics);
+        let lag = statistics.topics.values()
+            .flat_map(|topic| topic.partitions.iter())
+            .filter(|(partition, stats)| **partition >= 0 && stats.consumer_lag >= 0)
+            .map(|(_, stats)| stats.consumer_lag)
+            .sum();
+        self.health.set_lag(lag);
+    }
+}
+
+impl ConsumerContext for KafkaContext {
+    fn pre_rebalance(&self, rebalance: &Rebalance) {
+        if let Rebalance::Revoke(tpl) = rebalance {
+            let partitions = partitions_of(tpl);
+            info!("Partitions revoked: {:?}", partitions);
+            let res = block_on(async {
+                let offsets = self.processor.lock().await.revoke_partitions(&partitions).await?;
+                // The consumer is gone when closing, the offsets are committed by the shutdown
+                match self.consumer.get().and_then(Weak::upgrade) {
+                    Some(consumer) => commit_offsets(consumer.as_ref(), offsets, CommitMode::Sync),
+                    None => Ok(()),
+                }
+            });
+            self.fail_on(res);
+        }
+    }
+
+    fn post_rebalance(&self, rebalance: &Rebalance) {
+        if let Rebalance::Assign(tpl) = rebalance {
+            let partitions = partitions_of(tpl);
+            info!("Partitions assigned: {:?}", partitions);
+            let res = block_on(async {
+                self.processor.lock().await.assign_partitions(&partitions).await
+            });
+            self.fail_on(res);
+            if !partitions.is_empty() {
+                self.health.set_assigned(true);
+            }
+        }
+    }
+}
+
+fn partitions_of(tpl: &TopicPartitionList) -> Vec<(String, i32)> {
+    tpl.elements().iter()
+        .map(|elem| (elem.topic().to_string(), elem.partition()))
+        .collect()
+}
+
+/// Runs the future to completion from a synchronous consumer callback.
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::task::block_in_place(|| Handle::current().block_on(future))
+}
+
+/// Runs the ingest pipeline until cancelled, requires a multi-threaded tokio runtime.
 pub async fn start_ingest(
-    topic: String,
     opts: IngestOptions,
     cancellation_token: Arc<CancellationToken>,
 ) -> Result<(), IngestError> {
-    info!("Starting ingest for topic: {}", topic);
+    if Handle::current().runtime_flavor() == RuntimeFlavor::CurrentThread {
+        return Err(IngestError::InvalidConfig("The ingest requires a multi-threaded tokio runtime".to_string()));
+    }
+    let mut ingest_processor = IngestProcessor::new(opts.clone())?;
+    ingest_processor.check_routes().await?;
+    let health = Arc::new(PipelineHealth::new(opts.consumer_group_id.clone(), opts.stall_timeout));
+    health::health().register(health.clone());
+    let probe = tokio::spawn(health::probe_readiness(
+        health.clone(),
+        ingest_processor.registry().clone(),
+        opts.routes.clone(),
+        opts.storage_options.clone(),
+    ).in_current_span());
 
-    // TODO separate method kafka config from opts
-    // Create the `StreamConsumer`, to receive the messages from the topic in form of a `Stream`.
-    let consumer: StreamConsumer<KafkaContext> = ClientConfig::new()
-        .set("group.id", &opts.consumer_group_id)
-        .set("bootstrap.servers", &opts.kafka_brokers)
-        .set("enable.partition.eof", "false")
-        .set("session.timeout.ms", "6000")
-        .set("enable.auto.commit", "false")
-        .set("auto.offset.reset", "earliest")
-        .create_with_context(KafkaContext).map_err(|_e| {
-        IngestError::IngestError
-    })?;
+    let processor = Arc::new(Mutex::new(ingest_processor));
+    // The compacted files are written with the same parquet settings
+    let optimizer = opts.optimize.clone().map(|optimize| tokio::spawn(
+        maintenance::run_optimize(
+            processor.clone(),
+            opts.storage_options.clone(),
+            OptimizeOptions { parquet: opts.parquet.clone(), ..optimize },
+            opts.commit_retry.clone(),
+        ).in_current_span()
+    ));
+    let vacuum = opts.vacuum.clone().map(|vacuum| tokio::spawn(
+        maintenance::run_vacuum(processor.clone(), opts.storage_options.clone(), vacuum).in_current_span()
+    ));
+    let res = run_ingest(processor, &opts, health.clone(), cancellation_token).await;
+    for task in optimizer.into_iter().chain(vacuum) {
+        task.abort();
+    }
+    probe.abort();
+    health::health().unregister(&health);
+    res
+}
 
-    consumer.subscribe(&[topic.as_str()]).map_err(|_e| {
-        IngestError::IngestError
-    })?;
+async fn run_ingest(
+    processor: Arc<Mutex<IngestProcessor>>,
+    opts: &IngestOptions,
+    health: Arc<PipelineHealth>,
+    cancellation_token: Arc<CancellationToken>,
+) -> Result<(), IngestError> {
+    // Create the `StreamConsumer`, to receive the messages from the topic in form of a `Stream`.
+    let context = KafkaContext {
+        health: health.clone(),
+        processor: processor.clone(),
+        consumer: OnceLock::new(),
+        rebalance_error: Default::default(),
+    };
+    let consumer: Arc<StreamConsumer<KafkaContext>> = Arc::new(kafka::consumer_config(opts).create_with_context(context)?);
+    let _ = consumer.context().consumer.set(Arc::downgrade(&consumer));
 
+    let subscription: Vec<String> = processor.lock().await.subscription().into_iter().map(String::from).collect();
+    info!("Starting ingest for topics: {:?}", subscription);
+    consumer.subscribe(&subscription.iter().map(String::as_str).collect::<Vec<_>>())?;
+    health.set_subscribed(true);
 
-    let ingest_processor = IngestProcessor::new(topic, opts)?;
+    let mut flush_check = tokio::time::interval(FLUSH_CHECK_INTERVAL);
+    let mut decoded_records = processor.lock().await.take_decoded_records()
+        .expect("decoded records of a new processor");
 
-    // The run loop
+    // The run loop, the messages are decoded in parallel by the partition tasks and buffered here in order.
+    // No messages are consumed while too many are in flight or while the full table buffers are written.
     loop {
+        let in_flight = processor.lock().await.in_flight();
         tokio::select! {
-            consumer_result = consumer.recv() => {
+            consumer_result = consumer.recv(), if in_flight < opts.max_in_flight_messages => {
+                if let Some(e) = consumer.context().take_rebalance_error() {
+                    return Err(e);
+                }
                 match consumer_result {
                     Ok(message) => {
-                        ingest_processor.process_message(message).await?;
+                        let mut ingest_processor = processor.lock().await;
+                        let (topic, partition) = (message.topic(), message.partition());
+                        if let Some(offset) = ingest_processor.seek_offset(topic, partition, message.offset()) {
+                            info!("Seeking partition {}/{} to offset {}", topic, partition, offset);
+                            consumer.seek(topic, partition, Offset::Offset(offset), SEEK_TIMEOUT)?;
+                            continue;
+                        }
+                        ingest_processor.dispatch(message.detach());
                     }
                     Err(e) => {
                         error!("Error while consuming message: {:?}", e);
                     }
                 }
             }
-            _ = cancellation_token.cancelled() => {
-                // TODO clean up if needed
-                return Ok(());
+            Some(record) = decoded_records.recv() => {
+                let mut ingest_processor = processor.lock().await;
+                ingest_processor.process_decoded(record).await?;
+                health.progress();
+                if ingest_processor.flush(false).await? > 0 {
+                    commit_offsets(consumer.as_ref(), ingest_processor.take_committable_offsets(), CommitMode::Async)?;
+                }
+            }
+            _ = flush_check.tick() => {
+                health.set_assigned(consumer.assignment().is_ok_and(|tpl| tpl.count() > 0));
+                let mut ingest_processor = processor.lock().await;
+                ingest_processor.flush(false).await?;
+                commit_offsets(consumer.as_ref(), ingest_processor.take_committable_offsets(), CommitMode::Async)?;
             }
+            _ = cancellation_token.cancelled() => break,
+        }
+    }
+
+    // Stop polling, write the buffered (and in flight) messages and commit their offsets before leaving the group
+    info!("Stopping ingest, draining the buffered messages");
+    let deadline = Instant::now() + opts.shutdown_timeout;
+    let drained = tokio::time::timeout(opts.shutdown_timeout, async {
+        let mut ingest_processor = processor.lock().await;
+        while ingest_processor.in_flight() > 0 {
+            let Some(record) = decoded_records.recv().await else {
+                break;
+            };
+            ingest_processor.process_decoded(record).await?;
+        }
+        ingest_processor.flush(true).await?;
+        commit_offsets(consumer.as_ref(), ingest_processor.take_committable_offsets(), CommitMode::Sync)?;
+        if !ingest_processor.unrouted_messages().is_empty() {
+            info!("Skipped messages without target table: {:?}", ingest_processor.unrouted_messages());
         }
+        Ok(())
+    }).await;
+    consumer.unsubscribe();
+    health.set_subscribed(false);
+    health.set_assigned(false);
+
+    // Closing the consumer blocks until the group is left
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let closed = tokio::time::timeout(remaining, tokio::task::spawn_blocking(move || drop(consumer))).await;
+    match (drained, closed) {
+        (Ok(drained), Ok(_)) => drained,
+        (Ok(drained), Err(_)) => {
+            warn!("Consumer did not close within {:?}", opts.shutdown_timeout);
+            drained
+        }
+        (Err(_), _) => Err(IngestError::ShutdownTimeout(opts.shutdown_timeout)),
+    }
+}
+
+/// Commits the next offsets to consume per partition.
+fn commit_offsets<C: Consumer<KafkaContext>>(
+    consumer: &C,
+    offsets: PartitionOffsets,
+    mode: CommitMode,
+) -> Result<(), IngestError> {
+    if offsets.is_empty() {
+        return Ok(());
     }
-}
\ No newline at end of file
+    let mut tpl = TopicPartitionList::new();
+    for ((topic, partition), offset) in offsets {
+        tpl.add_partition_offset(&topic, partition, Offset::Offset(offset))?;
+    }
+    consumer.commit(&tpl, mode)?;
+    Ok(())
+}
@@ -1,47 +1,117 @@
+mod cli;
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use clap::Parser;
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info};
-use ingest::{IngestOptions, MessageFormat, start_ingest};
+use tracing::{error, info, info_span, Instrument};
+use ingest::{consumer_offsets, create_table, IngestOptions, optimize_table, reset_consumer_offsets, serve_endpoints, start_ingest, vacuum_table};
+use schema_registry::SchemaRegistry;
+use tracing_subscriber::EnvFilter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use ingest::SchemaSource::SchemaRegistry;
-
-// TODO add clap and cli commands
-// TODO add tracing json via cli param see: https://github.com/tokio-rs/tracing/blob/master/examples/examples/toggle-subscribers.rs
-
-const TOPIC: &str = "proto.ds.claim";
-const SCHEMA_REGISTRY_URL: &str = "http://localhost:58085/";
-
-const KAFKA_BROKERS: &str = "localhost:59092";
+use crate::cli::{Cli, Command, LogFormat, OffsetsCommand, RegistryArgs, SchemaCommand, TableCommand};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let plain = tracing_subscriber::fmt::layer();
-    tracing_subscriber::registry().with(plain).init();
+    let cli = Cli::parse();
+    init_tracing(cli.log_format, &cli.log_filter)?;
+    match cli.command {
+        Command::Run(args) => run(args.pipelines()?, args.http_addr).await?,
+        Command::Schema(SchemaCommand::Show(args)) => {
+            let registry = schema_registry(&args.registry)?;
+            let schemas = registry.schemas_of_subject(&args.subject.subject(&args.topic)?).await?;
+            for schema in schemas.iter() {
+                println!("{}", schema);
+            }
+        }
+        Command::Schema(SchemaCommand::Arrow(args)) => {
+            let registry = schema_registry(&args.registry)?;
+            let schema = registry.latest_proto_schema_of(&args.subject.subject(&args.topic)?).await?;
+            let full_name = args.subject.record_name.as_deref().unwrap_or(schema.full_name());
+            for field in schema.message_to_arrow_schema(full_name)?.fields() {
+                println!("{}: {} (nullable: {})", field.name(), field.data_type(), field.is_nullable());
+            }
+        }
+        Command::Table(TableCommand::Create(args)) => {
+            let registry = schema_registry(&args.registry)?;
+            let subject = args.subject.subject(&args.topic)?;
+            let version = create_table(
+                &registry, &subject, args.subject.record_name.as_deref(), &args.table_uri, &args.storage.options()?,
+            ).await?;
+            println!("Table {} at version {}", args.table_uri, version);
+        }
+        Command::Table(TableCommand::Optimize(args)) => {
+            let storage_options = args.storage.options()?;
+            let stats = optimize_table(
+                &args.table_uri, &storage_options, &args.optimize_options(), &HashSet::new(), &args.commit_retry(),
+            ).await?;
+            println!("Compacted {} files of {} partitions into {} files", stats.files_removed, stats.partitions, stats.files_added);
+        }
+        Command::Table(TableCommand::Vacuum(args)) => {
+            let files = vacuum_table(&args.table_uri, &args.storage.options()?, &args.vacuum_options(), args.dry_run).await?;
+            for file in &files {
+                println!("{}", file);
+            }
+            let action = if args.dry_run { "Found" } else { "Deleted" };
+            println!("{} {} files to vacuum", action, files.len());
+        }
+        Command::Offsets(OffsetsCommand::Show(args)) => {
+            let mut opts = IngestOptions::default();
+            args.kafka.apply(&mut opts)?;
+            println!("partition\tcommitted\tlow\thigh\tlag");
+            for offset in consumer_offsets(&opts, &args.topic)? {
+                let committed = offset.committed.map_or("-".to_string(), |o| o.to_string());
+                println!("{}\t{}\t{}\t{}\t{}", offset.partition, committed, offset.low_watermark, offset.high_watermark, offset.lag());
+            }
+        }
+        Command::Offsets(OffsetsCommand::Reset(args)) => {
+            let mut opts = IngestOptions::default();
+            args.kafka.apply(&mut opts)?;
+            for (partition, offset) in reset_consumer_offsets(&opts, &args.topic, &args.partitions, args.to)? {
+                println!("Partition {} reset to offset {}", partition, offset);
+            }
+        }
+    }
+    Ok(())
+}
 
-    let opts = IngestOptions {
-        kafka_brokers: KAFKA_BROKERS.to_string(),
-        consumer_group_id: format!("{}-delta-ingest", TOPIC),
-        input_format: MessageFormat::Protobuf(
-            SchemaRegistry(url::Url::parse(SCHEMA_REGISTRY_URL)?,
-            ),
-        ),
+fn init_tracing(format: LogFormat, filter: &str) -> anyhow::Result<()> {
+    let registry = tracing_subscriber::registry().with(EnvFilter::try_new(filter)?);
+    match format {
+        LogFormat::Plain => registry.with(tracing_subscriber::fmt::layer()).init(),
+        LogFormat::Pretty => registry.with(tracing_subscriber::fmt::layer().pretty()).init(),
+        // Includes the fields of the current span and its parents (pipeline, topic, partition, table etc)
+        LogFormat::Json => registry.with(tracing_subscriber::fmt::layer().json()).init(),
+    }
+    Ok(())
+}
 
-    };
+fn schema_registry(args: &RegistryArgs) -> anyhow::Result<SchemaRegistry> {
+    Ok(SchemaRegistry::new(args.settings().build()?).with_retry_options(args.retry_options()))
+}
 
+/// Runs the pipelines (and the metrics and health server) until one of them fails or ctrl-c/SIGTERM is received.
+async fn run(pipelines: Vec<(String, IngestOptions)>, http_addr: Option<SocketAddr>) -> anyhow::Result<()> {
     let cancellation = Arc::new(CancellationToken::new());
-    let res = tokio::spawn({
-        let cancellation = cancellation.clone();
-        async move {
-            let res = start_ingest(TOPIC.to_string(), opts, cancellation.clone()).await;
-            cancellation.cancel();
-            res
-        }
-    });
+    let server = http_addr.map(|addr| tokio::spawn(serve_endpoints(addr, cancellation.clone())));
+    let runs: Vec<_> = pipelines.into_iter()
+        .map(|(name, opts)| {
+            let cancellation = cancellation.clone();
+            let span = info_span!("pipeline", pipeline = %name);
+            let run = tokio::spawn(async move {
+                let res = start_ingest(opts, cancellation.clone()).await;
+                cancellation.cancel();
+                res
+            }.instrument(span));
+            (name, run)
+        })
+        .collect();
 
     tokio::select! {
-        _ = tokio::signal::ctrl_c() => {
-            info!("Ctrl-C received, stopping ingest");
+        signal = shutdown_signal() => {
+            info!("{} received, stopping ingest", signal?);
             cancellation.cancel();
         }
         _ = cancellation.cancelled() => {
@@ -50,10 +120,34 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
-    if let Err(err) = res.await? {
-        error!("Ingest run failed: {:?}", err);
-    } else {
-        info!("Ingest run finished gracefully");
+    for (name, run) in runs {
+        if let Err(err) = run.await? {
+            error!("Ingest run of {} failed: {:?}", name, err);
+        } else {
+            info!("Ingest run of {} finished gracefully", name);
+        }
+    }
+    if let Some(server) = server {
+        if let Err(err) = server.await? {
+            error!("Http server failed: {:?}", err);
+        }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Waits for Ctrl-C or (on unix) SIGTERM, returning the received signal name.
+async fn shutdown_signal() -> anyhow::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res.map(|_| "Ctrl-C").map_err(Into::into),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        Ok("Ctrl-C")
+    }
+}
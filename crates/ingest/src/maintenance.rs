@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use deltalake::{DeltaOps, DeltaTable, DeltaTableError, PartitionFilter, PartitionValue};
+use deltalake::operations::optimize::OptimizeType;
+use deltalake::parquet::file::properties::WriterProperties;
+use schema_registry::RetryOptions;
+use tokio::sync::Mutex;
+use tracing::{debug, info, info_span, Instrument, warn};
+
+use crate::{DataWriterError, ParquetOptions};
+use crate::ingest::{IngestProcessor, PartitionValues};
+use crate::metrics::metrics;
+use crate::storage::open_table;
+use crate::writer::is_retryable_commit_error;
+
+/// Options of the table compaction (OPTIMIZE) of the small files written by the ingestion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizeOptions {
+    /// How often the tables of a pipeline are compacted.
+    pub interval: Duration,
+    /// The target size of the compacted files in bytes, the table `delta.targetFileSize` (or 100MB) if not set.
+    pub target_size: Option<i64>,
+    /// Z-orders the compacted files by the columns instead of bin-packing them.
+    pub z_order_columns: Vec<String>,
+    /// Parquet writer settings of the compacted files.
+    pub parquet: ParquetOptions,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(3600),
+            target_size: None,
+            z_order_columns: Vec::new(),
+            parquet: ParquetOptions::default(),
+        }
+    }
+}
+
+/// Options of the table VACUUM, deleting the data files removed from the table longer than the retention ago.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VacuumOptions {
+    /// How often the tables of a pipeline are vacuumed.
+    pub interval: Duration,
+    /// The retention of the removed files, the table `delta.deletedFileRetentionDuration` (7 days by default)
+    /// if not set. A shorter retention is rejected unless `unsafe_retention` is set.
+    pub retention: Option<Duration>,
+    /// Allows a retention shorter than the table `delta.deletedFileRetentionDuration`,
+    /// which breaks the readers and writers of the older table versions.
+    pub unsafe_retention: bool,
+}
+
+impl Default for VacuumOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(24 * 3600),
+            retention: None,
+            unsafe_retention: false,
+        }
+    }
+}
+
+/// The result of [`optimize_table`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OptimizeStats {
+    /// Number of the compacted partitions (1 for unpartitioned tables).
+    pub partitions: usize,
+    pub files_removed: u64,
+    pub files_added: u64,
+}
+
+/// Compacts the small files of the table partitions, except the `active` ones (partition values in the order of
+/// the table partition columns) which are still being written. Unpartitioned tables are compacted as a whole,
+/// unless active (the empty partition values).
+///
+/// Each partition is compacted in a separate commit, commits that conflict with concurrent writers
+/// are retried according to the [`RetryOptions`].
+pub async fn optimize_table(
+    table_uri: &str,
+    storage_options: &HashMap<String, String>,
+    opts: &OptimizeOptions,
+    active: &HashSet<PartitionValues>,
+    retry: &RetryOptions,
+) -> Result<OptimizeStats, DataWriterError> {
+    let writer_properties = opts.parquet.writer_properties()?;
+    let mut table = open_table(table_uri, storage_options).await?;
+    let partition_columns = table.metadata()?.partition_columns.clone();
+    let mut stats = OptimizeStats::default();
+
+    // The partitions with multiple files, in a stable order
+    let mut files_per_partition: HashMap<PartitionValues, usize> = HashMap::new();
+    for add in table.snapshot()?.file_actions()? {
+        let values = partition_columns.iter()
+            .map(|column| add.partition_values.get(column).cloned().flatten())
+            .collect();
+        *files_per_partition.entry(values).or_default() += 1;
+    }
+    let mut partitions: Vec<PartitionValues> = files_per_partition.into_iter()
+        .filter(|(values, files)| *files > 1 && !active.contains(values))
+        .map(|(values, _)| values)
+        .collect();
+    partitions.sort();
+
+    for values in partitions {
+        // Null partition values can not be selected by a filter
+        let Some(filters) = partition_columns.iter().zip(&values)
+            .map(|(column, value)| value.as_ref().map(|value| PartitionFilter {
+                key: column.clone(),
+                value: PartitionValue::Equal(value.clone()),
+            }))
+            .collect::<Option<Vec<_>>>()
+        else {
+            debug!("Skipping partition with null values {:?}", values);
+            continue;
+        };
+        let metrics = optimize_partition(&mut table, &filters, opts, &writer_properties, retry).await?;
+        stats.partitions += 1;
+        stats.files_removed += metrics.num_files_removed;
+        stats.files_added += metrics.num_files_added;
+    }
+    Ok(stats)
+}
+
+/// Deletes the data files removed from the table longer than the retention ago, or only lists them on a `dry_run`.
+/// Returns the paths of the deleted files.
+pub async fn vacuum_table(
+    table_uri: &str,
+    storage_options: &HashMap<String, String>,
+    opts: &VacuumOptions,
+    dry_run: bool,
+) -> Result<Vec<String>, DataWriterError> {
+    let table = open_table(table_uri, storage_options).await?;
+    let mut vacuum = DeltaOps(table).vacuum().with_dry_run(dry_run);
+    if let Some(retention) = opts.retention {
+        let retention = chrono::Duration::from_std(retention).map_err(|_| {
+            DeltaTableError::Generic(format!("The vacuum retention {:?} is out of range", retention))
+        })?;
+        vacuum = vacuum.with_retention_period(retention).with_enforce_retention_duration(!opts.unsafe_retention);
+    }
+    let (_, metrics) = vacuum.await?;
+    Ok(metrics.files_deleted)
+}
+
+async fn optimize_partition(
+    table: &mut DeltaTable,
+    filters: &[PartitionFilter],
+    opts: &OptimizeOptions,
+    writer_properties: &WriterProperties,
+    retry: &RetryOptions,
+) -> Result<deltalake::operations::optimize::Metrics, DataWriterError> {
+    let mut attempt = 0;
+    loop {
+        let optimize_type = if opts.z_order_columns.is_empty() {
+            OptimizeType::Compact
+        } else {
+            OptimizeType::ZOrder(opts.z_order_columns.clone())
+        };
+        let mut optimize = DeltaOps(table.clone()).optimize()
+            .with_filters(filters)
+            .with_type(optimize_type)
+            .with_writer_properties(writer_properties.clone());
+        if let Some(target_size) = opts.target_size {
+            optimize = optimize.with_target_size(target_size);
+        }
+        match optimize.await {
+            Ok((optimized, metrics)) => {
+                *table = optimized;
+                return Ok(metrics);
+            }
+            Err(e) if attempt < retry.max_retries && is_retryable_commit_error(&e) => {
+                let backoff = retry.backoff(attempt);
+                warn!("Optimize of table {} failed (attempt {}), retrying in {:?}: {}",
+                    table.table_uri(), attempt + 1, backoff, e);
+                tokio::time::sleep(backoff).await;
+                table.update().await?;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Periodically compacts the tables written by the processor, except their partitions written since the last run.
+pub(crate) async fn run_optimize(
+    processor: Arc<Mutex<IngestProcessor>>,
+    storage_options: HashMap<String, String>,
+    opts: OptimizeOptions,
+    retry: RetryOptions,
+) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + opts.interval, opts.interval);
+    loop {
+        interval.tick().await;
+        let tables = processor.lock().await.take_written_partitions();
+        for (table_uri, active) in tables {
+            let span = info_span!("optimize", table = %table_uri);
+            match optimize_table(&table_uri, &storage_options, &opts, &active, &retry).instrument(span).await {
+                Ok(stats) if stats.files_removed > 0 => {
+                    info!("Compacted {} files of {} partitions of table {} into {} files",
+                        stats.files_removed, stats.partitions, table_uri, stats.files_added);
+                    metrics().files_compacted.with_label_values(&[&table_uri]).inc_by(stats.files_removed);
+                }
+                Ok(_) => debug!("No files to compact in table {}", table_uri),
+                Err(e) => warn!("Failed to optimize table {}: {}", table_uri, e),
+            }
+        }
+    }
+}
+
+/// Periodically vacuums the tables written by the processor.
+pub(crate) async fn run_vacuum(
+    processor: Arc<Mutex<IngestProcessor>>,
+    storage_options: HashMap<String, String>,
+    opts: VacuumOptions,
+) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + opts.interval, opts.interval);
+    loop {
+        interval.tick().await;
+        let tables = processor.lock().await.table_uris();
+        for table_uri in tables {
+            let span = info_span!("vacuum", table = %table_uri);
+            match vacuum_table(&table_uri, &storage_options, &opts, false).instrument(span).await {
+                Ok(files) if !files.is_empty() => {
+                    info!("Vacuumed {} files of table {}", files.len(), table_uri);
+                    metrics().files_vacuumed.with_label_values(&[&table_uri]).inc_by(files.len() as u64);
+                }
+                Ok(_) => debug!("No files to vacuum in table {}", table_uri),
+                Err(e) => warn!("Failed to vacuum table {}: {}", table_uri, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deltalake::arrow::datatypes::{DataType, Field as ArrowField, Schema as ArrowSchema};
+    use serde_json::json;
+
+    use crate::{DataWriter, TableOptions};
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn optimizes_inactive_partitions() {
+        let dir = TestDir::new("optimize");
+        let uri = dir.to_str().unwrap();
+
+        let schema = Arc::new(ArrowSchema::new(vec![
+            ArrowField::new("id", DataType::Int32, true),
+            ArrowField::new("status", DataType::Utf8, true),
+        ]));
+        let table_options = TableOptions { partition_columns: vec!["status".to_string()], ..Default::default() };
+        let mut writer = DataWriter::try_new_with_options(uri, schema, table_options, RetryOptions::no_retries()).await.unwrap();
+        for id in 0..3 {
+            writer.write(&[json!({"id": id, "status": "closed"}), json!({"id": id, "status": "open"})]).await.unwrap();
+            writer.flush_and_commit().await.unwrap();
+        }
+        assert_eq!(writer.table().get_files_count(), 6);
+
+        // The open partition is still being written
+        let active = HashSet::from([vec![Some("open".to_string())]]);
+        let stats = optimize_table(uri, &HashMap::new(), &OptimizeOptions::default(), &active, &RetryOptions::no_retries()).await.unwrap();
+        assert_eq!(stats, OptimizeStats { partitions: 1, files_removed: 3, files_added: 1 });
+
+        let stats = optimize_table(uri, &HashMap::new(), &OptimizeOptions::default(), &HashSet::new(), &RetryOptions::no_retries()).await.unwrap();
+        assert_eq!(stats, OptimizeStats { partitions: 1, files_removed: 3, files_added: 1 });
+        let table = deltalake::open_table(uri).await.unwrap();
+        assert_eq!(table.get_files_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn optimizes_inactive_unpartitioned_tables() {
+        let dir = TestDir::new("optimize-unpartitioned");
+        let uri = dir.to_str().unwrap();
+
+        let schema = Arc::new(ArrowSchema::new(vec![ArrowField::new("id", DataType::Int32, true)]));
+        let mut writer = DataWriter::try_new(uri, schema, RetryOptions::no_retries()).await.unwrap();
+        for id in 0..3 {
+            writer.write(&[json!({"id": id})]).await.unwrap();
+            writer.flush_and_commit().await.unwrap();
+        }
+
+        // The table is still being written
+        let active = HashSet::from([vec![]]);
+        let stats = optimize_table(uri, &HashMap::new(), &OptimizeOptions::default(), &active, &RetryOptions::no_retries()).await.unwrap();
+        assert_eq!(stats, OptimizeStats::default());
+
+        let stats = optimize_table(uri, &HashMap::new(), &OptimizeOptions::default(), &HashSet::new(), &RetryOptions::no_retries()).await.unwrap();
+        assert_eq!(stats, OptimizeStats { partitions: 1, files_removed: 3, files_added: 1 });
+    }
+
+    #[tokio::test]
+    async fn vacuums_removed_files() {
+        let dir = TestDir::new("vacuum");
+        let uri = dir.to_str().unwrap();
+
+        let schema = Arc::new(ArrowSchema::new(vec![ArrowField::new("id", DataType::Int32, true)]));
+        let mut writer = DataWriter::try_new(uri, schema, RetryOptions::no_retries()).await.unwrap();
+        for id in 0..2 {
+            writer.write(&[json!({"id": id})]).await.unwrap();
+            writer.flush_and_commit().await.unwrap();
+        }
+        optimize_table(uri, &HashMap::new(), &OptimizeOptions::default(), &HashSet::new(), &RetryOptions::no_retries()).await.unwrap();
+
+        // The compacted files are retained for a week by default
+        assert!(vacuum_table(uri, &HashMap::new(), &VacuumOptions::default(), false).await.unwrap().is_empty());
+        // A retention shorter than the table one must be allowed explicitly
+        let opts = VacuumOptions { retention: Some(Duration::ZERO), ..Default::default() };
+        let error = vacuum_table(uri, &HashMap::new(), &opts, true).await.unwrap_err();
+        assert!(error.to_string().contains("retention"), "{}", error);
+        let opts = VacuumOptions { retention: Some(Duration::ZERO), unsafe_retention: true, ..Default::default() };
+        assert_eq!(vacuum_table(uri, &HashMap::new(), &opts, true).await.unwrap().len(), 2);
+        assert_eq!(vacuum_table(uri, &HashMap::new(), &opts, false).await.unwrap().len(), 2);
+        assert!(vacuum_table(uri, &HashMap::new(), &opts, false).await.unwrap().is_empty());
+        let opts = VacuumOptions { retention: Some(Duration::MAX), ..Default::default() };
+        let error = vacuum_table(uri, &HashMap::new(), &opts, true).await.unwrap_err();
+        assert!(error.to_string().contains("vacuum retention"));
+        let parquet_files = std::fs::read_dir(&*dir).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "parquet"))
+            .count();
+        assert_eq!(parquet_files, 1);
+    }
+}
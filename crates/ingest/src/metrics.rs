@@ -0,0 +1,135 @@
+use std::sync::OnceLock;
+
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use rdkafka::Statistics;
+
+/// The ingest metrics, shared by all the pipelines of the process.
+pub(crate) struct IngestMetrics {
+    pub registry: Registry,
+    /// Labels: topic.
+    pub messages_consumed: IntCounterVec,
+    /// Labels: topic.
+    pub messages_decoded: IntCounterVec,
+    /// Labels: topic.
+    pub messages_failed: IntCounterVec,
+    /// Decoded messages without target table. Labels: topic.
+    pub messages_skipped: IntCounterVec,
+    /// Labels: table.
+    pub rows_written: IntCounterVec,
+    /// Labels: table.
+    pub bytes_written: IntCounterVec,
+    /// Labels: table.
+    pub commit_duration: HistogramVec,
+    /// Labels: table.
+    pub buffered_messages: IntGaugeVec,
+    /// Labels: table.
+    pub files_compacted: IntCounterVec,
+    /// Labels: table.
+    pub files_vacuumed: IntCounterVec,
+    pub registry_cache_hits: IntCounter,
+    pub registry_cache_misses: IntCounter,
+    /// Labels: topic, partition.
+    pub consumer_lag: IntGaugeVec,
+}
+
+/// The metrics of the process, registered on first use.
+pub(crate) fn metrics() -> &'static IngestMetrics {
+    static METRICS: OnceLock<IngestMetrics> = OnceLock::new();
+    METRICS.get_or_init(IngestMetrics::new)
+}
+
+impl IngestMetrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("ingest".to_string()), None)
+            .expect("valid metrics prefix");
+        let metrics = Self {
+            messages_consumed: counter_vec("messages_consumed_total", "Consumed kafka messages", &["topic"]),
+            messages_decoded: counter_vec("messages_decoded_total", "Successfully decoded messages", &["topic"]),
+            messages_failed: counter_vec("messages_failed_total", "Messages that failed to decode", &["topic"]),
+            messages_skipped: counter_vec("messages_skipped_total", "Decoded messages without a target table", &["topic"]),
+            rows_written: counter_vec("rows_written_total", "Rows committed to delta tables", &["table"]),
+            bytes_written: counter_vec("bytes_written_total", "Data file bytes committed to delta tables", &["table"]),
+            commit_duration: HistogramVec::new(
+                HistogramOpts::new("commit_duration_seconds", "Duration of the delta table writes and commits"),
+                &["table"],
+            ).expect("valid metric"),
+            buffered_messages: IntGaugeVec::new(
+                Opts::new("buffered_messages", "Messages buffered and not yet written per table"),
+                &["table"],
+            ).expect("valid metric"),
+            files_compacted: counter_vec("files_compacted_total", "Small data files compacted by the table optimize runs", &["table"]),
+            files_vacuumed: counter_vec("files_vacuumed_total", "Removed data files deleted by the table vacuum runs", &["table"]),
+            registry_cache_hits: IntCounter::new("schema_registry_cache_hits_total", "Schema lookups served from the cache")
+                .expect("valid metric"),
+            registry_cache_misses: IntCounter::new("schema_registry_cache_misses_total", "Schema lookups fetched from the registry")
+                .expect("valid metric"),
+            consumer_lag: IntGaugeVec::new(
+                Opts::new("consumer_lag", "Messages behind the partition high watermark"),
+                &["topic", "partition"],
+            ).expect("valid metric"),
+            registry,
+        };
+
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.messages_consumed.clone()),
+            Box::new(metrics.messages_decoded.clone()),
+            Box::new(metrics.messages_failed.clone()),
+            Box::new(metrics.messages_skipped.clone()),
+            Box::new(metrics.rows_written.clone()),
+            Box::new(metrics.bytes_written.clone()),
+            Box::new(metrics.commit_duration.clone()),
+            Box::new(metrics.buffered_messages.clone()),
+            Box::new(metrics.files_compacted.clone()),
+            Box::new(metrics.files_vacuumed.clone()),
+            Box::new(metrics.registry_cache_hits.clone()),
+            Box::new(metrics.registry_cache_misses.clone()),
+            Box::new(metrics.consumer_lag.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("unique metric");
+        }
+        metrics
+    }
+
+    /// Updates the consumer lag from the librdkafka statistics.
+    pub fn update_consumer_lag(&self, statistics: &Statistics) {
+        for (topic, stats) in &statistics.topics {
+            for (partition, stats) in &stats.partitions {
+                // Partition -1 is the internal unassigned partition and a negative lag is unknown
+                if *partition >= 0 && stats.consumer_lag >= 0 {
+                    self.consumer_lag
+                        .with_label_values(&[topic, &partition.to_string()])
+                        .set(stats.consumer_lag);
+                }
+            }
+        }
+    }
+
+    /// The metrics in the prometheus text format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics encoding to memory");
+        buffer
+    }
+}
+
+fn counter_vec(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    IntCounterVec::new(Opts::new(name, help), labels).expect("valid metric")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_metrics() {
+        metrics().messages_consumed.with_label_values(&["metrics.test"]).inc();
+        metrics().buffered_messages.with_label_values(&["./data/metrics_test"]).set(5);
+
+        let text = String::from_utf8(metrics().encode()).unwrap();
+        assert!(text.contains("ingest_messages_consumed_total{topic=\"metrics.test\"} 1"));
+        assert!(text.contains("ingest_buffered_messages{table=\"./data/metrics_test\"} 5"));
+    }
+}
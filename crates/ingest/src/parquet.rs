@@ -0,0 +1,201 @@
+use std::str::FromStr;
+
+use deltalake::parquet::basic::{BrotliLevel, Compression, GzipLevel, ZstdLevel};
+use deltalake::parquet::errors::ParquetError;
+use deltalake::parquet::file::properties::{EnabledStatistics, WriterProperties};
+use deltalake::parquet::schema::types::ColumnPath;
+
+/// Compression codec of the parquet data files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParquetCompression {
+    Uncompressed,
+    #[default]
+    Snappy,
+    Gzip,
+    Brotli,
+    /// The LZ4 raw block format, without the deprecated hadoop framing.
+    Lz4,
+    Zstd,
+}
+
+impl FromStr for ParquetCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uncompressed" => Ok(ParquetCompression::Uncompressed),
+            "snappy" => Ok(ParquetCompression::Snappy),
+            "gzip" => Ok(ParquetCompression::Gzip),
+            "brotli" => Ok(ParquetCompression::Brotli),
+            "lz4" => Ok(ParquetCompression::Lz4),
+            "zstd" => Ok(ParquetCompression::Zstd),
+            _ => Err(format!("Unknown compression {}, expected uncompressed, snappy, gzip, brotli, lz4 or zstd", s)),
+        }
+    }
+}
+
+/// The level of the column statistics written to the parquet data files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParquetStatistics {
+    /// No statistics, the delta log has no column statistics to skip files by either.
+    None,
+    /// Statistics per column chunk.
+    Chunk,
+    /// Statistics per column chunk and data page.
+    #[default]
+    Page,
+}
+
+impl FromStr for ParquetStatistics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ParquetStatistics::None),
+            "chunk" => Ok(ParquetStatistics::Chunk),
+            "page" => Ok(ParquetStatistics::Page),
+            _ => Err(format!("Unknown statistics level {}, expected none, chunk or page", s)),
+        }
+    }
+}
+
+/// Parquet writer settings of the written (and compacted) data files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParquetOptions {
+    pub compression: ParquetCompression,
+    /// Level of the gzip (0-10), brotli (0-11) or zstd (1-22) compression, the codec default if not set.
+    pub compression_level: Option<i32>,
+    /// Maximum number of rows per row group, 1M if not set.
+    pub max_row_group_size: Option<usize>,
+    /// Dictionary encodes the columns.
+    pub dictionary: bool,
+    pub statistics: ParquetStatistics,
+    /// Maximum size in bytes of the min and max statistics of a column, 4KB if not set.
+    pub max_statistics_size: Option<usize>,
+    /// Columns (dot separated paths of nested fields) with bloom filters, speeding up the point lookups.
+    pub bloom_filter_columns: Vec<String>,
+    /// False positive probability of the bloom filters, 0.05 if not set.
+    pub bloom_filter_fpp: Option<f64>,
+    /// Number of distinct values the bloom filters are sized for, 1M if not set.
+    pub bloom_filter_ndv: Option<u64>,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self {
+            compression: ParquetCompression::default(),
+            compression_level: None,
+            max_row_group_size: None,
+            dictionary: true,
+            statistics: ParquetStatistics::default(),
+            max_statistics_size: None,
+            bloom_filter_columns: Vec::new(),
+            bloom_filter_fpp: None,
+            bloom_filter_ndv: None,
+        }
+    }
+}
+
+impl ParquetOptions {
+    /// The parquet writer properties, fails on invalid compression levels and bloom filter probabilities.
+    pub fn writer_properties(&self) -> Result<WriterProperties, ParquetError> {
+        let unsigned = |level: i32| u32::try_from(level)
+            .map_err(|_| ParquetError::General(format!("Invalid compression level {}", level)));
+        let compression = match (self.compression, self.compression_level) {
+            (ParquetCompression::Uncompressed, None) => Compression::UNCOMPRESSED,
+            (ParquetCompression::Snappy, None) => Compression::SNAPPY,
+            (ParquetCompression::Lz4, None) => Compression::LZ4_RAW,
+            (ParquetCompression::Gzip, level) => Compression::GZIP(match level {
+                Some(level) => GzipLevel::try_new(unsigned(level)?)?,
+                None => GzipLevel::default(),
+            }),
+            (ParquetCompression::Brotli, level) => Compression::BROTLI(match level {
+                Some(level) => BrotliLevel::try_new(unsigned(level)?)?,
+                None => BrotliLevel::default(),
+            }),
+            (ParquetCompression::Zstd, level) => Compression::ZSTD(match level {
+                Some(level) => ZstdLevel::try_new(level)?,
+                None => ZstdLevel::default(),
+            }),
+            (compression, Some(_)) => {
+                return Err(ParquetError::General(format!("The {:?} compression has no levels", compression)));
+            }
+        };
+        if let Some(fpp) = self.bloom_filter_fpp.filter(|fpp| *fpp <= 0.0 || *fpp >= 1.0) {
+            return Err(ParquetError::General(format!("Invalid bloom filter fpp {}, expected between 0 and 1", fpp)));
+        }
+
+        let mut builder = WriterProperties::builder()
+            .set_compression(compression)
+            .set_dictionary_enabled(self.dictionary)
+            .set_statistics_enabled(match self.statistics {
+                ParquetStatistics::None => EnabledStatistics::None,
+                ParquetStatistics::Chunk => EnabledStatistics::Chunk,
+                ParquetStatistics::Page => EnabledStatistics::Page,
+            });
+        if let Some(size) = self.max_row_group_size {
+            builder = builder.set_max_row_group_size(size);
+        }
+        if let Some(size) = self.max_statistics_size {
+            builder = builder.set_max_statistics_size(size);
+        }
+        for column in &self.bloom_filter_columns {
+            let path = ColumnPath::new(column.split('.').map(str::to_string).collect());
+            builder = builder.set_column_bloom_filter_enabled(path.clone(), true);
+            if let Some(fpp) = self.bloom_filter_fpp {
+                builder = builder.set_column_bloom_filter_fpp(path.clone(), fpp);
+            }
+            if let Some(ndv) = self.bloom_filter_ndv {
+                builder = builder.set_column_bloom_filter_ndv(path, ndv);
+            }
+        }
+        Ok(builder.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writer_properties_of_options() {
+        let opts = ParquetOptions {
+            compression: "zstd".parse().unwrap(),
+            compression_level: Some(9),
+            max_row_group_size: Some(10_000),
+            dictionary: false,
+            statistics: "chunk".parse().unwrap(),
+            bloom_filter_columns: vec!["id".to_string(), "details.id".to_string()],
+            bloom_filter_fpp: Some(0.01),
+            ..Default::default()
+        };
+        let props = opts.writer_properties().unwrap();
+        let id = ColumnPath::from("id");
+        assert_eq!(props.compression(&id), Compression::ZSTD(ZstdLevel::try_new(9).unwrap()));
+        assert_eq!(props.max_row_group_size(), 10_000);
+        assert!(!props.dictionary_enabled(&id));
+        assert_eq!(props.statistics_enabled(&id), EnabledStatistics::Chunk);
+        assert_eq!(props.bloom_filter_properties(&id).unwrap().fpp, 0.01);
+        let nested = ColumnPath::new(vec!["details".to_string(), "id".to_string()]);
+        assert!(props.bloom_filter_properties(&nested).is_some());
+        assert!(props.bloom_filter_properties(&ColumnPath::from("name")).is_none());
+
+        let default = ParquetOptions::default().writer_properties().unwrap();
+        assert_eq!(default.compression(&id), Compression::SNAPPY);
+        assert!(default.bloom_filter_properties(&id).is_none());
+    }
+
+    #[test]
+    fn invalid_compression_levels() {
+        let opts = |compression: &str, level| ParquetOptions {
+            compression: compression.parse().unwrap(),
+            compression_level: Some(level),
+            ..Default::default()
+        };
+        assert!(opts("gzip", 10).writer_properties().is_ok());
+        assert!(opts("gzip", 11).writer_properties().is_err());
+        assert!(opts("zstd", 23).writer_properties().is_err());
+        assert!(opts("snappy", 1).writer_properties().is_err());
+        assert!("lzo".parse::<ParquetCompression>().is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rdkafka::Message;
+use rdkafka::message::OwnedMessage;
+use schema_registry::DecodedMessage;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, Instrument};
+
+use crate::KeyFormat;
+use crate::deserialize::{DecodedKey, DeserializeError, ProtoDeserializer};
+
+/// A consumed message and the result of decoding its payload.
+pub struct DecodedRecord {
+    pub message: OwnedMessage,
+    pub decoded: Result<DecodedMessage, DeserializeError>,
+    /// The decoded key, `None` if the message has no key or the keys are ignored.
+    pub key: Result<Option<DecodedKey>, DeserializeError>,
+    /// The partition worker that decoded the message.
+    worker: u64,
+}
+
+struct PartitionWorker {
+    id: u64,
+    messages: UnboundedSender<(OwnedMessage, KeyFormat)>,
+}
+
+/// Decodes the consumed messages in parallel, with a task per assigned topic partition.
+///
+/// The decoded records are sent to a single channel, in the consumed order of each partition.
+/// Every dispatched message produces exactly one record, so the messages in flight can be bounded
+/// by the consumer of the channel.
+pub struct PartitionWorkers {
+    deserializer: Arc<ProtoDeserializer>,
+    workers: HashMap<(String, i32), PartitionWorker>,
+    next_id: u64,
+    decoded: UnboundedSender<DecodedRecord>,
+    /// Dispatched messages whose records are not yet received.
+    in_flight: usize,
+}
+
+impl PartitionWorkers {
+    pub fn new(deserializer: Arc<ProtoDeserializer>) -> (Self, UnboundedReceiver<DecodedRecord>) {
+        let (decoded, records) = unbounded_channel();
+        let workers = Self {
+            deserializer,
+            workers: HashMap::new(),
+            next_id: 0,
+            decoded,
+            in_flight: 0,
+        };
+        (workers, records)
+    }
+
+    #[inline]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Sends the message to the worker of its partition, starting the worker if needed.
+    pub fn dispatch(&mut self, message: OwnedMessage, key_format: KeyFormat) {
+        let tp = (message.topic().to_string(), message.partition());
+        let worker = self.workers.entry(tp).or_insert_with_key(|(topic, partition)| {
+            let id = self.next_id;
+            self.next_id += 1;
+            let (messages, receiver) = unbounded_channel();
+            debug!("Starting decode worker of partition {}/{}", topic, partition);
+            tokio::spawn(
+                decode_partition(self.deserializer.clone(), receiver, self.decoded.clone(), id).in_current_span()
+            );
+            PartitionWorker { id, messages }
+        });
+        self.in_flight += 1;
+        worker.messages.send((message, key_format)).expect("decode worker running while its sender exists");
+    }
+
+    /// Marks the record as received, returning false if its partition has been stopped since it was dispatched.
+    pub fn receive(&mut self, record: &DecodedRecord) -> bool {
+        self.in_flight -= 1;
+        self.workers.get(&(record.message.topic().to_string(), record.message.partition()))
+            .is_some_and(|worker| worker.id == record.worker)
+    }
+
+    /// Stops the workers of the (revoked) partitions. Their records still in flight are discarded when received.
+    pub fn stop(&mut self, partitions: &[(String, i32)]) {
+        for tp in partitions {
+            // Dropping the sender ends the worker after its queued messages
+            self.workers.remove(tp);
+        }
+    }
+}
+
+async fn decode_partition(
+    deserializer: Arc<ProtoDeserializer>,
+    mut messages: UnboundedReceiver<(OwnedMessage, KeyFormat)>,
+    decoded: UnboundedSender<DecodedRecord>,
+    worker: u64,
+) {
+    while let Some((message, key_format)) = messages.recv().await {
+        let result = deserializer.deserialize_message(&message).await;
+        let key = deserializer.deserialize_key(&message, key_format).await;
+        if decoded.send(DecodedRecord { message, decoded: result, key, worker }).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rdkafka::message::Timestamp;
+    use schema_registry::RegistrySettings;
+
+    use crate::{IngestOptions, MessageFormat, SchemaSource};
+
+    use super::*;
+
+    fn message(partition: i32, offset: i64) -> OwnedMessage {
+        // Empty payloads fail to decode, which is still a record
+        OwnedMessage::new(None, None, "persons".to_string(), Timestamp::NotAvailable, partition, offset, None)
+    }
+
+    #[tokio::test]
+    async fn decodes_partitions_in_order_and_discards_stopped() {
+        let deserializer = ProtoDeserializer::build_from(&IngestOptions {
+            input_format: MessageFormat::Protobuf(SchemaSource::SchemaRegistry(RegistrySettings::new("http://localhost:1/"))),
+            ..Default::default()
+        }).unwrap();
+        let (mut workers, mut records) = PartitionWorkers::new(Arc::new(deserializer));
+
+        for offset in 0..10 {
+            workers.dispatch(message(0, offset), KeyFormat::None);
+            workers.dispatch(message(1, offset), KeyFormat::None);
+        }
+        assert_eq!(workers.in_flight(), 20);
+        workers.stop(&[("persons".to_string(), 1)]);
+
+        let mut offsets = Vec::new();
+        while workers.in_flight() > 0 {
+            let record = records.recv().await.unwrap();
+            assert!(matches!(record.decoded, Err(DeserializeError::EmptyPayload)));
+            if workers.receive(&record) {
+                assert_eq!(record.message.partition(), 0);
+                offsets.push(record.message.offset());
+            }
+        }
+        assert_eq!(offsets, (0..10).collect::<Vec<_>>());
+    }
+}
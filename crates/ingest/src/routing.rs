@@ -0,0 +1,144 @@
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+
+use crate::{IngestError, KeyFormat, TombstoneMode, TopicRoute, WriteMode};
+use crate::storage::check_table_uri;
+
+/// Resolves the [`TopicRoute`] of the messages of a topic.
+/// Exact topic routes take precedence over pattern routes, pattern routes are matched in order.
+pub(crate) struct TopicRouter {
+    topics: HashMap<String, TopicRoute>,
+    patterns: Vec<(Regex, TopicRoute)>,
+    subscription: Vec<String>,
+}
+
+impl TopicRouter {
+    pub fn try_new(routes: &[TopicRoute]) -> Result<Self, IngestError> {
+        if routes.is_empty() {
+            return Err(IngestError::InvalidRoute("At least one topic route is required".to_string()));
+        }
+
+        let mut topics = HashMap::new();
+        let mut patterns = Vec::new();
+        for route in routes {
+            if route.table_uri.is_none() && route.message_tables.is_empty() {
+                return Err(IngestError::InvalidRoute(format!("No target table for topic {}", route.topic)));
+            }
+            for table_uri in route.table_uri.iter().chain(route.message_tables.values()) {
+                check_table_uri(table_uri).map_err(IngestError::InvalidRoute)?;
+            }
+            if route.tombstones != TombstoneMode::Skip && (route.key_format == KeyFormat::None || route.table_uri.is_none()) {
+                return Err(IngestError::InvalidRoute(format!(
+                    "Tombstones of topic {} require a key format and a default table", route.topic
+                )));
+            }
+            if matches!(&route.write_mode, WriteMode::Upsert { keys, .. } if keys.is_empty()) {
+                return Err(IngestError::InvalidRoute(format!("No upsert key columns for topic {}", route.topic)));
+            }
+            if route.cdc_envelope.is_some() && route.write_mode == WriteMode::Append {
+                return Err(IngestError::InvalidRoute(format!("Change data capture of topic {} requires upsert keys", route.topic)));
+            }
+            route.transforms.validate()
+                .map_err(|e| IngestError::InvalidRoute(format!("Invalid field transforms of topic {}: {}", route.topic, e)))?;
+            if let Some(transform) = &route.sql_transform {
+                transform.validate()
+                    .map_err(|e| IngestError::InvalidRoute(format!("Invalid SQL transform of topic {}: {}", route.topic, e)))?;
+            }
+            let mut partition_columns = HashSet::new();
+            if let Some(column) = route.partition_columns.iter().find(|c| !partition_columns.insert(&c.name)) {
+                return Err(IngestError::InvalidRoute(format!("Duplicate partition column {} of topic {}", column.name, route.topic)));
+            }
+            if route.is_pattern() {
+                let regex = Regex::new(&route.topic)
+                    .map_err(|e| IngestError::InvalidRoute(format!("Invalid topic pattern {}: {}", route.topic, e)))?;
+                patterns.push((regex, route.clone()));
+            } else if topics.insert(route.topic.clone(), route.clone()).is_some() {
+                return Err(IngestError::InvalidRoute(format!("Duplicate route for topic {}", route.topic)));
+            }
+        }
+
+        Ok(Self {
+            topics,
+            patterns,
+            subscription: routes.iter().map(|r| r.topic.clone()).collect(),
+        })
+    }
+
+    /// Topic names and patterns to subscribe the consumer to.
+    pub fn subscription(&self) -> Vec<&str> {
+        self.subscription.iter().map(String::as_str).collect()
+    }
+
+    /// The route of the given topic, `None` if no route matches.
+    pub fn route_of(&self, topic: &str) -> Option<&TopicRoute> {
+        if let Some(route) = self.topics.get(topic) {
+            return Some(route);
+        }
+        self.patterns.iter()
+            .find(|(regex, _)| regex.is_match(topic))
+            .map(|(_, route)| route)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CdcEnvelope, FieldTransforms, PartitionColumn, SqlTransform};
+
+    use super::*;
+
+    fn table_uri_of(router: &TopicRouter, topic: &str, full_name: &str) -> Option<String> {
+        router.route_of(topic).and_then(|r| r.table_uri_of(topic, full_name))
+    }
+
+    #[test]
+    fn routes_exact_and_pattern_topics() {
+        let router = TopicRouter::try_new(&[
+            TopicRoute::new("^proto\\..*", "./data/{topic}"),
+            TopicRoute::new("proto.ds.claim", "./data/claims"),
+        ]).unwrap();
+
+        assert_eq!(router.subscription(), vec!["^proto\\..*", "proto.ds.claim"]);
+        assert_eq!(table_uri_of(&router, "proto.ds.claim", "example.Claim"), Some("./data/claims".to_string()));
+        assert_eq!(table_uri_of(&router, "proto.ds.person", "example.Person"), Some("./data/proto.ds.person".to_string()));
+        assert!(router.route_of("json.ds.person").is_none());
+    }
+
+    #[test]
+    fn routes_message_types() {
+        let router = TopicRouter::try_new(&[
+            TopicRoute::by_message_type("proto.ds.person")
+                .with_message_table("example.Person", "./data/persons")
+                .with_message_table("example.Contact", "./data/contacts"),
+            TopicRoute::new("^proto\\..*", "./data/{topic}")
+                .with_message_table("example.Contact", "./data/{topic}_contacts"),
+        ]).unwrap();
+
+        assert_eq!(table_uri_of(&router, "proto.ds.person", "example.Person"), Some("./data/persons".to_string()));
+        assert_eq!(table_uri_of(&router, "proto.ds.person", "example.Contact"), Some("./data/contacts".to_string()));
+        assert_eq!(table_uri_of(&router, "proto.ds.person", "example.Details"), None);
+        assert_eq!(table_uri_of(&router, "proto.ds.claim", "example.Contact"), Some("./data/proto.ds.claim_contacts".to_string()));
+        assert_eq!(table_uri_of(&router, "proto.ds.claim", "example.Claim"), Some("./data/proto.ds.claim".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_routes() {
+        assert!(TopicRouter::try_new(&[]).is_err());
+        assert!(TopicRouter::try_new(&[TopicRoute::new("^proto(", "./data/{topic}")]).is_err());
+        assert!(TopicRouter::try_new(&[TopicRoute::new("a", "./data/a"), TopicRoute::new("a", "./data/b")]).is_err());
+        assert!(TopicRouter::try_new(&[TopicRoute::by_message_type("a")]).is_err());
+        assert!(TopicRouter::try_new(&[TopicRoute::new("a", "./data/a").with_tombstones(TombstoneMode::Delete)]).is_err());
+        let upsert = WriteMode::Upsert { keys: vec![], event_time: None };
+        assert!(TopicRouter::try_new(&[TopicRoute::new("a", "./data/a").with_write_mode(upsert)]).is_err());
+        let cdc = TopicRoute::new("a", "./data/a").with_cdc_envelope(CdcEnvelope::default());
+        assert!(TopicRouter::try_new(&[cdc]).is_err());
+        let date = "date(created_date)".parse::<PartitionColumn>().unwrap();
+        let partitioned = TopicRoute::new("a", "./data/a").with_partition_column(date.clone()).with_partition_column(date);
+        assert!(TopicRouter::try_new(&[partitioned]).is_err());
+        assert!(TopicRouter::try_new(&[TopicRoute::new("a", "hdfs://namenode/a")]).is_err());
+        let flatten = FieldTransforms { flatten: Some(String::new()), ..Default::default() };
+        assert!(TopicRouter::try_new(&[TopicRoute::new("a", "./data/a").with_transforms(flatten)]).is_err());
+        let ddl = SqlTransform::new("DROP TABLE messages");
+        assert!(TopicRouter::try_new(&[TopicRoute::new("a", "./data/a").with_sql_transform(ddl)]).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::IngestError;
+use crate::health::health;
+use crate::metrics::metrics;
+
+/// Serves the prometheus `/metrics`, the liveness `/healthz` and the readiness `/readyz` endpoints until cancelled.
+pub async fn serve_endpoints(addr: SocketAddr, cancellation_token: Arc<CancellationToken>) -> Result<(), IngestError> {
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(handle))
+    });
+    let server = Server::try_bind(&addr)
+        .map_err(|e| IngestError::InvalidConfig(format!("Failed to bind http server to {}: {}", addr, e)))?
+        .serve(make_service);
+    info!("Serving metrics and health checks on http://{}", addr);
+
+    server
+        .with_graceful_shutdown(async move { cancellation_token.cancelled().await })
+        .await
+        .map_err(|e| IngestError::InvalidConfig(format!("Http server failed: {}", e)))
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, prometheus::TEXT_FORMAT)
+            .body(Body::from(metrics().encode())),
+        (&Method::GET, "/healthz") => health_response(health().liveness()),
+        (&Method::GET, "/readyz") => health_response(health().readiness()),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.expect("valid response"))
+}
+
+fn health_response((healthy, status): (bool, serde_json::Value)) -> Result<Response<Body>, hyper::http::Error> {
+    Response::builder()
+        .status(if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE })
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(status.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn metrics_endpoint() {
+        let response = handle(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], prometheus::TEXT_FORMAT);
+
+        let response = handle(Request::get("/other").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn health_endpoints() {
+        // Live but not ready without running pipelines
+        let response = handle(Request::get("/healthz").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = handle(Request::get("/readyz").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+    }
+}
@@ -0,0 +1,217 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use deltalake::arrow::compute::concat_batches;
+use deltalake::arrow::datatypes::{Schema as ArrowSchema, SchemaRef as ArrowSchemaRef};
+use deltalake::arrow::record_batch::RecordBatch;
+use deltalake::datafusion::datasource::{TableProvider, TableType};
+use deltalake::datafusion::error::{DataFusionError, Result as DataFusionResult};
+use deltalake::datafusion::execution::context::{SessionState, SQLOptions};
+use deltalake::datafusion::logical_expr::{Expr, LogicalPlan};
+use deltalake::datafusion::physical_plan::ExecutionPlan;
+use deltalake::datafusion::physical_plan::memory::MemoryExec;
+use deltalake::datafusion::prelude::SessionContext;
+use deltalake::datafusion::sql::parser::{DFParser, Statement};
+use deltalake::datafusion::sql::sqlparser::ast::Statement as SqlStatement;
+
+use crate::DataWriterError;
+
+/// The table of the messages in the SQL transform queries.
+const MESSAGES_TABLE: &str = "messages";
+
+/// A SQL query transforming the batches of messages before they are written, e.g. computing columns, casting,
+/// filtering (`WHERE`) and masking them. The messages are queried as the `messages` table, with the columns of the
+/// table rows (the transformed message fields, the key and the derived partition columns).
+///
+/// The schema of the created tables is the output schema of the query, planned against the schema of the messages.
+/// The upsert keys and the partition columns must be output columns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SqlTransform {
+    pub query: String,
+}
+
+impl SqlTransform {
+    /// A `SELECT ... FROM messages` query.
+    pub fn new<Q: Into<String>>(query: Q) -> Self {
+        Self { query: query.into() }
+    }
+
+    /// Selects the expressions (e.g. `*`, `upper(name) AS name_upper`) of the messages.
+    pub fn select<I: IntoIterator<Item = S>, S: AsRef<str>>(expressions: I) -> Self {
+        let expressions: Vec<_> = expressions.into_iter().map(|e| e.as_ref().to_string()).collect();
+        Self::new(format!("SELECT {} FROM {}", expressions.join(", "), MESSAGES_TABLE))
+    }
+
+    /// Parses the query, without planning it against the schema of the messages.
+    /// Fails on invalid SQL and on the statements other than a single query (e.g. DDL or DML statements).
+    pub fn validate(&self) -> Result<(), DataWriterError> {
+        let statements = DFParser::parse_sql(&self.query).map_err(DataFusionError::from)?;
+        match statements.front() {
+            Some(Statement::Statement(statement)) if statements.len() == 1 && matches!(**statement, SqlStatement::Query(_)) => Ok(()),
+            _ => Err(DataFusionError::Plan(format!("The SQL transform is not a single query: {}", self.query)).into()),
+        }
+    }
+
+    /// The schema of the transformed batches of messages of the given schema,
+    /// fails on invalid queries and the statements other than queries.
+    pub async fn output_schema(&self, input_schema: ArrowSchemaRef) -> Result<ArrowSchema, DataWriterError> {
+        Ok(self.plan(input_schema).await?.output_schema())
+    }
+
+    /// Transforms the batch of messages, planning the query for this batch only.
+    pub async fn apply(&self, batch: RecordBatch) -> Result<RecordBatch, DataWriterError> {
+        self.plan(batch.schema()).await?.apply(batch).await
+    }
+
+    /// Plans the query against the schema of the messages, for transforming all their batches.
+    pub(crate) async fn plan(&self, input_schema: ArrowSchemaRef) -> Result<PlannedSqlTransform, DataWriterError> {
+        let ctx = SessionContext::new();
+        let messages = Arc::new(MessagesTable { schema: input_schema, batch: Mutex::new(None) });
+        ctx.register_table(MESSAGES_TABLE, messages.clone())?;
+        let options = SQLOptions::new()
+            .with_allow_ddl(false)
+            .with_allow_dml(false)
+            .with_allow_statements(false);
+        let plan = ctx.sql_with_options(&self.query, options).await?.into_unoptimized_plan();
+        Ok(PlannedSqlTransform { ctx, plan, messages })
+    }
+}
+
+/// A [`SqlTransform`] planned once against the schema of the messages, whose batches are transformed
+/// by swapping the batch of the `messages` table.
+pub(crate) struct PlannedSqlTransform {
+    ctx: SessionContext,
+    plan: LogicalPlan,
+    messages: Arc<MessagesTable>,
+}
+
+impl PlannedSqlTransform {
+    pub fn output_schema(&self) -> ArrowSchema {
+        self.plan.schema().as_ref().into()
+    }
+
+    /// Transforms the batch of messages, which must have the planned schema.
+    pub async fn apply(&mut self, batch: RecordBatch) -> Result<RecordBatch, DataWriterError> {
+        *self.messages.batch.lock().unwrap() = Some(batch);
+        let res = self.execute().await;
+        self.messages.batch.lock().unwrap().take();
+        res
+    }
+
+    async fn execute(&self) -> Result<RecordBatch, DataWriterError> {
+        let frame = self.ctx.execute_logical_plan(self.plan.clone()).await?;
+        let schema: ArrowSchemaRef = Arc::new(frame.schema().into());
+        let batches = frame.collect().await?;
+        Ok(concat_batches(&schema, &batches)?)
+    }
+}
+
+/// The `messages` table of the planned transforms, scanning the batch being transformed.
+struct MessagesTable {
+    schema: ArrowSchemaRef,
+    batch: Mutex<Option<RecordBatch>>,
+}
+
+#[async_trait]
+impl TableProvider for MessagesTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> ArrowSchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Temporary
+    }
+
+    async fn scan(
+        &self,
+        _state: &SessionState,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let batches: Vec<RecordBatch> = self.batch.lock().unwrap().iter().cloned().collect();
+        Ok(Arc::new(MemoryExec::try_new(&[batches], self.schema.clone(), projection.cloned())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deltalake::arrow::array::{Int32Array, StringArray};
+    use deltalake::arrow::datatypes::{DataType, Field as ArrowField};
+
+    use super::*;
+
+    fn messages() -> RecordBatch {
+        let schema = Arc::new(ArrowSchema::new(vec![
+            ArrowField::new("id", DataType::Int32, true),
+            ArrowField::new("email", DataType::Utf8, true),
+            ArrowField::new("type", DataType::Utf8, true),
+        ]));
+        RecordBatch::try_new(schema, vec![
+            Arc::new(Int32Array::from(vec![1, 2, 3])),
+            Arc::new(StringArray::from(vec![Some("a@b.com"), None, Some("c@d.com")])),
+            Arc::new(StringArray::from(vec!["person", "heartbeat", "person"])),
+        ]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn computes_and_filters_columns() {
+        let transform = SqlTransform::new(
+            "SELECT id, CAST(id AS BIGINT) * 10 AS score, regexp_replace(email, '^[^@]+', '***') AS email \
+             FROM messages WHERE type <> 'heartbeat'",
+        );
+        let schema = transform.output_schema(messages().schema()).await.unwrap();
+        let columns: Vec<_> = schema.fields().iter().map(|f| (f.name().as_str(), f.data_type().clone())).collect();
+        assert_eq!(columns, vec![("id", DataType::Int32), ("score", DataType::Int64), ("email", DataType::Utf8)]);
+
+        let batch = transform.apply(messages()).await.unwrap();
+        assert_eq!(batch.schema().as_ref(), &schema);
+        assert_eq!(batch.num_rows(), 2);
+        let emails = batch.column_by_name("email").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(emails.iter().collect::<Vec<_>>(), vec![Some("***@b.com"), Some("***@d.com")]);
+    }
+
+    #[tokio::test]
+    async fn selects_expressions() {
+        let transform = SqlTransform::select(["*", "upper(type) AS type_upper"]);
+        assert_eq!(transform.query, "SELECT *, upper(type) AS type_upper FROM messages");
+        let batch = transform.apply(messages()).await.unwrap();
+        assert_eq!(batch.num_columns(), 4);
+
+        // Everything filtered out
+        let batch = SqlTransform::new("SELECT id FROM messages WHERE id > 3").apply(messages()).await.unwrap();
+        assert_eq!((batch.num_rows(), batch.num_columns()), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn applies_the_plan_to_each_batch() {
+        // The subquery scans the swapped batch too
+        let transform = SqlTransform::new("SELECT id FROM messages WHERE id = (SELECT max(id) FROM messages)");
+        let mut planned = transform.plan(messages().schema()).await.unwrap();
+        let ids = |batch: RecordBatch| batch.column(0).as_any().downcast_ref::<Int32Array>().unwrap().values().to_vec();
+
+        assert_eq!(ids(planned.apply(messages()).await.unwrap()), vec![3]);
+        assert_eq!(ids(planned.apply(messages().slice(0, 2)).await.unwrap()), vec![2]);
+        assert!(planned.messages.batch.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_queries() {
+        assert!(SqlTransform::new("WITH p AS (SELECT * FROM messages) SELECT id FROM p").validate().is_ok());
+        for query in ["SELEC id FROM messages", "DROP TABLE messages", "INSERT INTO messages VALUES (1)",
+                      "SELECT 1; SELECT 2", "COPY messages TO 'out.csv'", "SET x = 1", ""] {
+            assert!(SqlTransform::new(query).validate().is_err(), "{}", query);
+        }
+
+        let schema = messages().schema();
+        assert!(SqlTransform::new("SELECT name FROM messages").output_schema(schema.clone()).await.is_err());
+        assert!(SqlTransform::new("SELEC id FROM messages").output_schema(schema.clone()).await.is_err());
+        assert!(SqlTransform::new("CREATE TABLE t (id INT)").output_schema(schema.clone()).await.is_err());
+        assert!(SqlTransform::new("DROP TABLE messages").output_schema(schema).await.is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Once};
+
+use deltalake::{DeltaResult, DeltaTable, DeltaTableBuilder, DeltaTableError, Path};
+use deltalake::storage::{factories, ObjectStoreFactory, ObjectStoreRef, StorageOptions, url_prefix_handler};
+use deltalake::storage::object_store::memory::InMemory;
+use url::Url;
+
+use crate::transactions::TransactionStoreFactory;
+
+/// Registers the object stores of the table uri schemes, the cloud ones of the enabled `s3`, `gcs` and `azure`
+/// features. `memory://<store>/<path>` tables are kept in the process memory (until it exits), for tests.
+/// The object stores of all the schemes keep the application transactions in the checkpoints (see [`crate::transactions::TransactionStore`]).
+pub(crate) fn register_object_stores() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        #[cfg(feature = "s3")]
+        deltalake::aws::register_handlers(None);
+        #[cfg(feature = "gcs")]
+        deltalake::gcp::register_handlers(None);
+        #[cfg(feature = "azure")]
+        deltalake::azure::register_handlers(None);
+        factories().insert(Url::parse("memory://").expect("valid url"), Arc::new(SharedMemoryFactory::default()));
+        let registered: Vec<_> = factories().iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect();
+        for (url, factory) in registered {
+            factories().insert(url, Arc::new(TransactionStoreFactory::new(factory)));
+        }
+    });
+}
+
+/// Fails on the table uris of object stores not built in, e.g. `s3://` uris without the `s3` feature.
+pub(crate) fn check_table_uri(table_uri: &str) -> Result<(), String> {
+    let Some(scheme) = table_uri.split_once("://").map(|(scheme, _)| scheme) else {
+        // A local path
+        return Ok(());
+    };
+    let (feature, enabled) = match scheme {
+        "file" | "memory" => return Ok(()),
+        "s3" | "s3a" => ("s3", cfg!(feature = "s3")),
+        "gs" => ("gcs", cfg!(feature = "gcs")),
+        "az" | "adl" | "azure" | "abfs" | "abfss" => ("azure", cfg!(feature = "azure")),
+        _ => return Err(format!("Unsupported scheme {} of table uri {}", scheme, table_uri)),
+    };
+    if enabled {
+        Ok(())
+    } else {
+        Err(format!("The table uri {} requires the ingest to be built with the {} feature", table_uri, feature))
+    }
+}
+
+/// Opens the delta table at the uri with the object store credentials and settings of the `storage_options`.
+pub(crate) async fn open_table(table_uri: &str, storage_options: &HashMap<String, String>) -> Result<DeltaTable, DeltaTableError> {
+    register_object_stores();
+    DeltaTableBuilder::from_valid_uri(table_uri)?
+        .with_storage_options(storage_options.clone())
+        .load()
+        .await
+}
+
+/// In-memory object stores shared by the tables of the same `memory://<store>` uri host.
+#[derive(Default)]
+struct SharedMemoryFactory {
+    stores: Mutex<HashMap<String, ObjectStoreRef>>,
+}
+
+impl ObjectStoreFactory for SharedMemoryFactory {
+    fn parse_url_opts(&self, url: &Url, _options: &StorageOptions) -> DeltaResult<(ObjectStoreRef, Path)> {
+        let store = self.stores.lock().expect("not poisoned")
+            .entry(url.host_str().unwrap_or_default().to_string())
+            .or_insert_with(|| Arc::new(InMemory::new()))
+            .clone();
+        let path = Path::from_url_path(url.path())?;
+        Ok((url_prefix_handler(store, path.clone()), path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deltalake::arrow::datatypes::{DataType, Field as ArrowField, Schema as ArrowSchema};
+    use schema_registry::RetryOptions;
+    use serde_json::json;
+
+    use crate::{DataWriter, TableOptions};
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn checks_table_uri_schemes() {
+        assert!(check_table_uri("./data/persons").is_ok());
+        assert!(check_table_uri("/data/{topic}").is_ok());
+        assert!(check_table_uri("file:///data/persons").is_ok());
+        assert!(check_table_uri("memory://test/persons").is_ok());
+        assert_eq!(check_table_uri("s3://bucket/persons").is_ok(), cfg!(feature = "s3"));
+        assert_eq!(check_table_uri("gs://bucket/persons").is_ok(), cfg!(feature = "gcs"));
+        assert_eq!(check_table_uri("abfss://container@account.dfs.core.windows.net/persons").is_ok(), cfg!(feature = "azure"));
+        assert!(check_table_uri("ftp://host/persons").is_err());
+    }
+
+    #[tokio::test]
+    async fn writes_to_memory_and_file_uris() {
+        let schema = Arc::new(ArrowSchema::new(vec![ArrowField::new("id", DataType::Int32, true)]));
+        let dir = TestDir::new("storage");
+        let file_uri = format!("file://{}", dir.to_str().unwrap());
+
+        for uri in ["memory://storage-test/persons", &file_uri] {
+            let mut writer = DataWriter::try_new_with_options(uri, schema.clone(), TableOptions::default(), RetryOptions::no_retries())
+                .await.unwrap();
+            writer.write(&[json!({"id": 1})]).await.unwrap();
+            writer.flush_and_commit().await.unwrap();
+
+            // The table is reopened from the same store
+            let table = open_table(uri, &HashMap::new()).await.unwrap();
+            assert_eq!((table.version(), table.get_files_count()), (1, 1));
+        }
+        assert!(open_table("memory://storage-test/contacts", &HashMap::new()).await.is_err());
+    }
+}
@@ -0,0 +1,30 @@
+use std::ops::Deref;
+use std::path::Path;
+
+use tempfile::TempDir;
+
+/// A temporary directory of the test tables and files, deleted when dropped.
+pub(crate) struct TestDir(TempDir);
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("ingest-{}-test-", name))
+            .tempdir()
+            .expect("temporary test directory");
+        Self(dir)
+    }
+
+    /// The uri of the table `name` in the directory.
+    pub fn table_uri(&self, name: &str) -> String {
+        self.join(name).to_str().expect("UTF-8 test directory").to_string()
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        self.0.path()
+    }
+}
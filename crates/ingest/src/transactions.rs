@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use deltalake::{DeltaResult, DeltaTable, DeltaTableError, ObjectMeta, ObjectStoreError, Path};
+use deltalake::arrow::array::{Array, Int64Array, StringArray, StructArray};
+use deltalake::arrow::json::ReaderBuilder;
+use deltalake::arrow::record_batch::RecordBatchReader;
+use deltalake::kernel::{Action, Txn};
+use deltalake::logstore::{LogStore, LogStoreConfig, LogStoreRef};
+use deltalake::operations::transaction::TransactionError;
+use deltalake::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use deltalake::parquet::arrow::{ArrowWriter, ProjectionMask};
+use deltalake::parquet::basic::Compression;
+use deltalake::parquet::file::properties::WriterProperties;
+use deltalake::storage::{ObjectStoreFactory, ObjectStoreRef, StorageOptions};
+use deltalake::storage::object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectStore, PutOptions, PutResult, Result as ObjectStoreResult,
+};
+use futures::stream::BoxStream;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use tokio::io::AsyncWrite;
+use url::Url;
+
+const LAST_CHECKPOINT: &str = "_last_checkpoint";
+
+/// The application transaction versions (`txn` actions) of the table, as of its latest version.
+///
+/// The table snapshot of deltalake 0.17 does not track them, so they are replayed like the snapshot is:
+/// from the last checkpoint (see [`TransactionStore`]) and the commits after it.
+pub(crate) async fn app_transaction_versions(table: &DeltaTable) -> Result<HashMap<String, i64>, DeltaTableError> {
+    transaction_versions(table.object_store().as_ref(), table.version()).await
+}
+
+/// The transaction versions of the last checkpoint, updated with the `txn` actions of the commits up to `version`.
+async fn transaction_versions(store: &dyn ObjectStore, version: i64) -> Result<HashMap<String, i64>, DeltaTableError> {
+    #[derive(Deserialize)]
+    struct LogAction {
+        txn: Option<Txn>,
+    }
+
+    let (mut versions, checkpoint_version) = match last_checkpoint(store).await? {
+        Some(checkpoint) => (checkpoint_transactions(store, &checkpoint).await?, checkpoint.version),
+        None => (HashMap::new(), -1),
+    };
+    for commit in checkpoint_version + 1..=version {
+        let entry = match store.get(&commit_path(commit)).await {
+            Ok(entry) => entry.bytes().await?,
+            // Removed by the log cleanup of a concurrent writer after a newer checkpoint
+            Err(ObjectStoreError::NotFound { .. }) => return Box::pin(transaction_versions(store, version)).await,
+            Err(e) => return Err(e.into()),
+        };
+        for line in entry.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+            let action: LogAction = serde_json::from_slice(line).map_err(|json_err| DeltaTableError::InvalidJsonLog {
+                json_err,
+                line: String::from_utf8_lossy(line).to_string(),
+                version: commit,
+            })?;
+            if let Some(txn) = action.txn {
+                versions.insert(txn.app_id, txn.version);
+            }
+        }
+    }
+    Ok(versions)
+}
+
+#[derive(Debug, Deserialize)]
+struct LastCheckpoint {
+    version: i64,
+    parts: Option<u32>,
+}
+
+async fn last_checkpoint(store: &dyn ObjectStore) -> Result<Option<LastCheckpoint>, DeltaTableError> {
+    match store.get(&log_path(LAST_CHECKPOINT)).await {
+        Ok(data) => Ok(Some(serde_json::from_slice(&data.bytes().await?)?)),
+        Err(ObjectStoreError::NotFound { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The `txn` actions of the (single or multi-part) checkpoint.
+async fn checkpoint_transactions(
+    store: &dyn ObjectStore,
+    checkpoint: &LastCheckpoint,
+) -> Result<HashMap<String, i64>, DeltaTableError> {
+    let paths = match checkpoint.parts {
+        None | Some(1) => vec![log_path(&format!("{:020}.checkpoint.parquet", checkpoint.version))],
+        Some(parts) => (1..=parts)
+            .map(|part| log_path(&format!("{:020}.checkpoint.{:010}.{:010}.parquet", checkpoint.version, part, parts)))
+            .collect(),
+    };
+    let mut versions = HashMap::new();
+    for path in paths {
+        let data = store.get(&path).await?.bytes().await?;
+        versions.extend(parquet_transactions(data)?);
+    }
+    Ok(versions)
+}
+
+/// The `txn` actions of a checkpoint parquet file.
+fn parquet_transactions(data: Bytes) -> Result<Vec<(String, i64)>, DeltaTableError> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(data)?;
+    let Ok(index) = builder.schema().index_of("txn") else {
+        return Ok(Vec::new());
+    };
+    let mask = ProjectionMask::roots(builder.parquet_schema(), [index]);
+    let mut transactions = Vec::new();
+    for batch in builder.with_projection(mask).build()? {
+        let batch = batch?;
+        let Some(txn) = batch.column(0).as_any().downcast_ref::<StructArray>() else {
+            continue;
+        };
+        let app_ids = txn.column_by_name("appId").and_then(|c| c.as_any().downcast_ref::<StringArray>());
+        let versions = txn.column_by_name("version").and_then(|c| c.as_any().downcast_ref::<Int64Array>());
+        let (Some(app_ids), Some(versions)) = (app_ids, versions) else {
+            continue;
+        };
+        transactions.extend((0..txn.len())
+            .filter(|i| txn.is_valid(*i))
+            .map(|i| (app_ids.value(i).to_string(), versions.value(i))));
+    }
+    Ok(transactions)
+}
+
+fn log_path(name: &str) -> Path {
+    Path::from_iter(["_delta_log", name])
+}
+
+fn commit_path(version: i64) -> Path {
+    log_path(&format!("{:020}.json", version))
+}
+
+/// The version of the single part checkpoint parquet file at `location`.
+fn checkpoint_version(location: &Path) -> Option<i64> {
+    let name = location.as_ref().strip_prefix("_delta_log/")?.strip_suffix(".checkpoint.parquet")?;
+    (name.len() == 20).then(|| name.parse().ok()).flatten()
+}
+
+/// An object store adding the `txn` actions of the application transactions to the written checkpoints,
+/// which deltalake 0.17 writes without them (see [`app_transaction_versions`]).
+///
+/// The transaction versions of a checkpoint are read from the previous checkpoint and the commits after it,
+/// so they are not lost when the log cleanup removes the commits before the checkpoint.
+#[derive(Debug)]
+pub(crate) struct TransactionStore {
+    inner: ObjectStoreRef,
+    /// The number of `txn` actions and the size in bytes of the written checkpoints, for their `_last_checkpoint`.
+    checkpoints: Mutex<HashMap<i64, (i64, i64)>>,
+}
+
+impl TransactionStore {
+    pub(crate) fn new(inner: ObjectStoreRef) -> Self {
+        Self { inner, checkpoints: Mutex::new(HashMap::new()) }
+    }
+
+    /// Appends the transaction versions as of the checkpoint version to the checkpoint parquet file,
+    /// unless it has `txn` actions already.
+    async fn add_checkpoint_transactions(&self, version: i64, data: Bytes) -> DeltaResult<Bytes> {
+        if !parquet_transactions(data.clone())?.is_empty() {
+            return Ok(data);
+        }
+        let transactions = transaction_versions(self.inner.as_ref(), version).await?;
+        if transactions.is_empty() {
+            return Ok(data);
+        }
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(data)?.build()?;
+        let schema = reader.schema();
+        let mut output = Vec::new();
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let mut writer = ArrowWriter::try_new(&mut output, schema.clone(), Some(properties))?;
+        for batch in reader {
+            writer.write(&batch?)?;
+        }
+        let actions = transactions.iter()
+            .map(|(app_id, version)| serde_json::to_value(Action::Txn(Txn {
+                app_id: app_id.clone(),
+                version: *version,
+                last_updated: None,
+            })))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut decoder = ReaderBuilder::new(schema).build_decoder()?;
+        decoder.serialize(&actions)?;
+        while let Some(batch) = decoder.flush()? {
+            writer.write(&batch)?;
+        }
+        writer.close()?;
+
+        self.checkpoints.lock().expect("not poisoned").insert(version, (actions.len() as i64, output.len() as i64));
+        Ok(Bytes::from(output))
+    }
+
+    /// Counts the added `txn` actions of the checkpoint in the `_last_checkpoint` pointing to it.
+    fn update_last_checkpoint(&self, data: Bytes) -> DeltaResult<Bytes> {
+        let mut last_checkpoint: JsonValue = serde_json::from_slice(&data)?;
+        let version = last_checkpoint["version"].as_i64().unwrap_or(-1);
+        let Some((transactions, size_in_bytes)) = self.checkpoints.lock().expect("not poisoned").remove(&version) else {
+            return Ok(data);
+        };
+        if let Some(size) = last_checkpoint["size"].as_i64() {
+            last_checkpoint["size"] = JsonValue::from(size + transactions);
+        }
+        if last_checkpoint.get("sizeInBytes").is_some_and(|size| !size.is_null()) {
+            last_checkpoint["sizeInBytes"] = JsonValue::from(size_in_bytes);
+        }
+        Ok(Bytes::from(serde_json::to_vec(&last_checkpoint)?))
+    }
+}
+
+impl fmt::Display for TransactionStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TransactionStore({})", self.inner)
+    }
+}
+
+fn to_object_store_error(e: DeltaTableError) -> ObjectStoreError {
+    ObjectStoreError::Generic { store: "TransactionStore", source: Box::new(e) }
+}
+
+#[async_trait]
+impl ObjectStore for TransactionStore {
+    async fn put_opts(&self, location: &Path, bytes: Bytes, opts: PutOptions) -> ObjectStoreResult<PutResult> {
+        let bytes = if let Some(version) = checkpoint_version(location) {
+            self.add_checkpoint_transactions(version, bytes).await.map_err(to_object_store_error)?
+        } else if location.as_ref() == log_path(LAST_CHECKPOINT).as_ref() {
+            self.update_last_checkpoint(bytes).map_err(to_object_store_error)?
+        } else {
+            bytes
+        };
+        self.inner.put_opts(location, bytes, opts).await
+    }
+
+    async fn put_multipart(&self, location: &Path) -> ObjectStoreResult<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> ObjectStoreResult<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> ObjectStoreResult<GetResult> {
+        self.inner.get_opts(location, options).await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> ObjectStoreResult<Bytes> {
+        self.inner.get_range(location, range).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> ObjectStoreResult<Vec<Bytes>> {
+        self.inner.get_ranges(location, ranges).await
+    }
+
+    async fn head(&self, location: &Path) -> ObjectStoreResult<ObjectMeta> {
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> ObjectStoreResult<()> {
+        self.inner.delete(location).await
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, ObjectStoreResult<Path>>,
+    ) -> BoxStream<'a, ObjectStoreResult<Path>> {
+        self.inner.delete_stream(locations)
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_offset(&self, prefix: Option<&Path>, offset: &Path) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        self.inner.list_with_offset(prefix, offset)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> ObjectStoreResult<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.inner.rename(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.inner.rename_if_not_exists(from, to).await
+    }
+}
+
+/// Wraps the object stores of another factory in a [`TransactionStore`].
+pub(crate) struct TransactionStoreFactory {
+    inner: Arc<dyn ObjectStoreFactory>,
+}
+
+impl TransactionStoreFactory {
+    pub(crate) fn new(inner: Arc<dyn ObjectStoreFactory>) -> Self {
+        Self { inner }
+    }
+}
+
+impl ObjectStoreFactory for TransactionStoreFactory {
+    fn parse_url_opts(&self, url: &Url, options: &StorageOptions) -> DeltaResult<(ObjectStoreRef, Path)> {
+        let (store, path) = self.inner.parse_url_opts(url, options)?;
+        Ok((Arc::new(TransactionStore::new(store)), path))
+    }
+}
+
+/// A log store adding the `txn` actions of the application transactions to the written commit,
+/// for the operations that do not take additional actions (merges and deletes).
+pub(crate) struct TransactionLogStore {
+    inner: LogStoreRef,
+    actions: Vec<Action>,
+    /// Whether the actions were added to the commit entry, which is written again on conflicts.
+    added: AtomicBool,
+}
+
+impl TransactionLogStore {
+    pub(crate) fn new(inner: LogStoreRef, actions: Vec<Action>) -> Self {
+        Self { inner, actions, added: AtomicBool::new(false) }
+    }
+}
+
+#[async_trait]
+impl LogStore for TransactionLogStore {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    async fn refresh(&self) -> DeltaResult<()> {
+        self.inner.refresh().await
+    }
+
+    async fn read_commit_entry(&self, version: i64) -> DeltaResult<Option<Bytes>> {
+        self.inner.read_commit_entry(version).await
+    }
+
+    async fn write_commit_entry(&self, version: i64, tmp_commit: &Path) -> Result<(), TransactionError> {
+        if !self.actions.is_empty() && !self.added.swap(true, Ordering::SeqCst) {
+            let store = self.inner.object_store();
+            let mut entry = store.get(tmp_commit).await?.bytes().await?.to_vec();
+            for action in &self.actions {
+                entry.push(b'\n');
+                entry.extend(serde_json::to_vec(action).map_err(|json_err| TransactionError::SerializeLogJson { json_err })?);
+            }
+            store.put(tmp_commit, Bytes::from(entry)).await?;
+        }
+        self.inner.write_commit_entry(version, tmp_commit).await
+    }
+
+    async fn get_latest_version(&self, start_version: i64) -> DeltaResult<i64> {
+        self.inner.get_latest_version(start_version).await
+    }
+
+    fn object_store(&self) -> ObjectStoreRef {
+        self.inner.object_store()
+    }
+
+    fn to_uri(&self, location: &Path) -> String {
+        self.inner.to_uri(location)
+    }
+
+    fn root_uri(&self) -> String {
+        self.inner.root_uri()
+    }
+
+    fn log_path(&self) -> &Path {
+        self.inner.log_path()
+    }
+
+    async fn is_delta_table_location(&self) -> DeltaResult<bool> {
+        self.inner.is_delta_table_location().await
+    }
+
+    fn object_store_url(&self) -> deltalake::datafusion::execution::object_store::ObjectStoreUrl {
+        self.inner.object_store_url()
+    }
+
+    fn config(&self) -> &LogStoreConfig {
+        self.inner.config()
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use deltalake::arrow::datatypes::{DataType, FieldRef, Fields, Schema as ArrowSchema};
+use serde_json::{Map, Value as JsonValue};
+
+/// Transforms of the decoded message fields before they are written, applied in order: `select`, `drop`,
+/// `rename` and `flatten`. Fields are referenced by dot separated paths of nested fields (e.g. `details.physical.age`).
+///
+/// The table schema is transformed the same way, the columns added to the rows (the key, the partition columns etc)
+/// and the upsert keys refer to the transformed fields.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FieldTransforms {
+    /// The fields kept, all the fields if empty. Selecting a nested field keeps its parents with only the selected fields.
+    pub select: Vec<String>,
+    /// The fields removed.
+    pub drop: Vec<String>,
+    /// The fields renamed in place (the new name of `details.physical.age` is the name of the field in `details.physical`).
+    /// The paths are the ones of the fields before any renaming.
+    pub rename: Vec<(String, String)>,
+    /// Flattens the nested struct fields into top level columns named by their paths joined with the separator
+    /// (e.g. `details_physical_age` with `_`). Lists of structs are kept as is.
+    pub flatten: Option<String>,
+}
+
+impl FieldTransforms {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.select.is_empty() && self.drop.is_empty() && self.rename.is_empty() && self.flatten.is_none()
+    }
+
+    /// Fails on empty field paths, names or flatten separators.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let paths = self.select.iter().chain(&self.drop).chain(self.rename.iter().map(|(path, _)| path));
+        if let Some(path) = paths.into_iter().find(|path| path.split('.').any(str::is_empty)) {
+            return Err(format!("Invalid field path {}", path));
+        }
+        if let Some((path, name)) = self.rename.iter().find(|(_, name)| name.is_empty() || name.contains('.')) {
+            return Err(format!("Invalid new name {} of field {}, expected a field name", name, path));
+        }
+        if self.flatten.as_ref().is_some_and(String::is_empty) {
+            return Err("Empty flatten separator".to_string());
+        }
+        Ok(())
+    }
+
+    /// The transformed schema of the messages, fails on unknown fields and conflicting (renamed or flattened) names.
+    pub(crate) fn apply_to_schema(&self, schema: &ArrowSchema) -> Result<ArrowSchema, String> {
+        let mut fields = schema.fields().clone();
+        for path in &self.select {
+            field_at(&fields, &split_path(path)).ok_or_else(|| format!("Unknown selected field {}", path))?;
+        }
+        if !self.select.is_empty() {
+            let paths: Vec<Vec<&str>> = self.select.iter().map(|path| split_path(path)).collect();
+            fields = select_fields(&fields, &paths.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        }
+        for path in &self.drop {
+            let path = split_path(path);
+            field_at(&fields, &path).ok_or_else(|| format!("Unknown dropped field {}", path.join(".")))?;
+            fields = update_parent(&fields, &path, &mut |parent, name| {
+                parent.iter().filter(|field| field.name() != name).cloned().collect()
+            });
+        }
+        for (path, new_name) in self.renames() {
+            let path = split_path(path);
+            field_at(&fields, &path).ok_or_else(|| format!("Unknown renamed field {}", path.join(".")))?;
+            let mut conflict = false;
+            fields = update_parent(&fields, &path, &mut |parent, name| {
+                conflict = name != new_name && parent.iter().any(|field| field.name() == new_name);
+                parent.iter()
+                    .map(|field| match field.name() == name {
+                        true => Arc::new(field.as_ref().clone().with_name(new_name)),
+                        false => field.clone(),
+                    })
+                    .collect()
+            });
+            if conflict {
+                return Err(format!("The new name {} of field {} conflicts with an existing field", new_name, path.join(".")));
+            }
+        }
+        if let Some(separator) = &self.flatten {
+            let mut flattened = Vec::new();
+            flatten_fields(&fields, None, separator, false, &mut flattened);
+            let mut names = HashSet::new();
+            if let Some(field) = flattened.iter().find(|field| !names.insert(field.name())) {
+                return Err(format!("The flattened column {} conflicts with another column", field.name()));
+            }
+            fields = flattened.into();
+        }
+        Ok(ArrowSchema::new_with_metadata(fields, schema.metadata().clone()))
+    }
+
+    /// Transforms the fields of a decoded message, consistently with [`FieldTransforms::apply_to_schema`].
+    /// Missing (null) fields are skipped.
+    pub(crate) fn apply(&self, value: &mut JsonValue) {
+        let JsonValue::Object(fields) = value else {
+            return;
+        };
+        if !self.select.is_empty() {
+            let paths: Vec<Vec<&str>> = self.select.iter().map(|path| split_path(path)).collect();
+            select_values(fields, &paths.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        }
+        for path in &self.drop {
+            let path = split_path(path);
+            if let Some(parent) = parent_object(fields, &path) {
+                parent.remove(*path.last().expect("non empty path"));
+            }
+        }
+        for (path, new_name) in self.renames() {
+            let path = split_path(path);
+            if let Some(parent) = parent_object(fields, &path) {
+                if let Some(value) = parent.remove(*path.last().expect("non empty path")) {
+                    parent.insert(new_name.to_string(), value);
+                }
+            }
+        }
+        if let Some(separator) = &self.flatten {
+            let mut flattened = Map::new();
+            flatten_values(std::mem::take(fields), None, separator, &mut flattened);
+            *fields = flattened;
+        }
+    }
+
+    /// The renames of the nested fields before the renames of their parents, so the paths stay valid.
+    fn renames(&self) -> Vec<(&str, &str)> {
+        let mut renames: Vec<_> = self.rename.iter().map(|(path, name)| (path.as_str(), name.as_str())).collect();
+        renames.sort_by_key(|(path, _)| std::cmp::Reverse(path.matches('.').count()));
+        renames
+    }
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('.').collect()
+}
+
+/// The field at the path, `None` if a field is missing or a parent is not a struct.
+fn field_at<'a>(fields: &'a Fields, path: &[&str]) -> Option<&'a FieldRef> {
+    let (name, rest) = path.split_first()?;
+    let field = fields.iter().find(|field| field.name() == name)?;
+    match (rest.is_empty(), field.data_type()) {
+        (true, _) => Some(field),
+        (false, DataType::Struct(children)) => field_at(children, rest),
+        (false, _) => None,
+    }
+}
+
+/// Keeps the fields of the paths, with the nested fields of their paths only.
+fn select_fields(fields: &Fields, paths: &[&[&str]]) -> Fields {
+    fields.iter()
+        .filter_map(|field| {
+            let nested: Vec<&[&str]> = paths.iter()
+                .filter(|path| path[0] == field.name())
+                .map(|path| &path[1..])
+                .collect();
+            match field.data_type() {
+                _ if nested.is_empty() => None,
+                _ if nested.iter().any(|path| path.is_empty()) => Some(field.clone()),
+                DataType::Struct(children) => Some(Arc::new(
+                    field.as_ref().clone().with_data_type(DataType::Struct(select_fields(children, &nested))),
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Replaces the fields of the parent struct of the path with the result of `f` of the parent fields and the field name.
+fn update_parent(fields: &Fields, path: &[&str], f: &mut dyn FnMut(&Fields, &str) -> Fields) -> Fields {
+    match path {
+        [name] => f(fields, name),
+        [name, rest @ ..] => fields.iter()
+            .map(|field| match field.data_type() {
+                DataType::Struct(children) if field.name() == name => Arc::new(
+                    field.as_ref().clone().with_data_type(DataType::Struct(update_parent(children, rest, f))),
+                ),
+                _ => field.clone(),
+            })
+            .collect(),
+        [] => fields.clone(),
+    }
+}
+
+/// Appends the non struct fields, the nested ones named by their path and nullable if a parent is.
+fn flatten_fields(fields: &Fields, prefix: Option<&str>, separator: &str, nullable: bool, flattened: &mut Vec<FieldRef>) {
+    for field in fields {
+        let name = match prefix {
+            Some(prefix) => format!("{}{}{}", prefix, separator, field.name()),
+            None => field.name().clone(),
+        };
+        let nullable = nullable || field.is_nullable();
+        match field.data_type() {
+            DataType::Struct(children) => flatten_fields(children, Some(&name), separator, nullable, flattened),
+            _ => flattened.push(Arc::new(field.as_ref().clone().with_name(name).with_nullable(nullable))),
+        }
+    }
+}
+
+fn select_values(fields: &mut Map<String, JsonValue>, paths: &[&[&str]]) {
+    fields.retain(|name, value| {
+        let nested: Vec<&[&str]> = paths.iter()
+            .filter(|path| path[0] == name)
+            .map(|path| &path[1..])
+            .collect();
+        if nested.is_empty() {
+            return false;
+        }
+        if let (false, JsonValue::Object(children)) = (nested.iter().any(|path| path.is_empty()), value) {
+            select_values(children, &nested);
+        }
+        true
+    });
+}
+
+/// The object of the parent struct of the path, `None` if a parent is missing (null).
+fn parent_object<'a>(fields: &'a mut Map<String, JsonValue>, path: &[&str]) -> Option<&'a mut Map<String, JsonValue>> {
+    match path {
+        [_] => Some(fields),
+        [name, rest @ ..] => match fields.get_mut(*name)? {
+            JsonValue::Object(children) => parent_object(children, rest),
+            _ => None,
+        },
+        [] => None,
+    }
+}
+
+/// Moves the non null values of the non struct fields, the nested ones named by their path.
+/// The objects of the decoded messages are the struct fields (maps are decoded to lists of entries).
+fn flatten_values(fields: Map<String, JsonValue>, prefix: Option<&str>, separator: &str, flattened: &mut Map<String, JsonValue>) {
+    for (name, value) in fields {
+        let name = match prefix {
+            Some(prefix) => format!("{}{}{}", prefix, separator, name),
+            None => name,
+        };
+        match value {
+            JsonValue::Object(children) => flatten_values(children, Some(&name), separator, flattened),
+            // Null values are left out like missing ones, as the flattened fields of null structs
+            JsonValue::Null => {}
+            value => {
+                flattened.insert(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deltalake::arrow::datatypes::Field as ArrowField;
+    use serde_json::json;
+
+    use crate::record_batch_from_json;
+
+    use super::*;
+
+    fn schema() -> ArrowSchema {
+        let physical = DataType::Struct(vec![
+            ArrowField::new("age", DataType::Int32, true),
+            ArrowField::new("height", DataType::Float32, true),
+        ].into());
+        ArrowSchema::new(vec![
+            ArrowField::new("id", DataType::Int32, false),
+            ArrowField::new("name", DataType::Utf8, true),
+            ArrowField::new("details", DataType::Struct(vec![
+                ArrowField::new("physical", physical, true),
+                ArrowField::new("email", DataType::Utf8, true),
+            ].into()), true),
+        ])
+    }
+
+    fn person() -> JsonValue {
+        json!({"id": 1, "name": "a", "details": {"physical": {"age": 42, "height": 1.8}, "email": "a@b"}})
+    }
+
+    fn transformed(transforms: &FieldTransforms, value: JsonValue) -> (ArrowSchema, JsonValue) {
+        let schema = transforms.apply_to_schema(&schema()).unwrap();
+        let mut value = value;
+        transforms.apply(&mut value);
+        // The transformed values are decodable with the transformed schema
+        let batch = record_batch_from_json(Arc::new(schema.clone()), &[value.clone()]).unwrap();
+        assert_eq!(batch.schema().as_ref(), &schema);
+        (schema, value)
+    }
+
+    fn column_names(schema: &ArrowSchema) -> Vec<&str> {
+        schema.fields().iter().map(|field| field.name().as_str()).collect()
+    }
+
+    #[test]
+    fn selects_drops_and_renames_nested_fields() {
+        let transforms = FieldTransforms {
+            select: vec!["id".to_string(), "details.physical".to_string()],
+            drop: vec!["details.physical.height".to_string()],
+            rename: vec![
+                ("details".to_string(), "info".to_string()),
+                ("details.physical.age".to_string(), "age_years".to_string()),
+            ],
+            flatten: None,
+        };
+        let (schema, value) = transformed(&transforms, person());
+        assert_eq!(column_names(&schema), vec!["id", "info"]);
+        assert_eq!(schema.field_with_name("info").unwrap().data_type(), &DataType::Struct(vec![
+            ArrowField::new("physical", DataType::Struct(vec![ArrowField::new("age_years", DataType::Int32, true)].into()), true),
+        ].into()));
+        assert_eq!(value, json!({"id": 1, "info": {"physical": {"age_years": 42}}}));
+
+        // Missing (null) structs are skipped
+        let (_, value) = transformed(&transforms, json!({"id": 2, "details": null}));
+        assert_eq!(value, json!({"id": 2, "info": null}));
+    }
+
+    #[test]
+    fn flattens_nested_fields() {
+        let transforms = FieldTransforms {
+            rename: vec![("details.physical.age".to_string(), "age_years".to_string())],
+            flatten: Some("_".to_string()),
+            ..Default::default()
+        };
+        let (schema, value) = transformed(&transforms, person());
+        assert_eq!(column_names(&schema), vec!["id", "name", "details_physical_age_years", "details_physical_height", "details_email"]);
+        assert!(!schema.field_with_name("id").unwrap().is_nullable());
+        assert!(schema.field_with_name("details_email").unwrap().is_nullable());
+        assert_eq!(value, json!({
+            "id": 1, "name": "a", "details_physical_age_years": 42, "details_physical_height": 1.8, "details_email": "a@b",
+        }));
+
+        let (_, value) = transformed(&transforms, json!({"id": 2, "details": null}));
+        assert_eq!(value, json!({"id": 2}));
+    }
+
+    #[test]
+    fn invalid_transforms() {
+        let schema = schema();
+        let select = |path: &str| FieldTransforms { select: vec![path.to_string()], ..Default::default() };
+        assert!(select("details.email").apply_to_schema(&schema).is_ok());
+        assert!(select("details.phone").apply_to_schema(&schema).is_err());
+        assert!(select("name.first").apply_to_schema(&schema).is_err());
+        assert!(select("details.").validate().is_err());
+
+        let rename = |path: &str, name: &str| FieldTransforms {
+            rename: vec![(path.to_string(), name.to_string())],
+            ..Default::default()
+        };
+        assert!(rename("name", "id").apply_to_schema(&schema).is_err());
+        assert!(rename("name", "a.b").validate().is_err());
+
+        let flatten = FieldTransforms { flatten: Some("_".to_string()), ..rename("name", "details_email") };
+        assert!(flatten.apply_to_schema(&schema).is_err());
+        assert!(FieldTransforms { flatten: Some(String::new()), ..Default::default() }.validate().is_err());
+    }
+}
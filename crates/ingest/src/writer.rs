@@ -1,8 +1,39 @@
-use deltalake::arrow::datatypes::SchemaRef as ArrowSchemaRef;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use deltalake::{checkpoints, DeltaOps, DeltaTable, DeltaTableError};
+use deltalake::logstore::LogStoreRef;
+use deltalake::arrow::datatypes::{DataType, Field as ArrowField, Schema as ArrowSchema, SchemaRef as ArrowSchemaRef, TimeUnit};
 use deltalake::arrow::error::ArrowError;
 use deltalake::arrow::json::ReaderBuilder;
 use deltalake::arrow::record_batch::RecordBatch;
+use deltalake::datafusion::common::Column;
+use deltalake::datafusion::error::DataFusionError;
+use deltalake::datafusion::prelude::{Expr, SessionContext};
+use deltalake::kernel::{Action, Add, StructType, Txn};
+use deltalake::operations::cast::cast_record_batch;
+use deltalake::operations::delete::DeleteBuilder;
+use deltalake::operations::merge::{MergeBuilder, MergeMetrics};
+use deltalake::operations::transaction::{CommitBuilder, TransactionError};
+use deltalake::parquet::errors::ParquetError;
+use deltalake::parquet::file::properties::WriterProperties;
+use deltalake::protocol::{DeltaOperation, SaveMode};
+use deltalake::writer::{DeltaWriter, RecordBatchWriter};
+use schema_registry::RetryOptions;
 use serde_json::Value as JsonValue;
+use tracing::{debug, warn};
+
+use crate::SqlTransform;
+use crate::sql::PlannedSqlTransform;
+use crate::storage::register_object_stores;
+use crate::transactions::{self, TransactionLogStore};
+
+const SOURCE_ALIAS: &str = "source";
+const TARGET_ALIAS: &str = "target";
+
+/// Number of messages written at once when the data files are split by size.
+const TARGET_FILE_SIZE_CHUNK: usize = 1000;
 
 #[derive(Debug, thiserror::Error)]
 pub enum DataWriterError {
@@ -15,17 +46,519 @@ pub enum DataWriterError {
         source: ArrowError,
     },
 
+    /// Delta table operation returned an error.
+    #[error("Delta table interaction failed: {source}")]
+    DeltaTable {
+        /// The wrapped [`DeltaTableError`]
+        #[from]
+        source: DeltaTableError,
+    },
+
+    /// Parquet returned an error, e.g. on invalid writer properties.
+    #[error("Parquet interaction failed: {source}")]
+    Parquet {
+        /// The wrapped [`ParquetError`]
+        #[from]
+        source: ParquetError,
+    },
+
+    /// DataFusion returned an error, e.g. on invalid SQL transforms.
+    #[error("DataFusion interaction failed: {source}")]
+    DataFusion {
+        /// The wrapped [`DataFusionError`]
+        #[from]
+        source: DataFusionError,
+    },
+
     #[error("Unknown generic error")]
     Generic
 }
 
 
-pub struct DataWriter {
+/// The result of a [`DataWriter::flush_and_commit`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommitStats {
+    /// The committed table version.
+    pub version: i64,
+    /// Number of the written data files.
+    pub files: usize,
+    /// Total size of the written data files in bytes.
+    pub bytes: i64,
+}
 
+/// Options of the tables opened or created by a [`DataWriter`], existing tables are not altered.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableOptions {
+    /// Credentials and settings of the table object store, e.g. `AWS_REGION` or `GOOGLE_SERVICE_ACCOUNT`.
+    pub storage_options: HashMap<String, String>,
+    /// Table properties, e.g. `delta.logRetentionDuration`.
+    pub configuration: HashMap<String, String>,
+    /// The partition columns, the data files of each flush are split per partition.
+    pub partition_columns: Vec<String>,
+    /// Transforms the written and merged messages, a created table has the output schema of the query.
+    pub sql_transform: Option<SqlTransform>,
+}
+
+/// Writes decoded json messages to a delta table.
+///
+/// Messages are buffered in memory (as parquet) until [`DataWriter::flush_and_commit`] is called,
+/// which writes the buffered data files and commits them to the delta log.
+pub struct DataWriter {
+    table: DeltaTable,
+    writer: RecordBatchWriter,
+    /// Arrow schema of the decoded input messages (as derived from the proto schema).
+    input_schema: ArrowSchemaRef,
+    /// Transforms the batches of input messages to the table schema, planned once for all the batches.
+    sql_transform: Option<PlannedSqlTransform>,
+    /// Arrow schema of the table, the record batch writer drops the partition columns from its own after a write.
+    table_schema: ArrowSchemaRef,
+    commit_retry: RetryOptions,
+    /// Commits between checkpoints, overriding the table `delta.checkpointInterval`.
+    checkpoint_interval: Option<i64>,
+    /// The table version checkpoints are counted from.
+    checkpointed_version: i64,
+    /// Parquet properties of the files written by the merges and deletes too.
+    writer_properties: Option<WriterProperties>,
+    /// The size the buffered data is written to a file at, before the commit.
+    target_file_size: Option<usize>,
+    /// The files written (but not committed) when the buffered data reached the target file size.
+    written_files: Vec<Add>,
 }
 
 impl DataWriter {
+    /// Opens the delta table at `table_uri` or creates it from `input_schema` if it does not exist.
+    pub async fn try_new(
+        table_uri: &str,
+        input_schema: ArrowSchemaRef,
+        commit_retry: RetryOptions,
+    ) -> Result<Self, DataWriterError> {
+        Self::try_new_with_options(table_uri, input_schema, TableOptions::default(), commit_retry).await
+    }
+
+    /// Like [`DataWriter::try_new`], creating a missing table with the given [`TableOptions`]
+    /// (of the output schema of the SQL transform of the `input_schema` if set).
+    pub async fn try_new_with_options(
+        table_uri: &str,
+        input_schema: ArrowSchemaRef,
+        table_options: TableOptions,
+        commit_retry: RetryOptions,
+    ) -> Result<Self, DataWriterError> {
+        let sql_transform = match &table_options.sql_transform {
+            Some(transform) => Some(transform.plan(input_schema.clone()).await?),
+            None => None,
+        };
+        let output_schema = match &sql_transform {
+            Some(transform) => Arc::new(transform.output_schema()),
+            None => input_schema.clone(),
+        };
+        let delta_schema = StructType::try_from(&to_delta_compatible_schema(&output_schema))?;
+        register_object_stores();
+        let table = DeltaOps::try_from_uri_with_storage_options(table_uri, table_options.storage_options)
+            .await?
+            .create()
+            .with_save_mode(SaveMode::Ignore)
+            .with_columns(delta_schema.fields().to_vec())
+            .with_configuration(table_options.configuration.into_iter().map(|(k, v)| (k, Some(v))))
+            .with_partition_columns(table_options.partition_columns)
+            .await?;
+        let writer = RecordBatchWriter::for_table(&table)?;
+        let table_schema = writer.arrow_schema();
+        let checkpointed_version = table.version();
+
+        Ok(Self {
+            table,
+            writer,
+            input_schema,
+            sql_transform,
+            table_schema,
+            commit_retry,
+            checkpoint_interval: None,
+            checkpointed_version,
+            writer_properties: None,
+            target_file_size: None,
+            written_files: Vec::new(),
+        })
+    }
+
+    /// Writes the data files with the given parquet properties instead of the default (snappy compressed) ones.
+    pub fn with_writer_properties(mut self, writer_properties: WriterProperties) -> Self {
+        self.writer = self.writer.with_writer_properties(writer_properties.clone());
+        self.writer_properties = Some(writer_properties);
+        self
+    }
+
+    /// Splits the data of a commit into files of about `size` bytes, instead of a single file per partition.
+    pub fn with_target_file_size(mut self, size: usize) -> Self {
+        self.target_file_size = Some(size);
+        self
+    }
 
+    /// Creates a checkpoint every `interval` commits instead of the table `delta.checkpointInterval` (100 by default).
+    pub fn with_checkpoint_interval(mut self, interval: i64) -> Self {
+        self.checkpoint_interval = Some(interval);
+        self
+    }
+
+    #[inline]
+    pub fn table(&self) -> &DeltaTable {
+        &self.table
+    }
+
+    /// The size of the buffered (not flushed) data in bytes.
+    #[inline]
+    pub fn buffer_len(&self) -> usize {
+        self.writer.buffer_len()
+    }
+
+    /// Buffers the passed json messages, converted to the table schema.
+    pub async fn write(&mut self, json: &[JsonValue]) -> Result<(), DataWriterError> {
+        if json.is_empty() {
+            return Ok(());
+        }
+        let chunk_size = if self.target_file_size.is_some() { TARGET_FILE_SIZE_CHUNK } else { json.len() };
+        for chunk in json.chunks(chunk_size) {
+            let batch = table_record_batch(&self.input_schema, self.sql_transform.as_mut(), &self.table_schema, chunk).await?;
+            if batch.num_rows() == 0 {
+                continue;
+            }
+            self.writer.write(batch).await?;
+            if self.target_file_size.is_some_and(|size| self.writer.buffer_len() >= size) {
+                let files = self.writer.flush().await?;
+                self.written_files.extend(files);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes all the buffered data to the table and commits it.
+    /// Returns the committed table version and written files or `None` if there was nothing to commit.
+    ///
+    /// Failed commits (conflicts with concurrent writers, storage errors) are retried
+    /// with backoff according to the configured commit [`RetryOptions`].
+    pub async fn flush_and_commit(&mut self) -> Result<Option<CommitStats>, DataWriterError> {
+        self.flush_and_commit_transactions(HashMap::new()).await
+    }
+
+    /// Like [`DataWriter::flush_and_commit`], also recording the given application transaction versions
+    /// (e.g. the last written kafka offset per partition) atomically with the written data.
+    /// The transactions are committed alone when there is no buffered data.
+    pub async fn flush_and_commit_transactions(
+        &mut self,
+        app_transactions: HashMap<String, i64>,
+    ) -> Result<Option<CommitStats>, DataWriterError> {
+        let mut adds = std::mem::take(&mut self.written_files);
+        adds.extend(self.writer.flush().await?);
+        if adds.is_empty() && app_transactions.is_empty() {
+            return Ok(None);
+        }
+        let files = adds.len();
+        let bytes = adds.iter().map(|add| add.size).sum();
+        let actions = adds.into_iter()
+            .map(Action::Add)
+            .chain(transaction_actions(&app_transactions))
+            .collect();
+        let version = self.commit(actions).await?;
+        Ok(Some(CommitStats { version, files, bytes }))
+    }
+
+    /// Merges the passed json messages into the table on the `keys` columns and commits them, inserting the rows
+    /// of new keys and replacing the rows of existing keys. If `event_time` is set, rows are only replaced
+    /// by messages with the same or a newer event time.
+    ///
+    /// The messages must have unique keys. Failed commits are retried like [`DataWriter::flush_and_commit`],
+    /// merging the messages again into the latest table version.
+    /// Returns `None` if nothing was committed, e.g. when no rows were inserted or updated.
+    pub async fn merge(
+        &mut self,
+        json: &[JsonValue],
+        keys: &[String],
+        event_time: Option<&str>,
+    ) -> Result<Option<MergeMetrics>, DataWriterError> {
+        self.merge_with_transactions(json, keys, event_time, &HashMap::new()).await
+    }
+
+    /// Like [`DataWriter::merge`], also recording the given application transaction versions in the merge commit,
+    /// nothing is recorded if nothing is committed.
+    pub async fn merge_with_transactions(
+        &mut self,
+        json: &[JsonValue],
+        keys: &[String],
+        event_time: Option<&str>,
+        app_transactions: &HashMap<String, i64>,
+    ) -> Result<Option<MergeMetrics>, DataWriterError> {
+        if json.is_empty() {
+            return Ok(None);
+        }
+        let batch = table_record_batch(&self.input_schema, self.sql_transform.as_mut(), &self.table_schema, json).await?;
+        // All the messages were filtered out by the SQL transform
+        if batch.num_rows() == 0 {
+            return Ok(None);
+        }
+        let columns: Vec<String> = batch.schema().fields().iter().map(|f| f.name().clone()).collect();
+        let predicate = keys.iter()
+            .map(|key| merge_column(TARGET_ALIAS, key).eq(merge_column(SOURCE_ALIAS, key)))
+            .reduce(Expr::and)
+            .ok_or(DataWriterError::Generic)?;
+        let update_predicate = event_time.map(|column| {
+            merge_column(TARGET_ALIAS, column).is_null()
+                .or(merge_column(SOURCE_ALIAS, column).gt_eq(merge_column(TARGET_ALIAS, column)))
+        });
+
+        let mut attempt = 0;
+        loop {
+            let source = SessionContext::new().read_batch(batch.clone()).map_err(DeltaTableError::from)?;
+            let mut merge = MergeBuilder::new(self.transaction_log_store(app_transactions), self.table.snapshot()?.clone(), predicate.clone(), source)
+                .with_source_alias(SOURCE_ALIAS)
+                .with_target_alias(TARGET_ALIAS)
+                .when_matched_update(|update| {
+                    let update = match &update_predicate {
+                        Some(predicate) => update.predicate(predicate.clone()),
+                        None => update,
+                    };
+                    columns.iter().fold(update, |update, c| update.update(c.as_str(), merge_column(SOURCE_ALIAS, c)))
+                })?
+                .when_not_matched_insert(|insert| {
+                    columns.iter().fold(insert, |insert, c| insert.set(c.as_str(), merge_column(SOURCE_ALIAS, c)))
+                })?;
+            if let Some(writer_properties) = &self.writer_properties {
+                merge = merge.with_writer_properties(writer_properties.clone());
+            }
+            let res = merge.await;
+
+            match res {
+                Ok((table, metrics)) => {
+                    let committed = table.version() > self.table.version();
+                    self.set_table_state(table);
+                    if !committed {
+                        return Ok(None);
+                    }
+                    debug!("Merged {} rows to version {} of table {}", metrics.num_source_rows, self.table.version(), self.table.table_uri());
+                    self.checkpoint().await;
+                    return Ok(Some(metrics));
+                }
+                Err(e) if attempt < self.commit_retry.max_retries && is_retryable_commit_error(&e) => {
+                    let backoff = self.commit_retry.backoff(attempt);
+                    warn!("Merge into table {} failed (attempt {}), retrying in {:?}: {}",
+                        self.table.table_uri(), attempt + 1, backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    if let Err(e) = self.table.update().await {
+                        warn!("Failed to reload table {}: {}", self.table.table_uri(), e);
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Deletes the committed rows (not the buffered ones) matching the predicate.
+    /// Returns the number of deleted rows.
+    ///
+    /// Failed commits are retried like [`DataWriter::flush_and_commit`], deleting the rows again from the latest
+    /// table version.
+    pub async fn delete(&mut self, predicate: Expr) -> Result<usize, DataWriterError> {
+        Ok(self.delete_with_transactions(predicate, &HashMap::new()).await?.unwrap_or_default())
+    }
+
+    /// Like [`DataWriter::delete`], also recording the given application transaction versions in the delete commit. Returns `None` if nothing was committed (no matching rows), recording nothing.
+    pub async fn delete_with_transactions(
+        &mut self,
+        predicate: Expr,
+        app_transactions: &HashMap<String, i64>,
+    ) -> Result<Option<usize>, DataWriterError> {
+        let mut attempt = 0;
+        loop {
+            let mut delete = DeleteBuilder::new(self.transaction_log_store(app_transactions), self.table.snapshot()?.clone())
+                .with_predicate(predicate.clone());
+            if let Some(writer_properties) = &self.writer_properties {
+                delete = delete.with_writer_properties(writer_properties.clone());
+            }
+            let res = delete.await;
+
+            match res {
+                Ok((table, metrics)) => {
+                    let committed = table.version() > self.table.version();
+                    self.set_table_state(table);
+                    if !committed {
+                        return Ok(None);
+                    }
+                    self.checkpoint().await;
+                    return Ok(Some(metrics.num_deleted_rows.unwrap_or_default()));
+                }
+                Err(e) if attempt < self.commit_retry.max_retries && is_retryable_commit_error(&e) => {
+                    let backoff = self.commit_retry.backoff(attempt);
+                    warn!("Delete from table {} failed (attempt {}), retrying in {:?}: {}",
+                        self.table.table_uri(), attempt + 1, backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    if let Err(e) = self.table.update().await {
+                        warn!("Failed to reload table {}: {}", self.table.table_uri(), e);
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// The latest transaction versions of the given applications recorded in the delta log,
+    /// after loading the commits of any concurrent writers.
+    pub async fn app_transaction_versions(&mut self, app_ids: &HashSet<String>) -> Result<HashMap<String, i64>, DataWriterError> {
+        self.table.update().await?;
+        Ok(app_transaction_versions(&self.table, app_ids).await?)
+    }
+
+    /// The table log store adding the `txn` actions of the application transactions to the next commit.
+    fn transaction_log_store(&self, app_transactions: &HashMap<String, i64>) -> LogStoreRef {
+        Arc::new(TransactionLogStore::new(self.table.log_store(), transaction_actions(app_transactions)))
+    }
+
+    /// Takes the state of a table committed through [`DataWriter::transaction_log_store`], keeping the table log store.
+    fn set_table_state(&mut self, table: DeltaTable) {
+        let mut updated = DeltaTable::new(self.table.log_store(), self.table.config.clone());
+        updated.state = table.state;
+        self.table = updated;
+    }
+
+    async fn commit(&mut self, actions: Vec<Action>) -> Result<i64, DataWriterError> {
+        let mut attempt = 0;
+        loop {
+            let partition_cols = self.table.metadata()?.partition_columns.clone();
+            let operation = DeltaOperation::Write {
+                mode: SaveMode::Append,
+                partition_by: (!partition_cols.is_empty()).then_some(partition_cols),
+                predicate: None,
+            };
+            let res = CommitBuilder::default()
+                .with_actions(actions.clone())
+                .build(Some(self.table.snapshot()?), self.table.log_store(), operation)
+                .map_err(DeltaTableError::from)?
+                .await;
+
+            match res {
+                Ok(commit) => {
+                    let version = commit.version();
+                    self.table.update().await?;
+                    debug!("Committed version {} to table {}", version, self.table.table_uri());
+                    self.checkpoint().await;
+                    return Ok(version);
+                }
+                Err(e) if attempt < self.commit_retry.max_retries && is_retryable_commit_error(&e) => {
+                    let backoff = self.commit_retry.backoff(attempt);
+                    warn!("Commit to table {} failed (attempt {}), retrying in {:?}: {}",
+                        self.table.table_uri(), attempt + 1, backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    // Reload the table state so that the next attempt is based on the winning commits
+                    if let Err(e) = self.table.update().await {
+                        warn!("Failed to reload table {}: {}", self.table.table_uri(), e);
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Creates a checkpoint of the latest table version once the checkpoint interval is reached by the commits
+    /// since the last one, then deletes the expired log files (older than the table `delta.logRetentionDuration`,
+    /// 30 days by default) unless the table `delta.enableExpiredLogCleanup` is false.
+    ///
+    /// Failures are only logged, the checkpoint is created again by the next commit.
+    async fn checkpoint(&mut self) {
+        let version = self.table.version();
+        let Ok(snapshot) = self.table.snapshot() else {
+            return;
+        };
+        let config = snapshot.table_config();
+        let interval = self.checkpoint_interval.unwrap_or(config.checkpoint_interval() as i64).max(1);
+        let cleanup = config.enable_expired_log_cleanup();
+        if version / interval <= self.checkpointed_version / interval {
+            return;
+        }
+        if let Err(e) = checkpoints::create_checkpoint(&self.table).await {
+            warn!("Failed to create checkpoint of version {} of table {}: {}", version, self.table.table_uri(), e);
+            return;
+        }
+        debug!("Created checkpoint of version {} of table {}", version, self.table.table_uri());
+        self.checkpointed_version = version;
+        if cleanup {
+            match checkpoints::cleanup_metadata(&self.table).await {
+                Ok(deleted) => debug!("Deleted {} expired log files of table {}", deleted, self.table.table_uri()),
+                Err(e) => warn!("Failed to delete the expired log files of table {}: {}", self.table.table_uri(), e),
+            }
+        }
+    }
+}
+
+/// The latest transaction versions of the given applications, as of the loaded table version.
+/// Applications without transactions are missing from the result.
+pub(crate) async fn app_transaction_versions(
+    table: &DeltaTable,
+    app_ids: &HashSet<String>,
+) -> Result<HashMap<String, i64>, DeltaTableError> {
+    let mut versions = transactions::app_transaction_versions(table).await?;
+    versions.retain(|app_id, _| app_ids.contains(app_id));
+    Ok(versions)
+}
+
+fn transaction_actions(app_transactions: &HashMap<String, i64>) -> Vec<Action> {
+    let last_updated = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_millis() as i64);
+    app_transactions.iter()
+        .map(|(app_id, version)| Action::Txn(Txn { app_id: app_id.clone(), version: *version, last_updated }))
+        .collect()
+}
+
+/// A column of the source or target table of a merge.
+fn merge_column(alias: &'static str, name: &str) -> Expr {
+    Expr::Column(Column::new(Some(alias), name))
+}
+
+/// Commit errors caused by concurrent writers or transient storage failures.
+pub(crate) fn is_retryable_commit_error(e: &DeltaTableError) -> bool {
+    match e {
+        DeltaTableError::VersionAlreadyExists(_) | DeltaTableError::ObjectStore { .. } => true,
+        DeltaTableError::Transaction { source } => matches!(source,
+            TransactionError::CommitConflict(_)
+            | TransactionError::MaxCommitAttempts(_)
+            | TransactionError::ObjectStore { .. }),
+        _ => false,
+    }
+}
+
+/// Converts the arrow schema derived from a proto schema to one that can be represented as a delta table schema.
+/// Delta tables only support microsecond UTC timestamps.
+pub fn to_delta_compatible_schema(schema: &ArrowSchema) -> ArrowSchema {
+    let fields: Vec<ArrowField> = schema.fields().iter()
+        .map(|f| to_delta_compatible_field(f))
+        .collect();
+    ArrowSchema::new_with_metadata(fields, schema.metadata().clone())
+}
+
+fn to_delta_compatible_field(field: &ArrowField) -> ArrowField {
+    let data_type = match field.data_type() {
+        DataType::Timestamp(_, _) => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+        DataType::Struct(fields) => {
+            DataType::Struct(fields.iter().map(|f| to_delta_compatible_field(f)).collect::<Vec<_>>().into())
+        }
+        DataType::List(f) => DataType::List(Arc::new(to_delta_compatible_field(f))),
+        other => other.clone(),
+    };
+    field.clone().with_data_type(data_type)
+}
+
+/// The messages converted to the table schema, through the SQL transform if set.
+/// Borrows the fields rather than the (not `Sync`) writer, keeping the write futures `Send`.
+async fn table_record_batch(
+    input_schema: &ArrowSchemaRef,
+    sql_transform: Option<&mut PlannedSqlTransform>,
+    table_schema: &ArrowSchemaRef,
+    json: &[JsonValue],
+) -> Result<RecordBatch, DataWriterError> {
+    let batch = record_batch_from_json(input_schema.clone(), json)?;
+    let batch = match sql_transform {
+        Some(transform) => transform.apply(batch).await?,
+        None => batch,
+    };
+    Ok(cast_record_batch(&batch, table_schema.clone(), false, true)?)
 }
 
 /// Creates an Arrow RecordBatch from the passed JSON buffer.
@@ -38,4 +571,198 @@ pub fn record_batch_from_json(
     decoder
         .flush()?
         .ok_or(DataWriterError::Generic)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use deltalake::arrow::array::{Array, TimestampMicrosecondArray};
+    use deltalake::DeltaConfigKey;
+    use serde_json::json;
+
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    fn input_schema() -> ArrowSchemaRef {
+        Arc::new(ArrowSchema::new(vec![
+            ArrowField::new("id", DataType::Int32, true),
+            ArrowField::new("age", DataType::UInt32, true),
+            ArrowField::new("created_date", DataType::Timestamp(TimeUnit::Millisecond, None), true),
+        ]))
+    }
+
+    #[test]
+    fn delta_compatible_schema_converts_timestamps() {
+        let schema = ArrowSchema::new(vec![
+            ArrowField::new("created_date", DataType::Timestamp(TimeUnit::Millisecond, None), true),
+            ArrowField::new("details", DataType::Struct(vec![
+                ArrowField::new("updated_date", DataType::Timestamp(TimeUnit::Millisecond, None), true),
+            ].into()), true),
+        ]);
+        let utc = DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()));
+
+        let schema = to_delta_compatible_schema(&schema);
+        assert_eq!(schema.field(0).data_type(), &utc);
+        assert_eq!(schema.field(1).data_type(), &DataType::Struct(vec![
+            ArrowField::new("updated_date", utc.clone(), true),
+        ].into()));
+    }
+
+    #[tokio::test]
+    async fn write_and_commit_to_new_table() {
+        let dir = TestDir::new("writer");
+        let uri = dir.to_str().unwrap();
+
+        let mut writer = DataWriter::try_new(uri, input_schema(), RetryOptions::no_retries()).await.unwrap();
+        assert_eq!(writer.flush_and_commit().await.unwrap(), None);
+
+        writer.write(&[
+            json!({"id": 1, "age": 30, "created_date": 1715276726099_i64}),
+            json!({"id": 2}),
+        ]).await.unwrap();
+        assert!(writer.buffer_len() > 0);
+        let stats = writer.flush_and_commit().await.unwrap().unwrap();
+        assert_eq!((stats.version, stats.files), (1, 1));
+        assert!(stats.bytes > 0);
+        assert_eq!(writer.table().version(), 1);
+
+        // Transaction versions are committed along with the data
+        writer.write(&[json!({"id": 3})]).await.unwrap();
+        let txns = HashMap::from([("group-topic-0".to_string(), 42)]);
+        assert_eq!(writer.flush_and_commit_transactions(txns).await.unwrap().unwrap().version, 2);
+        let app_ids = HashSet::from(["group-topic-0".to_string(), "group-topic-1".to_string()]);
+        let versions = writer.app_transaction_versions(&app_ids).await.unwrap();
+        assert_eq!(versions, HashMap::from([("group-topic-0".to_string(), 42)]));
+
+        // Re-opening an existing table does not recreate it
+        let writer = DataWriter::try_new(uri, input_schema(), RetryOptions::no_retries()).await.unwrap();
+        assert_eq!(writer.table().version(), 2);
+        assert_eq!(writer.table().get_files_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn checkpoints_and_cleans_up_expired_logs() {
+        let dir = TestDir::new("checkpoint");
+        let uri = dir.to_str().unwrap();
+
+        let table_options = TableOptions {
+            configuration: HashMap::from([
+                (DeltaConfigKey::LogRetentionDuration.as_ref().to_string(), "interval 0 seconds".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let mut writer = DataWriter::try_new_with_options(uri, input_schema(), table_options, RetryOptions::no_retries())
+            .await.unwrap()
+            .with_checkpoint_interval(3);
+        for id in 1..=4 {
+            writer.write(&[json!({"id": id})]).await.unwrap();
+            writer.flush_and_commit().await.unwrap();
+        }
+
+        let log_dir = dir.join("_delta_log");
+        assert!(log_dir.join("00000000000000000003.checkpoint.parquet").exists());
+        assert!(!log_dir.join("00000000000000000002.json").exists());
+        assert!(log_dir.join("00000000000000000004.json").exists());
+        let table = deltalake::open_table(uri).await.unwrap();
+        assert_eq!((table.version(), table.get_files_count()), (4, 4));
+    }
+
+    #[tokio::test]
+    async fn keeps_transactions_of_merges_and_deletes_in_checkpoints() {
+        let dir = TestDir::new("transactions");
+        let uri = dir.to_str().unwrap();
+        let log_dir = dir.join("_delta_log");
+        let commit = |version: i64| std::fs::read_to_string(log_dir.join(format!("{:020}.json", version))).unwrap();
+        let keys = ["id".to_string()];
+
+        let table_options = TableOptions {
+            configuration: HashMap::from([
+                (DeltaConfigKey::LogRetentionDuration.as_ref().to_string(), "interval 0 seconds".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let mut writer = DataWriter::try_new_with_options(uri, input_schema(), table_options, RetryOptions::no_retries())
+            .await.unwrap()
+            .with_checkpoint_interval(3);
+        let txns = HashMap::from([("group-topic-0".to_string(), 10)]);
+        writer.merge_with_transactions(&[json!({"id": 1}), json!({"id": 2})], &keys, None, &txns).await.unwrap().unwrap();
+        assert!(commit(1).contains(r#"{"txn":{"appId":"group-topic-0","version":10"#));
+        let predicate = |id: i32| Expr::Column(Column::from_name("id")).eq(deltalake::datafusion::prelude::lit(id));
+        let txns = HashMap::from([("group-topic-1".to_string(), 20)]);
+        assert_eq!(writer.delete_with_transactions(predicate(1), &txns).await.unwrap(), Some(1));
+        assert!(commit(2).contains(r#"{"txn":{"appId":"group-topic-1","version":20"#));
+
+        // The checkpoint keeps the transactions of the commits removed by the log cleanup
+        writer.write(&[json!({"id": 3})]).await.unwrap();
+        let txns = HashMap::from([("group-topic-0".to_string(), 11)]);
+        assert_eq!(writer.flush_and_commit_transactions(txns).await.unwrap().unwrap().version, 3);
+        assert!(log_dir.join("00000000000000000003.checkpoint.parquet").exists());
+        assert!(!log_dir.join("00000000000000000002.json").exists());
+        let txns = HashMap::from([("group-topic-2".to_string(), 30)]);
+        assert_eq!(writer.delete_with_transactions(predicate(3), &txns).await.unwrap(), Some(1));
+
+        let table = deltalake::open_table(uri).await.unwrap();
+        assert_eq!((table.version(), table.get_files_count()), (4, 1));
+        let app_ids = HashSet::from(["group-topic-0".to_string(), "group-topic-1".to_string(), "group-topic-2".to_string()]);
+        assert_eq!(app_transaction_versions(&table, &app_ids).await.unwrap(), HashMap::from([
+            ("group-topic-0".to_string(), 11),
+            ("group-topic-1".to_string(), 20),
+            ("group-topic-2".to_string(), 30),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn writes_files_of_target_size_with_writer_properties() {
+        use deltalake::parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let dir = TestDir::new("target-size");
+        let uri = dir.to_str().unwrap();
+
+        let parquet = crate::ParquetOptions { bloom_filter_columns: vec!["id".to_string()], ..Default::default() };
+        let mut writer = DataWriter::try_new(uri, input_schema(), RetryOptions::no_retries()).await.unwrap()
+            .with_writer_properties(parquet.writer_properties().unwrap())
+            .with_target_file_size(1);
+        let messages: Vec<JsonValue> = (0..2500).map(|id| json!({"id": id})).collect();
+        writer.write(&messages).await.unwrap();
+        let stats = writer.flush_and_commit().await.unwrap().unwrap();
+        assert_eq!((stats.version, stats.files), (1, 3));
+
+        let path = writer.table().get_file_uris().unwrap().next().unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(path).unwrap()).unwrap();
+        let row_group = reader.metadata().row_group(0);
+        assert!(row_group.column(0).bloom_filter_offset().is_some());
+        assert!(row_group.column(1).bloom_filter_offset().is_none());
+    }
+
+    #[tokio::test]
+    async fn retries_conflicting_deletes() {
+        let dir = TestDir::new("delete");
+        let uri = dir.to_str().unwrap();
+        let predicate = Expr::Column(Column::from_name("id")).eq(deltalake::datafusion::prelude::lit(1));
+
+        let mut writer = DataWriter::try_new(uri, input_schema(), RetryOptions::no_retries()).await.unwrap();
+        writer.write(&[json!({"id": 1}), json!({"id": 2})]).await.unwrap();
+        writer.flush_and_commit().await.unwrap();
+
+        // A concurrent writer appends rows matching the predicate of the stale writers
+        let mut stale = DataWriter::try_new(uri, input_schema(), RetryOptions::no_retries()).await.unwrap();
+        let mut retrying = DataWriter::try_new(uri, input_schema(), RetryOptions { max_retries: 2, ..Default::default() })
+            .await.unwrap();
+        writer.write(&[json!({"id": 1})]).await.unwrap();
+        writer.flush_and_commit().await.unwrap();
+
+        assert!(stale.delete(predicate.clone()).await.is_err());
+        assert_eq!(retrying.delete(predicate).await.unwrap(), 2);
+        assert_eq!(retrying.table().version(), 3);
+    }
+
+    #[test]
+    fn json_timestamps_are_cast_to_micros() {
+        let batch = record_batch_from_json(input_schema(), &[json!({"id": 1, "created_date": 1715276726099_i64})]).unwrap();
+        let target = Arc::new(to_delta_compatible_schema(&input_schema()));
+        let batch = cast_record_batch(&batch, target, false, true).unwrap();
+        let created = batch.column(2).as_any().downcast_ref::<TimestampMicrosecondArray>().unwrap();
+        assert_eq!(created.value(0), 1715276726099000);
+        assert!(batch.column(1).is_null(0));
+    }
+}
@@ -5,7 +5,6 @@ use crate::SchemaRegistryError;
 
 /// Converts a protobuf compiled schema to arrow schema.
 /// This function uses the protofish library compiled [`Context`] and top level message [`MessageInfo`].
-
 pub(crate) fn to_arrow_schema(ctx: &Context, info: &MessageInfo) -> Result<ArrowSchema, SchemaRegistryError> {
     let mut fields = vec![];
     for field in info.iter_fields() {
@@ -69,7 +68,7 @@ pub(crate) fn message_field_to_arrow(ctx: &Context, info: &MessageField) -> Resu
         ValueType::Message(info) => {
             let info = ctx.resolve_message(info);
 
-            if let Some(ty) = try_map_as_well_known_type(&info) {
+            if let Some(ty) = try_map_as_well_known_type(info) {
                 ty
             } else {
                 let mut fields = vec![];
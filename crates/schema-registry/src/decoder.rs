@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use serde_json::Value as JsonValue;
+
+use crate::proto_schema::ProtoSchema;
+use crate::registry::{SchemaRegistry, SchemaRegistryError};
+
+/// A protobuf message decoded from a schema registry encoded payload.
+#[derive(Debug, Clone)]
+pub struct DecodedMessage {
+    /// The schema registry id of the writer schema.
+    pub schema_id: u32,
+    /// The full name of the decoded message type (e.g. `example.Person`).
+    pub full_name: String,
+    /// The compiled writer schema.
+    pub schema: Arc<ProtoSchema>,
+    /// The decoded message.
+    pub value: JsonValue,
+}
+
+/// Decodes confluent schema registry protobuf payloads (magic byte, schema id, message indexes and message data)
+/// using the schemas (and cache) of a [`SchemaRegistry`].
+pub struct ProtoDecoder {
+    registry: Arc<SchemaRegistry>,
+}
+
+impl ProtoDecoder {
+    pub fn new(registry: Arc<SchemaRegistry>) -> Self {
+        Self { registry }
+    }
+
+    #[inline]
+    pub fn registry(&self) -> &Arc<SchemaRegistry> {
+        &self.registry
+    }
+
+    pub async fn decode(&self, payload: &[u8]) -> Result<DecodedMessage, SchemaRegistryError> {
+        let (schema_id, index, data) = split_payload(payload)?;
+        let schema = self.registry.proto_schema_of(schema_id).await?;
+        let full_name = schema.message_name(&index)
+            .ok_or(SchemaRegistryError::CompileMessageNotFound(index))?
+            .to_string();
+        let value = schema.decode_message_to_json(&full_name, data)?;
+
+        Ok(DecodedMessage {
+            schema_id,
+            full_name,
+            schema,
+            value,
+        })
+    }
+}
+
+/// Splits a confluent protobuf payload to the schema id, the message index and the message data.
+pub(crate) fn split_payload(payload: &[u8]) -> Result<(u32, Vec<i32>, &[u8]), SchemaRegistryError> {
+    if payload.len() < 6 || payload[0] != 0 {
+        return Err(SchemaRegistryError::InvalidPayload("Missing magic byte or schema id".to_string()));
+    }
+    let schema_id = u32::from_be_bytes([payload[1], payload[2], payload[3], payload[4]]);
+
+    let mut pos = 5;
+    let count = read_zigzag_varint(payload, &mut pos)?;
+    let index = if count == 0 {
+        // Optimization for the first message of the schema
+        vec![0]
+    } else {
+        (0..count)
+            .map(|_| read_zigzag_varint(payload, &mut pos))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    Ok((schema_id, index, &payload[pos..]))
+}
+
+fn read_zigzag_varint(bytes: &[u8], pos: &mut usize) -> Result<i32, SchemaRegistryError> {
+    let mut value: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = *bytes.get(*pos)
+            .ok_or_else(|| SchemaRegistryError::InvalidPayload("Truncated message index".to_string()))?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((value >> 1) as i32) ^ -((value & 1) as i32));
+        }
+    }
+    Err(SchemaRegistryError::InvalidPayload("Invalid message index varint".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use schema_registry_converter::async_impl::schema_registry::SrSettings;
+    use serde_json::json;
+
+    use crate::proto_schema::tests::nested_polymorphic_schema;
+
+    use super::*;
+
+    #[test]
+    fn split_payloads() {
+        let (id, index, data) = split_payload(&[0, 0, 0, 0, 80, 0, 8, 1]).unwrap();
+        assert_eq!(id, 80);
+        assert_eq!(index, vec![0]);
+        assert_eq!(data, &[8, 1]);
+
+        // Two indexes [1, 0] zigzag encoded
+        let (id, index, data) = split_payload(&[0, 0, 0, 1, 0, 4, 2, 0, 8]).unwrap();
+        assert_eq!(id, 256);
+        assert_eq!(index, vec![1, 0]);
+        assert_eq!(data, &[8]);
+
+        assert!(split_payload(&[1, 0, 0, 0, 80, 0]).is_err());
+        assert!(split_payload(&[0, 0, 0, 0, 80]).is_err());
+        assert!(split_payload(&[0, 0, 0, 0, 80, 4, 2]).is_err());
+    }
+
+    #[tokio::test]
+    async fn decode_registry_payload() {
+        let registry = SchemaRegistry::new(SrSettings::new("http://localhost:1/".to_string()));
+        registry.insert_raw_schemas(80, nested_polymorphic_schema()).unwrap();
+        let decoder = ProtoDecoder::new(Arc::new(registry));
+
+        // Person { id: 1, name: "John" }
+        let mut payload = vec![0, 0, 0, 0, 80, 0];
+        payload.extend_from_slice(&[8, 1, 18, 4, b'J', b'o', b'h', b'n']);
+
+        let decoded = decoder.decode(&payload).await.unwrap();
+        assert_eq!(decoded.schema_id, 80);
+        assert_eq!(decoded.full_name, "example.Person");
+        assert_eq!(decoded.value, json!({"id": 1, "name": "John"}));
+    }
+}
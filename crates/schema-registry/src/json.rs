@@ -42,7 +42,7 @@ pub(crate) fn try_decode_json_as_well_known_type(_ctx: &Context, info: &MessageI
     match info.full_name.as_str() {
         // Timestamps as Number(i64) in milliseconds
         "google.protobuf.Timestamp" => {
-            let seconds = if let Some(seconds) = value.fields.get(0) {
+            let seconds = if let Some(seconds) = value.fields.first() {
                 if let Value::Int64(v) = seconds.value {
                     v
                 } else {
@@ -99,10 +99,10 @@ pub(crate) fn decode_field_to_json(ctx: &Context, field: FieldValue, _parent_ful
         Value::Message(v) => {
             let info = ctx.resolve_message(v.msg_ref);
 
-            if let Some(well_known_type) = try_decode_json_as_well_known_type(ctx, &info, &v) {
+            if let Some(well_known_type) = try_decode_json_as_well_known_type(ctx, info, &v) {
                 Ok(well_known_type)
             } else {
-                decode_message_to_json(ctx, &info, *v)
+                decode_message_to_json(ctx, info, *v)
             }
         }
         Value::Packed(packed_array) => {
@@ -176,7 +176,7 @@ mod tests {
     use crate::ProtoSchema;
     #[test]
     fn simple_schema_message_to_json() {
-        let proto_schema = ProtoSchema::try_compile_with_full_name("example.Person".to_string(), simple_schema_sample().as_slice());
+        let proto_schema = ProtoSchema::try_compile_with_full_name("example.Person", simple_schema_sample().as_slice());
         let proto_schema = proto_schema.expect("A valid proto3 raw schema");
 
         let expected_json = json!({
@@ -210,7 +210,7 @@ mod tests {
         let TypeInfo::Enum(wrapped_status) = proto_schema.context.get_type("example.WrappedStatus.Enum").unwrap() else { panic!("Expected enum WrappedStatus type info") };
 
         let proto_value = MessageValue {
-            msg_ref: msg.self_ref.clone(),
+            msg_ref: msg.self_ref,
             garbage: None,
             fields: vec![
                 FieldValue {
@@ -232,7 +232,7 @@ mod tests {
                 FieldValue {
                     number: 6,
                     value: Value::Message(Box::new(MessageValue {
-                        msg_ref: msg_detail.self_ref.clone(),
+                        msg_ref: msg_detail.self_ref,
                         garbage: None,
                         fields: vec![
                             FieldValue {
@@ -249,7 +249,7 @@ mod tests {
                 FieldValue {
                     number: 7,
                     value: Value::Message(Box::new(MessageValue {
-                        msg_ref: msg_contact.self_ref.clone(),
+                        msg_ref: msg_contact.self_ref,
                         garbage: None,
                         fields: vec![
                             FieldValue {
@@ -270,7 +270,7 @@ mod tests {
                 FieldValue {
                     number: 7,
                     value: Value::Message(Box::new(MessageValue {
-                        msg_ref: msg_contact.self_ref.clone(),
+                        msg_ref: msg_contact.self_ref,
                         garbage: None,
                         fields: vec![
                             FieldValue {
@@ -291,14 +291,14 @@ mod tests {
                 FieldValue {
                     number: 8,
                     value: Value::Enum(EnumValue {
-                        enum_ref: wrapped_status.self_ref.clone(),
+                        enum_ref: wrapped_status.self_ref,
                         value: 1,
                     }),
                 },
                 FieldValue {
                     number: 8,
                     value: Value::Enum(EnumValue {
-                        enum_ref: wrapped_status.self_ref.clone(),
+                        enum_ref: wrapped_status.self_ref,
                         value: 2,
                     }),
                 },
@@ -308,7 +308,7 @@ mod tests {
                 },
             ],
         };
-        let proto_value = proto_value.encode(&proto_schema.context());
+        let proto_value = proto_value.encode(proto_schema.context());
 
         let json = proto_schema.decode_to_json(proto_value.as_ref()).unwrap();
         assert_eq!(json, expected_json);
@@ -317,7 +317,7 @@ mod tests {
 
     #[test]
     fn nested_polymorphic_schema_message_to_json() {
-        let proto_schema = ProtoSchema::try_compile_with_full_name("example.Person".to_string(), nested_polymorphic_schema().as_slice());
+        let proto_schema = ProtoSchema::try_compile_with_full_name("example.Person", nested_polymorphic_schema().as_slice());
         let proto_schema = proto_schema.expect("A valid proto3 raw schema");
 
         let expected_json = json!({
@@ -335,7 +335,7 @@ mod tests {
                         "physical": {
                             "type": "PHYSICAL",
                             "age": 30,
-                            "created_date": JsonValue::Number((1715276726099 as i64).into()),
+                            "created_date": JsonValue::Number(1715276726099_i64.into()),
                             "created_by": "123e4567-e89b-12d3-a456-426614174000"
                         }
                     }
@@ -348,13 +348,13 @@ mod tests {
         let TypeInfo::Message(timestamp) = proto_schema.context.get_type("google.protobuf.Timestamp").unwrap()
             else { panic!("Expected message Timestamp type info") };
         let physical_value = MessageValue {
-            msg_ref: msg_physical.self_ref.clone(),
+            msg_ref: msg_physical.self_ref,
             garbage: None,
             fields: vec![
                 FieldValue {
                     number: 1,
                     value: Value::Enum(EnumValue {
-                        enum_ref: details_type.self_ref.clone(),
+                        enum_ref: details_type.self_ref,
                         value: 1,
                     }),
                 },
@@ -365,7 +365,7 @@ mod tests {
                 FieldValue {
                     number: 3,
                     value: Value::Message(Box::new(MessageValue {
-                        msg_ref: timestamp.self_ref.clone(),
+                        msg_ref: timestamp.self_ref,
                         garbage: None,
                         fields: vec![
                             FieldValue {
@@ -392,7 +392,7 @@ mod tests {
         let TypeInfo::Enum(status) = proto_schema.context.get_type("example.Status.Enum").unwrap() else { panic!("Expected enum Status type info") };
 
         let proto_value = MessageValue {
-            msg_ref: msg.self_ref.clone(),
+            msg_ref: msg.self_ref,
             garbage: None,
             fields: vec![
                 FieldValue {
@@ -406,14 +406,14 @@ mod tests {
                 FieldValue {
                     number: 3,
                     value: Value::Enum(EnumValue {
-                        enum_ref: status.self_ref.clone(),
+                        enum_ref: status.self_ref,
                         value: 1,
                     }),
                 },
                 FieldValue {
                     number: 4,
                     value: Value::Message(Box::new(MessageValue {
-                        msg_ref: proto_schema.context.get_message("example.Contact").unwrap().self_ref.clone(),
+                        msg_ref: proto_schema.context.get_message("example.Contact").unwrap().self_ref,
                         garbage: None,
                         fields: vec![
                             FieldValue {
@@ -435,7 +435,7 @@ mod tests {
                 FieldValue {
                     number: 5,
                     value: Value::Message(Box::new(MessageValue {
-                        msg_ref: msg_detail.self_ref.clone(),
+                        msg_ref: msg_detail.self_ref,
                         garbage: None,
                         fields: vec![
                             FieldValue {
@@ -448,7 +448,7 @@ mod tests {
             ],
         };
 
-        let proto_value = proto_value.encode(&proto_schema.context());
+        let proto_value = proto_value.encode(proto_schema.context());
         let json = proto_schema.decode_to_json(proto_value.as_ref()).unwrap();
         assert_eq!(json, expected_json);
     }
@@ -4,10 +4,22 @@ mod proto_schema;
 mod proto_resolver;
 mod arrow;
 mod json;
+mod decoder;
+mod retry;
+mod settings;
+mod subject;
 
 pub use proto_schema::ProtoSchema;
+pub use decoder::{DecodedMessage, ProtoDecoder};
+pub use retry::{retry, RetryOptions};
+pub use settings::{RegistryAuth, RegistrySettings};
+pub use subject::SubjectStrategy;
 pub use registry::{
     SchemaRegistryError,
     SchemaRegistry
 };
 
+
+// Re-exports
+pub use schema_registry_converter::async_impl::schema_registry::SrSettings;
+pub use schema_registry_converter::schema_registry_common::SubjectNameStrategy;
@@ -4,8 +4,11 @@ use logos::Logos;
 use crate::registry::SchemaRegistryError;
 
 pub struct ProtoInfo {
+    #[allow(dead_code)]
     package: Option<String>,
     imports: Vec<String>,
+    /// Message indexes (as encoded in confluent payloads) and the respective full message names.
+    messages: Vec<(Vec<i32>, String)>,
 }
 
 impl ProtoInfo {
@@ -13,18 +16,27 @@ impl ProtoInfo {
     pub fn imports(&self) -> &[String] {
         self.imports.as_slice()
     }
+
+    #[inline]
+    pub fn messages(&self) -> &[(Vec<i32>, String)] {
+        self.messages.as_slice()
+    }
 }
 
-/// Resolver that parses proto schema files and finds packages and imports.
+/// Resolver that parses proto schema files and finds packages, imports and message indexes.
 pub struct ProtoResolver;
 
 impl ProtoResolver {
 
     pub fn resolve(raw_schema: &str) -> Result<ProtoInfo, SchemaRegistryError> {
         let resolver = ResolverHelper::new(raw_schema);
+        let messages = resolver.indexes.iter()
+            .map(|index| (index.clone(), resolver.find_name(index)))
+            .collect();
         Ok(ProtoInfo {
             package: resolver.package,
             imports: resolver.imports,
+            messages,
         })
     }
 }
@@ -54,22 +66,23 @@ enum Token {
 }
 
 
-/// Resolver helper implementation  that parses proto schema files and finds packages and imports.
+/// Resolver helper implementation  that parses proto schema files and finds packages, imports and message indexes.
 ///
 /// Note: that this simplistic approach assumes "clean" protobuf representations that do not have comments
 /// such as `//` or `/* */` in the schema file. Note that confluent schema registry api provides clean proto representations.
 struct ResolverHelper {
     package: Option<String>,
-    // indexes: Vec<Vec<i32>>,
-    // names: Vec<String>,
+    indexes: Vec<Vec<i32>>,
+    names: Vec<String>,
     imports: Vec<String>,
 }
 
 impl ResolverHelper {
     pub fn new(s: &str) -> ResolverHelper {
+        let mut index: Vec<i32> = vec![0];
         let mut package: Option<String> = None;
-        // let mut indexes: Vec<Vec<i32>> = Vec::new();
-        // let mut names: Vec<String> = Vec::new();
+        let mut indexes: Vec<Vec<i32>> = Vec::new();
+        let mut names: Vec<String> = Vec::new();
         let mut imports: Vec<String> = Vec::new();
 
         let mut lex = Token::lexer(s);
@@ -81,10 +94,25 @@ impl ResolverHelper {
                     let slice = lex.slice();
                     package = Some(String::from(slice[8..slice.len() - 1].trim()));
                 }
+                Ok(Token::Message) => {
+                    let slice = lex.slice();
+                    let message = String::from(slice[8..].trim());
+                    while indexes.contains(&index) {
+                        *index.last_mut().expect("Index is never empty") += 1;
+                    }
+                    indexes.push(index.clone());
+                    names.push(message);
+                }
                 Ok(Token::Import) => {
                     let slice = lex.slice();
                     imports.push(String::from(slice[8..slice.len() - 2].trim()));
                 }
+                Ok(Token::Open) => {
+                    index.push(0);
+                }
+                Ok(Token::Close) => {
+                    index.pop();
+                }
                 _ => {}
             }
             next = lex.next();
@@ -92,16 +120,36 @@ impl ResolverHelper {
 
         ResolverHelper {
             package,
-            // indexes,
-            // names,
+            indexes,
+            names,
             imports,
         }
     }
+
+    /// Full name of the message with the given index, nested messages are prefixed with their parents names.
+    fn find_name(&self, index: &[i32]) -> String {
+        let mut name = self.package.clone().unwrap_or_default();
+        for i in 1..=index.len() {
+            if let Some(pos) = self.indexes.iter().position(|idx| idx.as_slice() == &index[..i]) {
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&self.names[pos]);
+            }
+        }
+        name
+    }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn find_name<'a>(info: &'a ProtoInfo, index: &[i32]) -> Option<&'a str> {
+        info.messages().iter()
+            .find(|(i, _)| i.as_slice() == index)
+            .map(|(_, name)| name.as_str())
+    }
+
     const PROTO_SAMPLE: &str = r#"
         syntax = "proto3";
         package model;
@@ -122,5 +170,19 @@ mod tests {
         let info = ProtoResolver::resolve(PROTO_SAMPLE).unwrap();
         assert_eq!(info.package, Some("model".to_string()));
         assert_eq!(info.imports(), &["google/protobuf/timestamp.proto", "shared.proto"]);
+        assert_eq!(find_name(&info, &[0]), Some("model.Task"));
+        assert_eq!(find_name(&info, &[1]), None);
+    }
+
+    #[test]
+    fn resolve_nested_message_indexes() {
+        let raw_schema = r#"syntax = "proto3"; package example; message A { bytes id = 1; } message C { D d = 1; message D { int64 counter = 1; } enum E { X = 0; } message F { int64 counter = 1; } } message G { bytes id = 1; }"#;
+        let info = ProtoResolver::resolve(raw_schema).unwrap();
+        assert_eq!(find_name(&info, &[0]), Some("example.A"));
+        assert_eq!(find_name(&info, &[1]), Some("example.C"));
+        assert_eq!(find_name(&info, &[1, 0]), Some("example.C.D"));
+        assert_eq!(find_name(&info, &[1, 1]), Some("example.C.F"));
+        assert_eq!(find_name(&info, &[2]), Some("example.G"));
+        assert_eq!(info.messages().len(), 5);
     }
 }
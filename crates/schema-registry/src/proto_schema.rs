@@ -14,21 +14,32 @@ use crate::registry::SchemaRegistryError;
 pub struct ProtoSchema {
     pub context: Context,
     pub full_name: String,
-
+    /// Message indexes and full names of the messages defined in the top level (last) raw schema.
+    messages: Vec<(Vec<i32>, String)>,
 }
 
 impl ProtoSchema {
     pub fn try_compile(raw_schemas: &[String]) -> Result<Self, SchemaRegistryError> {
-        // TODO find top level full name from last schema
-        Self::try_compile_with_full_name("".to_string(), raw_schemas)
+        Self::try_compile_with_full_name("", raw_schemas)
+    }
+
+    /// Compiles the raw schemas using the first message of the top level (last) raw schema as the full name.
+    pub fn try_compile_top_level(raw_schemas: &[String]) -> Result<Self, SchemaRegistryError> {
+        let mut schema = Self::try_compile(raw_schemas)?;
+        schema.full_name = schema.message_name(&[0])
+            .ok_or(SchemaRegistryError::CompileMessageNotFound(vec![0]))?
+            .to_string();
+        Ok(schema)
     }
 
     pub fn try_compile_with_full_name<S: AsRef<str>>(full_name: S, raw_schemas: &[String]) -> Result<Self, SchemaRegistryError> {
         let mut schemas = Vec::new();
+        let mut messages = Vec::new();
         for s in raw_schemas {
             let schema_info = ProtoResolver::resolve(s)?;
             add_common_files(schema_info.imports(), &mut schemas);
             schemas.push(s.to_string());
+            messages = schema_info.messages().to_vec();
         }
 
         let unique_schemas: HashSet<String> = schemas.into_iter().collect();
@@ -38,6 +49,7 @@ impl ProtoSchema {
         Ok(Self {
             context,
             full_name: full_name.as_ref().to_string(),
+            messages,
         })
     }
 
@@ -52,19 +64,36 @@ impl ProtoSchema {
         &self.context
     }
 
+    /// Finds the full name of the top level schema message with the given (confluent payload) message index.
+    pub fn message_name(&self, index: &[i32]) -> Option<&str> {
+        self.messages.iter()
+            .find(|(i, _)| i.as_slice() == index)
+            .map(|(_, name)| name.as_str())
+    }
+
     pub fn to_arrow_schema(&self) -> Result<ArrowSchema, SchemaRegistryError> {
-        let info = self.context.get_message(&self.full_name)
-            .ok_or(SchemaRegistryError::ArrowSchemaGenerationError(format!("Proto message definition not found {:?}", self.full_name)))?;
+        self.message_to_arrow_schema(&self.full_name)
+    }
+
+    /// Arrow schema of any message defined in the compiled context.
+    pub fn message_to_arrow_schema(&self, full_name: &str) -> Result<ArrowSchema, SchemaRegistryError> {
+        let info = self.context.get_message(full_name)
+            .ok_or(SchemaRegistryError::ArrowSchemaGenerationError(format!("Proto message definition not found {:?}", full_name)))?;
         let schema = to_arrow_schema(&self.context, info)?;
         Ok(schema)
     }
 
     pub fn decode_to_json(&self, data: &[u8]) -> Result<JsonValue, SchemaRegistryError> {
-        let info = self.context.get_message(&self.full_name)
-            .ok_or(SchemaRegistryError::DecodeJsonError(format!("Proto message definition not found {:?}", self.full_name)))?;
+        self.decode_message_to_json(&self.full_name, data)
+    }
+
+    /// Decodes the data of any message defined in the compiled context to json.
+    pub fn decode_message_to_json(&self, full_name: &str, data: &[u8]) -> Result<JsonValue, SchemaRegistryError> {
+        let info = self.context.get_message(full_name)
+            .ok_or(SchemaRegistryError::DecodeJsonError(format!("Proto message definition not found {:?}", full_name)))?;
 
         let value = self.context.decode(info.self_ref, data);
-        decode_message_to_json(&self.context, &info, value)
+        decode_message_to_json(&self.context, info, value)
     }
 }
 
@@ -265,6 +294,15 @@ pub mod tests {
         assert_eq!(&proto_schema.full_name, "example.Person");
     }
 
+    #[test]
+    fn compile_top_level_schema() {
+        let proto_schema = ProtoSchema::try_compile_top_level(nested_polymorphic_schema().as_slice());
+        let proto_schema = proto_schema.expect("A valid proto3 raw schema");
+        assert_eq!(&proto_schema.full_name, "example.Person");
+        assert_eq!(proto_schema.message_name(&[0]), Some("example.Person"));
+        assert_eq!(proto_schema.message_name(&[1]), None);
+    }
+
     #[test]
     fn compile_nested_polymorphic_schema() {
         let proto_schema = ProtoSchema::try_compile_with_full_name("example.Person", nested_polymorphic_schema().as_slice());
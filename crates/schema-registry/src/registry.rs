@@ -1,13 +1,15 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use dashmap::DashMap;
 use dashmap::mapref::entry::Entry;
 
 use futures_util::future::{BoxFuture, Shared};
 use futures_util::{FutureExt};
 use schema_registry_converter::async_impl::schema_registry::{get_referenced_schema, get_schema_by_id_and_type, get_schema_by_subject, SrSettings};
-use schema_registry_converter::schema_registry_common::{RegisteredSchema, SchemaType};
-use schema_registry_converter::schema_registry_common::SubjectNameStrategy::TopicNameStrategy;
+use schema_registry_converter::schema_registry_common::{RegisteredSchema, SchemaType, SubjectNameStrategy};
+use schema_registry_converter::error::SRCError;
 use crate::proto_schema::ProtoSchema;
+use crate::retry::{retry, RetryOptions};
 
 
 #[derive(Debug, Clone, thiserror::Error)]
@@ -26,6 +28,21 @@ pub enum SchemaRegistryError
     },
 
 
+    #[error("Proto message with index {0:?} not found in schema")]
+    CompileMessageNotFound(
+        Vec<i32>,
+    ),
+
+    #[error("Invalid schema registry payload: {0}")]
+    InvalidPayload(
+        String,
+    ),
+
+    #[error("Invalid schema registry settings: {0}")]
+    InvalidSettings(
+        String,
+    ),
+
     #[error("Arrow schema conversion error: {0}")]
     ArrowSchemaGenerationError(
         String,
@@ -44,53 +61,99 @@ impl From<protofish::context::ParseError> for SchemaRegistryError {
     }
 }
 
+impl SchemaRegistryError {
+    /// Returns true if retrying the failed operation may succeed (e.g. the registry was temporarily unreachable).
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SchemaRegistryError::InternalSchemaRegistryError { source } => source.retriable,
+            _ => false,
+        }
+    }
+}
+
 type SharedFutureSchema = Shared<BoxFuture<'static, Result<Arc<Vec<String>>, SchemaRegistryError>>>;
 
 pub struct SchemaRegistry {
     settings: SrSettings,
+    retry_options: RetryOptions,
     schemas: DashMap<u32, Arc<Vec<String>>>,
     cache: DashMap<u32, SharedFutureSchema>,
-    _proto_schemas: DashMap<u32, Arc<ProtoSchema>>,
+    proto_schemas: DashMap<u32, Arc<ProtoSchema>>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
 }
 
 impl SchemaRegistry {
     pub fn new(settings: SrSettings) -> Self {
         Self {
             settings,
+            retry_options: RetryOptions::default(),
             schemas: DashMap::new(),
             cache: DashMap::new(),
-            _proto_schemas: DashMap::new(),
+            proto_schemas: DashMap::new(),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
         }
     }
 
+    /// Sets the retry options applied to every schema registry call.
+    pub fn with_retry_options(mut self, retry_options: RetryOptions) -> Self {
+        self.retry_options = retry_options;
+        self
+    }
+
+    /// The raw schemas of the latest value schema of the topic (`<topic>-value` subject).
     pub async fn schemas_of_topic(&self, topic: &str) -> Result<Arc<Vec<String>>, SchemaRegistryError> {
-        let subject = TopicNameStrategy(topic.into(), false);
-        let schema = get_schema_by_subject(&self.settings, &subject).await?;
-        return if let Some(s) = self.schemas.get(&schema.id) {
-            Ok(s.value().clone())
-        } else {
-            self.schemas_of(schema.id).await
-        }
+        self.schemas_of_subject(&SubjectNameStrategy::TopicNameStrategy(topic.into(), false)).await
+    }
+
+    /// The raw schemas of the latest schema registered under the subject.
+    pub async fn schemas_of_subject(&self, subject: &SubjectNameStrategy) -> Result<Arc<Vec<String>>, SchemaRegistryError> {
+        let id = self.latest_schema_id(subject).await?;
+        self.schemas_of(id).await
+    }
+
+    /// The compiled latest schema registered under the subject.
+    pub async fn latest_proto_schema_of(&self, subject: &SubjectNameStrategy) -> Result<Arc<ProtoSchema>, SchemaRegistryError> {
+        let id = self.latest_schema_id(subject).await?;
+        self.proto_schema_of(id).await
+    }
+
+    /// The id of the latest schema registered under the subject.
+    pub async fn latest_schema_id(&self, subject: &SubjectNameStrategy) -> Result<u32, SchemaRegistryError> {
+        let schema = retry(&self.retry_options,
+                           || get_schema_by_subject(&self.settings, subject),
+                           is_retryable).await?;
+        Ok(schema.id)
     }
 
     pub async fn schemas_of(&self, id: u32) -> Result<Arc<Vec<String>>, SchemaRegistryError> {
         let schemas = self.schemas.get(&id);
         if let Some(s) = schemas {
-            return Ok(s.value().clone());
+            Ok(s.value().clone())
         } else {
-            let res = self.get_schemas_by_shared_future(id).await;
-            if res.is_ok() && !self.schemas.contains_key(&id) {
-                self.schemas.insert(id, res.clone().unwrap());
-                self.cache.remove(&id);
+            let future = self.get_schemas_by_shared_future(id);
+            let res = future.clone().await;
+            if let Ok(schemas) = &res {
+                self.schemas.entry(id).or_insert_with(|| schemas.clone());
             }
+            // Successful results are served from `schemas` from now on, failed ones are evicted
+            // so that the next request retries (only if no newer future has been cached meanwhile).
+            self.cache.remove_if(&id, |_, cached| cached.ptr_eq(&future));
             res
         }
     }
 
-    pub async fn proto_schema_of(&self, id: u32) -> Result<ProtoSchema, SchemaRegistryError> {
+    /// The compiled proto schema of the given id, its full name is the first message of the top level schema.
+    pub async fn proto_schema_of(&self, id: u32) -> Result<Arc<ProtoSchema>, SchemaRegistryError> {
+        if let Some(s) = self.proto_schemas.get(&id) {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(s.value().clone());
+        }
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
         let schemas = self.schemas_of(id).await?;
-        let compiled = ProtoSchema::try_compile(schemas.as_slice())?;
-        Ok(compiled)
+        let compiled = Arc::new(ProtoSchema::try_compile_top_level(schemas.as_slice())?);
+        Ok(self.proto_schemas.entry(id).or_insert(compiled).value().clone())
     }
 
     fn get_schemas_by_shared_future(&self, id: u32) -> SharedFutureSchema {
@@ -98,12 +161,12 @@ impl SchemaRegistry {
             Entry::Occupied(e) => e.get().clone(),
             Entry::Vacant(e) => {
                 let settings = self.settings.clone();
+                let retry_options = self.retry_options.clone();
                 let future = async move {
-                    let schema = get_schema_by_id_and_type(id, &settings, SchemaType::Protobuf).await;
-                    match schema {
-                        Ok(schema) => get_all_schema_references(&settings, schema).await,
-                        Err(e) => Err(SchemaRegistryError::InternalSchemaRegistryError { source: e }),
-                    }
+                    let schema = retry(&retry_options,
+                                       || get_schema_by_id_and_type(id, &settings, SchemaType::Protobuf),
+                                       is_retryable).await?;
+                    get_all_schema_references(&settings, &retry_options, schema).await
                 }
                     .boxed()
                     .shared();
@@ -112,30 +175,42 @@ impl SchemaRegistry {
         }
     }
 
-    /// Insert raw string schemas to a specific id (used only for testing purposes)
-    #[cfg(test)]
+    /// Number of the compiled schema lookups served from the cache and of those that had to be fetched or compiled.
+    pub fn cache_stats(&self) -> (u64, u64) {
+        (self.cache_hits.load(Ordering::Relaxed), self.cache_misses.load(Ordering::Relaxed))
+    }
+
+    /// Insert raw string schemas to a specific id (used for testing purposes or preloaded schemas)
     pub fn insert_raw_schemas(&self, id: u32, schemas: Vec<String>) -> Result<(), SchemaRegistryError> {
         self.schemas.insert(id, Arc::new(schemas));
         Ok(())
     }
 }
+fn is_retryable(e: &SRCError) -> bool {
+    e.retriable
+}
+
 async fn get_all_schema_references(
    settings: &SrSettings,
+   retry_options: &RetryOptions,
    schema: RegisteredSchema,
 ) -> Result<Arc<Vec<String>>, SchemaRegistryError> {
     let mut res = Vec::new();
-    get_all_schemas_recursive(settings, schema, &mut res).await?;
+    get_all_schemas_recursive(settings, retry_options, schema, &mut res).await?;
     Ok(Arc::new(res))
 }
 fn get_all_schemas_recursive<'a>(
     settings: &'a SrSettings,
+    retry_options: &'a RetryOptions,
     schema: RegisteredSchema,
     res: &'a mut Vec<String>,
 ) -> BoxFuture<'a, Result<(), SchemaRegistryError>> {
     async move {
         for s in schema.references {
-            let schema = get_referenced_schema(settings, &s).await?;
-            get_all_schemas_recursive(settings, schema, res).await?;
+            let schema = retry(retry_options,
+                               || get_referenced_schema(settings, &s),
+                               is_retryable).await?;
+            get_all_schemas_recursive(settings, retry_options, schema, res).await?;
         }
         res.push(schema.schema);
         Ok(())
@@ -192,4 +267,22 @@ mod tests {
         // let arrow_schema = res.to_arrow_schema().unwrap();
         // println!("{:?}", arrow_schema);
     }
+
+    #[tokio::test]
+    pub async fn failed_schema_fetch_is_evicted() {
+        // Nothing listens on this port so every registry call fails
+        let settings = SrSettings::new("http://localhost:1/".to_string());
+        let registry = SchemaRegistry::new(settings).with_retry_options(RetryOptions::no_retries());
+
+        let res = registry.proto_schema_of(81).await;
+        assert!(res.is_err());
+        assert!(registry.cache.is_empty());
+        assert!(!registry.schemas.contains_key(&81));
+
+        // A later request is not served from a cached failure
+        registry.insert_raw_schemas(81, vec![get_proto_sample().to_string()]).unwrap();
+        assert!(registry.proto_schema_of(81).await.is_ok());
+        assert!(registry.proto_schema_of(81).await.is_ok());
+        assert_eq!(registry.cache_stats(), (1, 2));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,131 @@
+use std::future::Future;
+use std::time::Duration;
+
+/// Exponential backoff options for retrying fallible async operations
+/// (schema registry fetches, delta table commits etc).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryOptions {
+    /// Maximum number of retries after the first failed attempt (0 disables retrying).
+    pub max_retries: usize,
+    /// Backoff applied before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the backoff between two attempts.
+    pub max_backoff: Duration,
+    /// Factor the backoff is multiplied with after every failed attempt.
+    pub multiplier: f64,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryOptions {
+    /// Options that never retry.
+    pub fn no_retries() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// The backoff to wait before the given retry `attempt` (starting from 0).
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt.min(i32::MAX as usize) as i32);
+        let backoff = self.initial_backoff.as_secs_f64() * factor;
+        if backoff.is_finite() && backoff < self.max_backoff.as_secs_f64() {
+            Duration::from_secs_f64(backoff)
+        } else {
+            self.max_backoff
+        }
+    }
+}
+
+/// Runs `operation` until it succeeds, the returned error is not retryable according to `is_retryable`
+/// or the retries of `opts` are exhausted. The last error is returned in the latter two cases.
+pub async fn retry<T, E, F, Fut, R>(opts: &RetryOptions, mut operation: F, is_retryable: R) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output=Result<T, E>>,
+        R: Fn(&E) -> bool,
+{
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < opts.max_retries && is_retryable(&e) => {
+                tokio::time::sleep(opts.backoff(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn fast_options(max_retries: usize) -> RetryOptions {
+        RetryOptions {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            multiplier: 2.0,
+        }
+    }
+
+    #[test]
+    fn backoff_is_exponential_and_bounded() {
+        let opts = RetryOptions {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+        };
+        assert_eq!(opts.backoff(0), Duration::from_millis(100));
+        assert_eq!(opts.backoff(1), Duration::from_millis(200));
+        assert_eq!(opts.backoff(2), Duration::from_millis(400));
+        assert_eq!(opts.backoff(4), Duration::from_secs(1));
+        assert_eq!(opts.backoff(usize::MAX), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        let calls = AtomicUsize::new(0);
+        let res: Result<usize, &str> = retry(&fast_options(3), || async {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            if call < 2 { Err("transient") } else { Ok(call) }
+        }, |_| true).await;
+
+        assert_eq!(res, Ok(2));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn stops_on_exhausted_or_non_retryable_errors() {
+        let calls = AtomicUsize::new(0);
+        let res: Result<(), &str> = retry(&fast_options(2), || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err("transient")
+        }, |_| true).await;
+        assert_eq!(res, Err("transient"));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = AtomicUsize::new(0);
+        let res: Result<(), &str> = retry(&fast_options(2), || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err("fatal")
+        }, |e| *e != "fatal").await;
+        assert_eq!(res, Err("fatal"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,182 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use schema_registry_converter::async_impl::schema_registry::SrSettings;
+
+use crate::registry::SchemaRegistryError;
+
+/// Environment variable of the basic authorization username.
+pub const USERNAME_ENV: &str = "SCHEMA_REGISTRY_USERNAME";
+/// Environment variable of the basic authorization password.
+pub const PASSWORD_ENV: &str = "SCHEMA_REGISTRY_PASSWORD";
+/// Environment variable of the bearer token.
+pub const TOKEN_ENV: &str = "SCHEMA_REGISTRY_TOKEN";
+
+/// Schema registry authorization.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum RegistryAuth {
+    #[default]
+    None,
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer {
+        token: String,
+    },
+}
+
+/// Connection settings of a (possibly authenticated and TLS secured) schema registry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistrySettings {
+    /// Registry urls, the next ones are tried in order when a call to the previous one fails.
+    pub urls: Vec<String>,
+    pub auth: RegistryAuth,
+    /// PEM encoded CA certificate trusted in addition to the system root certificates.
+    pub ca_certificate: Option<PathBuf>,
+    /// PEM encoded client certificate and (PKCS #8) private key for mutual TLS.
+    pub client_identity: Option<(PathBuf, PathBuf)>,
+    /// Timeout of every registry request.
+    pub timeout: Option<Duration>,
+}
+
+impl RegistrySettings {
+    pub fn new<U: Into<String>>(url: U) -> Self {
+        Self {
+            urls: vec![url.into()],
+            auth: RegistryAuth::None,
+            ca_certificate: None,
+            client_identity: None,
+            timeout: None,
+        }
+    }
+
+    /// Adds a failover registry url.
+    pub fn with_url<U: Into<String>>(mut self, url: U) -> Self {
+        self.urls.push(url.into());
+        self
+    }
+
+    pub fn with_auth(mut self, auth: RegistryAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    pub fn with_ca_certificate<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.ca_certificate = Some(path.into());
+        self
+    }
+
+    pub fn with_client_identity<C: Into<PathBuf>, K: Into<PathBuf>>(mut self, certificate: C, key: K) -> Self {
+        self.client_identity = Some((certificate.into(), key.into()));
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Reads the credentials from the `SCHEMA_REGISTRY_TOKEN` or the `SCHEMA_REGISTRY_USERNAME` and
+    /// `SCHEMA_REGISTRY_PASSWORD` environment variables, unless the authorization is already configured.
+    pub fn with_env_credentials(self) -> Self {
+        self.with_credentials_from(|key| std::env::var(key).ok())
+    }
+
+    fn with_credentials_from<F: Fn(&str) -> Option<String>>(mut self, var: F) -> Self {
+        if self.auth != RegistryAuth::None {
+            return self;
+        }
+        if let Some(token) = var(TOKEN_ENV) {
+            self.auth = RegistryAuth::Bearer { token };
+        } else if let Some(username) = var(USERNAME_ENV) {
+            self.auth = RegistryAuth::Basic { username, password: var(PASSWORD_ENV) };
+        }
+        self
+    }
+
+    /// Builds the settings of the underlying registry client, reading the configured certificates.
+    pub fn build(&self) -> Result<SrSettings, SchemaRegistryError> {
+        let (first, failover) = self.urls.split_first()
+            .ok_or_else(|| SchemaRegistryError::InvalidSettings("At least one registry url is required".to_string()))?;
+
+        let mut builder = SrSettings::new_builder(first.clone());
+        for url in failover {
+            builder.add_url(url.clone());
+        }
+        match &self.auth {
+            RegistryAuth::None => {}
+            RegistryAuth::Basic { username, password } => {
+                builder.set_basic_authorization(username, password.as_deref());
+            }
+            RegistryAuth::Bearer { token } => {
+                builder.set_token_authorization(token);
+            }
+        }
+        if let Some(timeout) = self.timeout {
+            builder.set_timeout(timeout);
+        }
+
+        let mut client = reqwest::Client::builder();
+        if let Some(path) = &self.ca_certificate {
+            let certificate = reqwest::Certificate::from_pem(&read_file(path)?)
+                .map_err(|e| SchemaRegistryError::InvalidSettings(format!("Invalid CA certificate {:?}: {}", path, e)))?;
+            client = client.add_root_certificate(certificate);
+        }
+        if let Some((certificate, key)) = &self.client_identity {
+            let identity = reqwest::Identity::from_pkcs8_pem(&read_file(certificate)?, &read_file(key)?)
+                .map_err(|e| SchemaRegistryError::InvalidSettings(format!("Invalid client certificate {:?}: {}", certificate, e)))?;
+            client = client.identity(identity);
+        }
+        Ok(builder.build_with(client)?)
+    }
+}
+
+fn read_file(path: &PathBuf) -> Result<Vec<u8>, SchemaRegistryError> {
+    std::fs::read(path)
+        .map_err(|e| SchemaRegistryError::InvalidSettings(format!("Failed to read {:?}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn build_authenticated_failover_settings() {
+        let settings = RegistrySettings::new("http://registry-1:8081")
+            .with_url("http://registry-2:8081")
+            .with_auth(RegistryAuth::Basic { username: "user".to_string(), password: Some("secret".to_string()) })
+            .with_timeout(Duration::from_secs(5));
+        assert!(settings.build().is_ok());
+
+        let settings = RegistrySettings::new("http://registry-1:8081")
+            .with_auth(RegistryAuth::Bearer { token: "token".to_string() });
+        assert!(settings.build().is_ok());
+    }
+
+    #[test]
+    fn reject_invalid_settings() {
+        let mut settings = RegistrySettings::new("http://registry:8081");
+        settings.urls.clear();
+        assert!(matches!(settings.build(), Err(SchemaRegistryError::InvalidSettings(_))));
+
+        let settings = RegistrySettings::new("http://registry:8081").with_ca_certificate("./missing-ca.pem");
+        assert!(matches!(settings.build(), Err(SchemaRegistryError::InvalidSettings(_))));
+    }
+
+    #[test]
+    fn credentials_from_environment() {
+        let env = HashMap::from([(USERNAME_ENV, "user"), (PASSWORD_ENV, "secret")]);
+        let var = |key: &str| env.get(key).map(|v| v.to_string());
+
+        let settings = RegistrySettings::new("http://registry:8081").with_credentials_from(var);
+        assert_eq!(settings.auth, RegistryAuth::Basic { username: "user".to_string(), password: Some("secret".to_string()) });
+
+        // Configured credentials take precedence
+        let auth = RegistryAuth::Bearer { token: "token".to_string() };
+        let settings = RegistrySettings::new("http://registry:8081").with_auth(auth.clone()).with_credentials_from(var);
+        assert_eq!(settings.auth, auth);
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::str::FromStr;
+
+use schema_registry_converter::schema_registry_common::SubjectNameStrategy;
+
+/// Naming strategy of the schema registry subjects the message (or key) schemas of a topic are registered under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SubjectStrategy {
+    /// `<topic>-value` or `<topic>-key` subjects (the confluent default).
+    #[default]
+    TopicName,
+    /// The full name of the message (e.g. `example.Person`), allows multiple message types per topic.
+    RecordName,
+    /// `<topic>-<message full name>` subjects, allows multiple message types per topic.
+    TopicRecordName,
+}
+
+impl SubjectStrategy {
+    /// The subject of the (key if `is_key`) schema of the given topic and message full name.
+    /// Note that as in confluent serializers, the record name strategies do not distinguish key schemas.
+    pub fn subject(&self, topic: &str, record_name: &str, is_key: bool) -> SubjectNameStrategy {
+        match self {
+            SubjectStrategy::TopicName => SubjectNameStrategy::TopicNameStrategy(topic.to_string(), is_key),
+            SubjectStrategy::RecordName => SubjectNameStrategy::RecordNameStrategy(record_name.to_string()),
+            SubjectStrategy::TopicRecordName => {
+                SubjectNameStrategy::TopicRecordNameStrategy(topic.to_string(), record_name.to_string())
+            }
+        }
+    }
+}
+
+impl FromStr for SubjectStrategy {
+    type Err = String;
+
+    /// Parses `topic`, `record` and `topic-record` (or the confluent class names e.g. `TopicNameStrategy`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "topic" | "TopicNameStrategy" => Ok(SubjectStrategy::TopicName),
+            "record" | "RecordNameStrategy" => Ok(SubjectStrategy::RecordName),
+            "topic-record" | "TopicRecordNameStrategy" => Ok(SubjectStrategy::TopicRecordName),
+            _ => Err(format!("Unknown subject name strategy {}, expected topic, record or topic-record", s)),
+        }
+    }
+}
+
+impl fmt::Display for SubjectStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SubjectStrategy::TopicName => "topic",
+            SubjectStrategy::RecordName => "record",
+            SubjectStrategy::TopicRecordName => "topic-record",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subject_names() {
+        let subject = |strategy: SubjectStrategy, is_key| {
+            strategy.subject("proto.ds.person", "example.Person", is_key).get_subject().unwrap()
+        };
+        assert_eq!(subject(SubjectStrategy::TopicName, false), "proto.ds.person-value");
+        assert_eq!(subject(SubjectStrategy::TopicName, true), "proto.ds.person-key");
+        assert_eq!(subject(SubjectStrategy::RecordName, false), "example.Person");
+        assert_eq!(subject(SubjectStrategy::TopicRecordName, true), "proto.ds.person-example.Person");
+    }
+
+    #[test]
+    fn parse_strategies() {
+        for strategy in [SubjectStrategy::TopicName, SubjectStrategy::RecordName, SubjectStrategy::TopicRecordName] {
+            assert_eq!(strategy.to_string().parse::<SubjectStrategy>(), Ok(strategy));
+        }
+        assert_eq!("RecordNameStrategy".parse::<SubjectStrategy>(), Ok(SubjectStrategy::RecordName));
+        assert!("record-topic".parse::<SubjectStrategy>().is_err());
+    }
+}
@@ -40,7 +40,7 @@ async fn main() -> anyhow::Result<()> {
         create_random_person_proto_value(&schema)
     }).collect::<Vec<_>>();
 
-    let time = std::time::SystemTime::now();
+    let _time = std::time::SystemTime::now();
     let persons = persons.iter().map(|x| {
         schema.decode_to_json(x).unwrap()
     }).collect::<Vec<_>>();
@@ -131,13 +131,13 @@ fn create_random_person_proto_value(schema: &ProtoSchema) -> Vec<u8> {
     let TypeInfo::Message(_timestamp) = schema.context.get_type("google.protobuf.Timestamp").unwrap()
         else { panic!("Expected message Timestamp type info") };
     let physical_value = MessageValue {
-        msg_ref: msg_physical.self_ref.clone(),
+        msg_ref: msg_physical.self_ref,
         garbage: None,
         fields: vec![
             FieldValue {
                 number: 1,
                 value: Value::Enum(EnumValue {
-                    enum_ref: details_type.self_ref.clone(),
+                    enum_ref: details_type.self_ref,
                     value: 1,
                 }),
             },
@@ -175,7 +175,7 @@ fn create_random_person_proto_value(schema: &ProtoSchema) -> Vec<u8> {
     let TypeInfo::Enum(status) = schema.context.get_type("example.Status.Enum").unwrap() else { panic!("Expected enum Status type info") };
 
     let proto_value = MessageValue {
-        msg_ref: msg.self_ref.clone(),
+        msg_ref: msg.self_ref,
         garbage: None,
         fields: vec![
             FieldValue {
@@ -189,14 +189,14 @@ fn create_random_person_proto_value(schema: &ProtoSchema) -> Vec<u8> {
             FieldValue {
                 number: 3,
                 value: Value::Enum(EnumValue {
-                    enum_ref: status.self_ref.clone(),
+                    enum_ref: status.self_ref,
                     value: 1,
                 }),
             },
             FieldValue {
                 number: 4,
                 value: Value::Message(Box::new(MessageValue {
-                    msg_ref: schema.context.get_message("example.Contact").unwrap().self_ref.clone(),
+                    msg_ref: schema.context.get_message("example.Contact").unwrap().self_ref,
                     garbage: None,
                     fields: vec![
                         FieldValue {
@@ -218,7 +218,7 @@ fn create_random_person_proto_value(schema: &ProtoSchema) -> Vec<u8> {
             FieldValue {
                 number: 5,
                 value: Value::Message(Box::new(MessageValue {
-                    msg_ref: msg_detail.self_ref.clone(),
+                    msg_ref: msg_detail.self_ref,
                     garbage: None,
                     fields: vec![
                         FieldValue {
@@ -230,5 +230,5 @@ fn create_random_person_proto_value(schema: &ProtoSchema) -> Vec<u8> {
             },
         ],
     };
-    proto_value.encode(&schema.context()).to_vec()
+    proto_value.encode(schema.context()).to_vec()
 }